@@ -0,0 +1,260 @@
+use std::{
+    fmt::{Display, Formatter},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use libp2p::{multiaddr::Protocol, Multiaddr};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+const MAX_DNS_NAME_LEN: usize = 253;
+
+/// Where consumers reach a supplier to retrieve a file. Serialized as its string form, e.g.
+/// `10.0.0.1`, `2001:db8::1`, `files.example.com` or `/dns4/files.example.com/tcp/8080`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum SupplierAddr {
+    Ip(IpAddr),
+    Dns(String),
+    Multiaddr(Multiaddr),
+}
+
+impl SupplierAddr {
+    /// Validates `name` as a DNS hostname.
+    pub fn dns(name: impl Into<String>) -> Result<Self, SupplierAddrError> {
+        let name = name.into();
+        let is_valid_label = |label: &str| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        };
+        let trimmed = name.strip_suffix('.').unwrap_or(&name);
+        if trimmed.is_empty()
+            || trimmed.len() > MAX_DNS_NAME_LEN
+            || !trimmed.split('.').all(is_valid_label)
+        {
+            return Err(SupplierAddrError::InvalidDnsName { name });
+        }
+        Ok(Self::Dns(name))
+    }
+
+    /// The IP address of the supplier, if it is known without a DNS lookup.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Self::Ip(ip) => Some(*ip),
+            Self::Multiaddr(addr) => multiaddr_ip(addr),
+            Self::Dns(_) => None,
+        }
+    }
+
+    /// The IPv4 address that peers which only understand IPv4 supplier addresses are given.
+    pub(crate) fn legacy_ipv4(&self) -> Option<Ipv4Addr> {
+        match self {
+            Self::Ip(IpAddr::V4(ip)) => Some(*ip),
+            Self::Multiaddr(addr) => addr.iter().find_map(|protocol| match protocol {
+                Protocol::Ip4(ip) => Some(ip),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// How far an IP address can be reached from, ordered from least to most reachable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum IpScope {
+    Loopback,
+    Private,
+    Public,
+}
+
+impl IpScope {
+    pub(crate) const fn of(ip: &IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ip) => {
+                let [a, b, ..] = ip.octets();
+                if ip.is_loopback() || ip.is_unspecified() {
+                    Self::Loopback
+                } else if ip.is_private()
+                    || ip.is_link_local()
+                    || ip.is_broadcast()
+                    || ip.is_documentation()
+                    // shared address space (RFC 6598) used by carrier-grade NAT
+                    || (a == 100 && b & 0xc0 == 64)
+                {
+                    Self::Private
+                } else {
+                    Self::Public
+                }
+            }
+            IpAddr::V6(ip) => {
+                let first = ip.segments()[0];
+                if ip.is_loopback() || ip.is_unspecified() {
+                    Self::Loopback
+                } else if first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80 {
+                    // unique local (fc00::/7) and link local (fe80::/10)
+                    Self::Private
+                } else {
+                    Self::Public
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn multiaddr_ip(addr: &Multiaddr) -> Option<IpAddr> {
+    addr.iter().find_map(|protocol| match protocol {
+        Protocol::Ip4(ip) => Some(ip.into()),
+        Protocol::Ip6(ip) => Some(ip.into()),
+        _ => None,
+    })
+}
+
+impl FromStr for SupplierAddr {
+    type Err = SupplierAddrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            Ok(Self::Ip(ip))
+        } else if s.starts_with('/') {
+            s.parse::<Multiaddr>().map(Self::Multiaddr).map_err(|err| {
+                SupplierAddrError::InvalidMultiaddr {
+                    reason: err.to_string(),
+                }
+            })
+        } else {
+            Self::dns(s)
+        }
+    }
+}
+
+impl Display for SupplierAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ip(ip) => write!(f, "{ip}"),
+            Self::Dns(name) => write!(f, "{name}"),
+            Self::Multiaddr(addr) => write!(f, "{addr}"),
+        }
+    }
+}
+
+impl From<SupplierAddr> for String {
+    fn from(value: SupplierAddr) -> Self {
+        value.to_string()
+    }
+}
+
+impl TryFrom<String> for SupplierAddr {
+    type Error = SupplierAddrError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<IpAddr> for SupplierAddr {
+    fn from(value: IpAddr) -> Self {
+        Self::Ip(value)
+    }
+}
+
+impl From<Ipv4Addr> for SupplierAddr {
+    fn from(value: Ipv4Addr) -> Self {
+        Self::Ip(value.into())
+    }
+}
+
+impl From<Ipv6Addr> for SupplierAddr {
+    fn from(value: Ipv6Addr) -> Self {
+        Self::Ip(value.into())
+    }
+}
+
+impl From<[u8; 4]> for SupplierAddr {
+    fn from(value: [u8; 4]) -> Self {
+        Self::Ip(value.into())
+    }
+}
+
+impl From<Multiaddr> for SupplierAddr {
+    fn from(value: Multiaddr) -> Self {
+        Self::Multiaddr(value)
+    }
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum SupplierAddrError {
+    #[error("Invalid DNS name: {name}")]
+    InvalidDnsName { name: String },
+    #[error("Invalid multiaddr: {reason}")]
+    InvalidMultiaddr { reason: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use pretty_assertions::assert_eq;
+
+    use super::{IpScope, SupplierAddr};
+
+    #[test]
+    fn test_parse_and_display() {
+        for addr in [
+            "10.0.0.1",
+            "2001:db8::1",
+            "files.example.com",
+            "/dns4/files.example.com/tcp/8080",
+        ] {
+            assert_eq!(addr.parse::<SupplierAddr>().unwrap().to_string(), addr);
+        }
+        assert_eq!(
+            "2001:db8::1".parse::<SupplierAddr>().unwrap(),
+            SupplierAddr::from("2001:db8::1".parse::<Ipv6Addr>().unwrap())
+        );
+        assert!("not a host".parse::<SupplierAddr>().is_err());
+        assert!("-bad.example.com".parse::<SupplierAddr>().is_err());
+        assert!("/not/a/multiaddr".parse::<SupplierAddr>().is_err());
+    }
+
+    #[test]
+    fn test_ip_scope() {
+        for (ip, scope) in [
+            ("127.0.0.1", IpScope::Loopback),
+            ("::1", IpScope::Loopback),
+            ("192.168.1.10", IpScope::Private),
+            ("10.1.2.3", IpScope::Private),
+            ("100.64.0.1", IpScope::Private),
+            ("fd00::1", IpScope::Private),
+            ("fe80::1", IpScope::Private),
+            ("8.8.8.8", IpScope::Public),
+            ("2606:4700::1111", IpScope::Public),
+        ] {
+            assert_eq!(IpScope::of(&ip.parse().unwrap()), scope, "{ip}");
+        }
+    }
+
+    #[test]
+    fn test_legacy_ipv4() {
+        assert_eq!(
+            SupplierAddr::from([10, 0, 0, 1]).legacy_ipv4(),
+            Some(Ipv4Addr::new(10, 0, 0, 1))
+        );
+        assert_eq!(
+            "/ip4/10.0.0.2/tcp/80"
+                .parse::<SupplierAddr>()
+                .unwrap()
+                .legacy_ipv4(),
+            Some(Ipv4Addr::new(10, 0, 0, 2))
+        );
+        assert_eq!(
+            SupplierAddr::dns("example.com").unwrap().legacy_ipv4(),
+            None
+        );
+    }
+}
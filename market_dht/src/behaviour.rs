@@ -1,14 +1,66 @@
+use std::{collections::HashMap, hash::Hash};
+
 use libp2p::{
-    identify::Behaviour as IdentifyBehaviour, kad::Behaviour as KadBehaviour,
-    swarm::NetworkBehaviour,
+    allow_block_list::{self, AllowedPeers, BlockedPeers},
+    identify::Behaviour as IdentifyBehaviour,
+    kad::Behaviour as KadBehaviour,
+    swarm::{behaviour::toggle::Toggle, InvalidProtocol, NetworkBehaviour},
+    StreamProtocol,
 };
+use tracing::span;
+
+use crate::req_res::RequestHandler;
 
 use self::{
-    file_req_res::FileReqResBehaviour,
-    ident::Identify,
-    kademlia::{Kad, KadStore},
+    announce::{Announce, GOSSIPSUB_PROTOCOL_PREFIX},
+    file_req_res::{FileReqResBehaviour, FILE_REQ_RES_PROTOCOL_NAME},
+    file_transfer::{FileTransferBehaviour, FILE_TRANSFER_PROTOCOL_NAME},
+    ident::{Identify, IDENTIFY_PROTOCOL_NAME},
+    kademlia::{Kad, KadStore, KAD_PROTOCOL_NAME},
 };
 
+/// The names of the protocols that the market speaks. Nodes only talk to nodes that use the same
+/// names, so giving a network its own prefix keeps it apart from every other network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ProtocolNames {
+    pub(crate) kad: StreamProtocol,
+    pub(crate) identify: String,
+    pub(crate) file_req_res: StreamProtocol,
+    pub(crate) file_transfer: StreamProtocol,
+    // gossipsub appends the version itself
+    pub(crate) gossipsub: String,
+}
+
+impl ProtocolNames {
+    /// Without a prefix the names of the public network are used.
+    pub(crate) fn new(prefix: Option<&str>) -> Result<Self, InvalidProtocol> {
+        let Some(prefix) = prefix else {
+            return Ok(Self::default());
+        };
+        let prefix = prefix.trim_end_matches('/');
+        let name = |name: &str| StreamProtocol::try_from_owned(format!("{prefix}/{name}/1.0.0"));
+        Ok(Self {
+            kad: name("kad")?,
+            identify: name("id")?.to_string(),
+            file_req_res: name("file_req_res")?,
+            file_transfer: name("file_transfer")?,
+            gossipsub: format!("{prefix}/meshsub"),
+        })
+    }
+}
+
+impl Default for ProtocolNames {
+    fn default() -> Self {
+        Self {
+            kad: KAD_PROTOCOL_NAME,
+            identify: IDENTIFY_PROTOCOL_NAME.to_owned(),
+            file_req_res: FILE_REQ_RES_PROTOCOL_NAME,
+            file_transfer: FILE_TRANSFER_PROTOCOL_NAME,
+            gossipsub: GOSSIPSUB_PROTOCOL_PREFIX.to_owned(),
+        }
+    }
+}
+
 #[derive(NetworkBehaviour)]
 #[allow(missing_debug_implementations)]
 #[non_exhaustive] // NOTE: maybe more protocols?
@@ -16,19 +68,31 @@ pub(crate) struct MarketBehaviour<TKadStore: KadStore> {
     kademlia: Kad<TKadStore>,
     identify: Identify,
     file_req_res: FileReqResBehaviour,
+    file_transfer: Toggle<FileTransferBehaviour>,
+    announce: Announce,
+    blocked_peers: allow_block_list::Behaviour<BlockedPeers>,
+    allowed_peers: Toggle<allow_block_list::Behaviour<AllowedPeers>>,
 }
 
 impl<TKadStore: KadStore> MarketBehaviour<TKadStore> {
     #[inline(always)]
-    pub(crate) const fn new(
+    pub(crate) fn new(
         kademlia: KadBehaviour<TKadStore>,
         identify: IdentifyBehaviour,
         file_req_res: FileReqResBehaviour,
+        file_transfer: Option<FileTransferBehaviour>,
+        announce: Announce,
+        blocked_peers: allow_block_list::Behaviour<BlockedPeers>,
+        allowed_peers: Option<allow_block_list::Behaviour<AllowedPeers>>,
     ) -> Self {
         Self {
             kademlia: Kad::new(kademlia),
             identify: Identify::new(identify),
             file_req_res,
+            file_transfer: Toggle::from(file_transfer),
+            announce,
+            blocked_peers,
+            allowed_peers: Toggle::from(allowed_peers),
         }
     }
 
@@ -37,7 +101,7 @@ impl<TKadStore: KadStore> MarketBehaviour<TKadStore> {
         &self.kademlia
     }
 
-    pub(crate) fn kademlia_mut(&mut self) -> &mut Kad<TKadStore> {
+    pub(crate) const fn kademlia_mut(&mut self) -> &mut Kad<TKadStore> {
         &mut self.kademlia
     }
 
@@ -47,7 +111,7 @@ impl<TKadStore: KadStore> MarketBehaviour<TKadStore> {
     }
 
     #[allow(dead_code)]
-    pub(crate) fn identify_mut(&mut self) -> &mut Identify {
+    pub(crate) const fn identify_mut(&mut self) -> &mut Identify {
         &mut self.identify
     }
 
@@ -56,9 +120,32 @@ impl<TKadStore: KadStore> MarketBehaviour<TKadStore> {
         &self.file_req_res
     }
 
-    pub(crate) fn file_req_res_mut(&mut self) -> &mut FileReqResBehaviour {
+    pub(crate) const fn file_req_res_mut(&mut self) -> &mut FileReqResBehaviour {
         &mut self.file_req_res
     }
+
+    pub(crate) fn file_transfer_mut(&mut self) -> Option<&mut FileTransferBehaviour> {
+        self.file_transfer.as_mut()
+    }
+
+    pub(crate) const fn announce_mut(&mut self) -> &mut Announce {
+        &mut self.announce
+    }
+
+    pub(crate) const fn blocked_peers_mut(
+        &mut self,
+    ) -> &mut allow_block_list::Behaviour<BlockedPeers> {
+        &mut self.blocked_peers
+    }
+}
+
+/// The span that an event of the outbound query or request `id` is handled in, a child of the span
+/// of the request that started it. Queries that the node starts on its own get a root span.
+pub(crate) fn parent_span<K: Eq + Hash>(
+    pending: &HashMap<K, RequestHandler>,
+    id: &K,
+) -> Option<span::Id> {
+    pending.get(id).and_then(|handler| handler.span().id())
 }
 
 mod macros {
@@ -76,6 +163,32 @@ mod macros {
 }
 use macros::send_response;
 
+pub(crate) mod announce;
 pub(crate) mod file_req_res;
+pub(crate) mod file_transfer;
 pub(crate) mod ident;
 pub(crate) mod kademlia;
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::ProtocolNames;
+
+    #[test]
+    fn test_protocol_prefix() {
+        assert_eq!(ProtocolNames::new(None).unwrap(), ProtocolNames::default());
+        // the public network keeps the name that deployed nodes speak
+        assert_eq!(
+            ProtocolNames::default().file_req_res.as_ref(),
+            "/file_req_res/1.0.0"
+        );
+        let names = ProtocolNames::new(Some("/testnet/")).unwrap();
+        assert_eq!(names.kad.as_ref(), "/testnet/kad/1.0.0");
+        assert_eq!(names.identify, "/testnet/id/1.0.0");
+        assert_eq!(names.file_req_res.as_ref(), "/testnet/file_req_res/1.0.0");
+        assert_eq!(names.file_transfer.as_ref(), "/testnet/file_transfer/1.0.0");
+        assert_eq!(names.gossipsub, "/testnet/meshsub");
+        assert!(ProtocolNames::new(Some("testnet")).is_err());
+    }
+}
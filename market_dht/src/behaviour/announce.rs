@@ -0,0 +1,280 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use libp2p::{
+    gossipsub::{self, IdentTopic, MessageAuthenticity, MessageId, PublishError, ValidationMode},
+    identity::Keypair,
+    swarm::NetworkBehaviour,
+    PeerId,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{info, warn};
+
+use crate::{behaviour::file_req_res::SupplierInfo, file_hash::FileHash};
+
+pub(crate) const GOSSIPSUB_PROTOCOL_PREFIX: &str = "/orcanet/meshsub";
+// NOTE: subscribers that fall further behind than this miss announcements
+pub(crate) const ANNOUNCEMENT_BUFFER: usize = 256;
+
+/// The kinds of market events that are announced, each on its own gossipsub topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum MarketTopic {
+    NewListings,
+    PriceChanges,
+    WithdrawnListings,
+}
+
+impl MarketTopic {
+    pub const ALL: [Self; 3] = [
+        Self::NewListings,
+        Self::PriceChanges,
+        Self::WithdrawnListings,
+    ];
+
+    /// The name of the gossipsub topic.
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::NewListings => "orcanet/market/new-listings",
+            Self::PriceChanges => "orcanet/market/price-changes",
+            Self::WithdrawnListings => "orcanet/market/withdrawn-listings",
+        }
+    }
+
+    fn topic(&self) -> IdentTopic {
+        IdentTopic::new(self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Announcement {
+    /// A supplier registered a file.
+    NewListing {
+        file_hash: FileHash,
+        supplier_info: SupplierInfo,
+    },
+    PriceChanged {
+        file_hash: FileHash,
+        old_price: i64,
+        supplier_info: SupplierInfo,
+    },
+    /// A supplier stopped supplying a file.
+    Withdrawn { file_hash: FileHash },
+}
+
+impl Announcement {
+    pub const fn topic(&self) -> MarketTopic {
+        match self {
+            Self::NewListing { .. } => MarketTopic::NewListings,
+            Self::PriceChanged { .. } => MarketTopic::PriceChanges,
+            Self::Withdrawn { .. } => MarketTopic::WithdrawnListings,
+        }
+    }
+
+    pub const fn file_hash(&self) -> &FileHash {
+        match self {
+            Self::NewListing { file_hash, .. }
+            | Self::PriceChanged { file_hash, .. }
+            | Self::Withdrawn { file_hash } => file_hash,
+        }
+    }
+}
+
+/// An [`Announcement`] along with who published it and when.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarketAnnouncement {
+    /// Taken from the signature of the message, so peers that relay it cannot forge it.
+    pub supplier: PeerId,
+    /// As claimed by the supplier.
+    pub published_at: SystemTime,
+    pub announcement: Announcement,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WireAnnouncement {
+    announcement: Announcement,
+    published_at_ms: u64,
+}
+
+/// Receives the announcements of the topics that it was created for, see
+/// [`crate::peer::Peer::subscribe`].
+#[derive(Debug)]
+pub struct MarketSubscription {
+    topics: HashSet<MarketTopic>,
+    receiver: broadcast::Receiver<MarketAnnouncement>,
+}
+
+impl MarketSubscription {
+    pub(crate) fn new(
+        topics: impl IntoIterator<Item = MarketTopic>,
+        receiver: broadcast::Receiver<MarketAnnouncement>,
+    ) -> Self {
+        Self {
+            topics: topics.into_iter().collect(),
+            receiver,
+        }
+    }
+
+    /// Waits for the next announcement. Announcements that came in while the subscription fell
+    /// too far behind are skipped. Returns `None` once the node has shut down.
+    pub async fn recv(&mut self) -> Option<MarketAnnouncement> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(announcement) if self.topics.contains(&announcement.announcement.topic()) => {
+                    return Some(announcement)
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Subscription fell behind and missed {missed} market announcements")
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct AnnounceHandler {
+    local_peer_id: PeerId,
+    sender: broadcast::Sender<MarketAnnouncement>,
+    topics: HashMap<gossipsub::TopicHash, MarketTopic>,
+}
+
+impl AnnounceHandler {
+    pub(crate) fn new(
+        local_peer_id: PeerId,
+        sender: broadcast::Sender<MarketAnnouncement>,
+    ) -> Self {
+        Self {
+            local_peer_id,
+            sender,
+            topics: MarketTopic::ALL
+                .into_iter()
+                .map(|topic| (topic.topic().hash(), topic))
+                .collect(),
+        }
+    }
+
+    /// Every node subscribes to every topic so that announcements reach all of the network, no
+    /// matter which of them the local subscribers care about.
+    pub(crate) fn subscribe(
+        &self,
+        Announce { gossipsub }: &mut Announce,
+    ) -> Result<(), gossipsub::SubscriptionError> {
+        for topic in MarketTopic::ALL {
+            gossipsub.subscribe(&topic.topic())?;
+        }
+        Ok(())
+    }
+
+    /// Publishes `announcement` to the network and hands it to the local subscribers.
+    pub(crate) fn announce(
+        &self,
+        Announce { gossipsub }: &mut Announce,
+        announcement: Announcement,
+    ) {
+        let published_at = SystemTime::now();
+        let wire = WireAnnouncement {
+            announcement,
+            published_at_ms: published_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+        };
+        let data = serde_json::to_vec(&wire).expect("announcements to serialize");
+        let topic = wire.announcement.topic();
+        match gossipsub.publish(topic.topic(), data) {
+            Ok(message_id) => info!("Announced {:?} as {message_id}", wire.announcement),
+            Err(PublishError::InsufficientPeers) => {
+                info!("No peers to announce {:?} to", wire.announcement)
+            }
+            Err(err) => warn!("Failed to announce {:?}: {err}", wire.announcement),
+        }
+        // NOTE: fails only if nobody is subscribed, which is fine
+        let _ = self.sender.send(MarketAnnouncement {
+            supplier: self.local_peer_id,
+            published_at,
+            announcement: wire.announcement,
+        });
+    }
+
+    pub(crate) fn handle_event(
+        &mut self,
+        AnnounceEvent::Gossipsub(event): AnnounceEvent,
+        blocked_peers: &HashSet<PeerId>,
+    ) {
+        match event {
+            gossipsub::Event::Message {
+                propagation_source,
+                message_id,
+                message,
+            } => {
+                let Some(supplier) = message.source else {
+                    warn!("Dropping unsigned announcement {message_id} from {propagation_source}");
+                    return;
+                };
+                if blocked_peers.contains(&supplier) {
+                    return;
+                }
+                let wire = match serde_json::from_slice::<WireAnnouncement>(&message.data) {
+                    Ok(wire) => wire,
+                    Err(err) => {
+                        warn!("Dropping invalid announcement {message_id} from {supplier}: {err}");
+                        return;
+                    }
+                };
+                if self.topics.get(&message.topic) != Some(&wire.announcement.topic()) {
+                    warn!("Dropping announcement {message_id} from {supplier} that was published to the wrong topic");
+                    return;
+                }
+                let _ = self.sender.send(MarketAnnouncement {
+                    supplier,
+                    published_at: UNIX_EPOCH + Duration::from_millis(wire.published_at_ms),
+                    announcement: wire.announcement,
+                });
+            }
+            gossipsub::Event::Subscribed { peer_id, topic } => {
+                info!("Peer {peer_id} subscribed to {topic}")
+            }
+            gossipsub::Event::Unsubscribed { peer_id, topic } => {
+                info!("Peer {peer_id} unsubscribed from {topic}")
+            }
+            gossipsub::Event::GossipsubNotSupported { peer_id } => {
+                info!("Peer {peer_id} does not support market announcements")
+            }
+        }
+    }
+}
+
+#[derive(NetworkBehaviour)]
+pub(crate) struct Announce {
+    gossipsub: gossipsub::Behaviour,
+}
+
+impl Announce {
+    /// Messages are signed by their publisher and identified by their content, so the same
+    /// announcement is only delivered once no matter how many peers relay it.
+    pub(crate) fn new(keypair: &Keypair, protocol_prefix: String) -> Result<Self, String> {
+        let config = gossipsub::ConfigBuilder::default()
+            .protocol_id_prefix(protocol_prefix)
+            .validation_mode(ValidationMode::Strict)
+            .message_id_fn(|message| {
+                let mut hasher = Sha256::new();
+                if let Some(source) = message.source {
+                    hasher.update(source.to_bytes());
+                }
+                hasher.update(&message.data);
+                MessageId::from(hasher.finalize().to_vec())
+            })
+            .build()
+            .map_err(|err| err.to_string())?;
+        let gossipsub =
+            gossipsub::Behaviour::new(MessageAuthenticity::Signed(keypair.clone()), config)?;
+        Ok(Self { gossipsub })
+    }
+}
@@ -1,28 +1,61 @@
-use std::{collections::HashMap, net::Ipv4Addr};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr},
+    time::Instant,
+};
 
 use libp2p::{
-    request_response::{self, cbor, Config, OutboundRequestId, ProtocolSupport},
+    request_response::{self, cbor, Config, InboundRequestId, OutboundRequestId, ProtocolSupport},
     swarm::NetworkBehaviour,
-    StreamProtocol,
+    PeerId, StreamProtocol,
 };
-use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
+use tracing::{error, info, info_span, warn};
 
 use crate::{
+    address::SupplierAddr,
+    config::InboundLimits,
     coordinator::LocalMarketMap,
+    file_hash::FileHash,
+    rate_limit::{InboundLimiter, InboundMetrics, InboundRejection},
+    reputation::ReputationTracker,
     req_res::{FileReqResRequestData, FileReqResResponseData, RequestHandler, ResponseData},
+    stats::RequestKind,
 };
 
-use super::macros::send_response;
+use super::{macros::send_response, parent_span};
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 #[non_exhaustive]
-// NOTE: maybe useful in the future later for some fields?
 pub(crate) struct FileReqResHandler {
     pending_requests: HashMap<OutboundRequestId, RequestHandler>,
+    limiter: InboundLimiter,
+    // inbound requests that were let through by the limiter and have not been answered yet
+    inbound_requests: HashMap<InboundRequestId, PeerId>,
+    // when each pending outbound request was sent, to score the supplier once it is answered
+    outbound_started: HashMap<OutboundRequestId, Instant>,
+    reputation: ReputationTracker,
 }
 
 impl FileReqResHandler {
+    pub(crate) fn new(inbound_limits: InboundLimits) -> Self {
+        Self {
+            pending_requests: Default::default(),
+            limiter: InboundLimiter::new(inbound_limits),
+            inbound_requests: Default::default(),
+            outbound_started: Default::default(),
+            reputation: Default::default(),
+        }
+    }
+
+    pub(crate) const fn reputation(&self) -> &ReputationTracker {
+        &self.reputation
+    }
+
+    pub(crate) const fn inbound_metrics(&self) -> InboundMetrics {
+        self.limiter.metrics()
+    }
+
     pub(crate) fn handle_request(
         &mut self,
         event: FileReqResRequestData,
@@ -31,8 +64,10 @@ impl FileReqResHandler {
     ) {
         match event {
             FileReqResRequestData::GetSupplierInfo { file_hash, peer_id } => {
-                let qid = req_res.send_request(&peer_id, FileHash(file_hash));
+                let qid = req_res.send_request(&peer_id, file_hash);
+                info!(request_id = %qid, %peer_id, "Sent supplier info request");
                 self.pending_requests.insert(qid, request_handler);
+                self.outbound_started.insert(qid, Instant::now());
             }
         }
     }
@@ -50,11 +85,25 @@ impl FileReqResHandler {
                     request,
                     channel,
                 } => {
+                    if let Err(rejected) = self.limiter.start(peer) {
+                        warn!("[RequestId {request_id}] Rejected request from {peer}: {rejected}");
+                        let response = SupplierInfoResponse::Rejected { rejected };
+                        if req_res.send_response(channel, response).is_err() {
+                            error!("[RequestId {request_id}] Failed to send response to {peer}!");
+                        }
+                        return;
+                    }
+                    self.inbound_requests.insert(request_id, peer);
+                    market_map.record_request(RequestKind::SupplierInfo, &request, Some(peer));
                     if let Some(supplier_info) = market_map.get_if_not_expired(&request) {
-                        if req_res.send_response(channel, supplier_info).is_err() {
+                        let response = SupplierInfoResponse::Found(supplier_info);
+                        if req_res.send_response(channel, response).is_err() {
                             error!("[RequestId {request_id}] Failed to send response to {peer}!");
+                            self.finish_inbound(&request_id);
                         }
                     } else {
+                        // NOTE: dropping the channel is reported as an inbound failure, which
+                        // finishes the request
                         warn!(
                             "File hash not found and a response was not sent: {:?}",
                             request
@@ -65,18 +114,52 @@ impl FileReqResHandler {
                     request_id,
                     response,
                 } => {
-                    let response =
-                        ResponseData::ReqResResponse(FileReqResResponseData::GetSupplierInfo {
-                            supplier_info: response,
-                        });
-                    send_response!(self.pending_requests, request_id, Ok(response));
+                    let span = info_span!(
+                        parent: parent_span(&self.pending_requests, &request_id),
+                        "outbound_request",
+                        %request_id,
+                        %peer,
+                    );
+                    let _entered = span.enter();
+                    let started = self.outbound_started.remove(&request_id);
+                    let response = match response {
+                        SupplierInfoResponse::Found(supplier_info) => {
+                            if let Some(started) = started {
+                                self.reputation.record_success(
+                                    peer,
+                                    started.elapsed(),
+                                    Instant::now(),
+                                );
+                            }
+                            Ok(ResponseData::ReqResResponse(
+                                FileReqResResponseData::GetSupplierInfo { supplier_info },
+                            ))
+                        }
+                        SupplierInfoResponse::Rejected { rejected } => {
+                            // NOTE: the supplier only held back because of our own requests
+                            self.reputation.record_rejection(peer, Instant::now());
+                            Err(rejected.into())
+                        }
+                    };
+                    send_response!(self.pending_requests, request_id, response);
                     info!("[RequestId {request_id}] Response sent to {peer}");
                 }
             },
             request_response::Event::OutboundFailure {
-                request_id, error, ..
+                peer,
+                request_id,
+                error,
             } => {
+                let span = info_span!(
+                    parent: parent_span(&self.pending_requests, &request_id),
+                    "outbound_request",
+                    %request_id,
+                    %peer,
+                );
+                let _entered = span.enter();
                 error!("Outbound failure: {}", error);
+                self.outbound_started.remove(&request_id);
+                self.reputation.record_failure(peer, Instant::now());
                 send_response!(self.pending_requests, request_id, Err(error.into()));
             }
             request_response::Event::InboundFailure {
@@ -88,22 +171,28 @@ impl FileReqResHandler {
                     "[RequestId {request_id}] Could not send response to {peer}: {}",
                     error
                 );
+                self.finish_inbound(&request_id);
             }
             request_response::Event::ResponseSent { peer, request_id } => {
                 info!("[RequestId {request_id}] Response sent to {peer}");
+                self.finish_inbound(&request_id);
             }
         }
     }
+
+    fn finish_inbound(&mut self, request_id: &InboundRequestId) {
+        if let Some(peer) = self.inbound_requests.remove(request_id) {
+            self.limiter.finish(&peer);
+        }
+    }
 }
 
-pub(crate) const FILE_REQ_RES_PROTOCOL: [(StreamProtocol, ProtocolSupport); 1] = [(
-    StreamProtocol::new("/file_req_res/1.0.0"),
-    ProtocolSupport::Full,
-)];
+pub(crate) const FILE_REQ_RES_PROTOCOL_NAME: StreamProtocol =
+    StreamProtocol::new("/file_req_res/1.0.0");
 
 #[derive(NetworkBehaviour)]
 pub(crate) struct FileReqResBehaviour {
-    req_res: cbor::Behaviour<FileHash, SupplierInfo>,
+    req_res: cbor::Behaviour<FileHash, SupplierInfoResponse>,
 }
 
 impl FileReqResBehaviour {
@@ -117,9 +206,100 @@ impl FileReqResBehaviour {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
-#[repr(transparent)]
-pub(crate) struct FileHash(pub(crate) Vec<u8>);
+/// Peers from before requests could be rejected respond with a bare [`SupplierInfo`], which is
+/// still what a found supplier info is encoded as. They fail to decode a rejection, which they
+/// treat like any other failed request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(
+    try_from = "WireSupplierInfoResponse",
+    into = "WireSupplierInfoResponse"
+)]
+pub(crate) enum SupplierInfoResponse {
+    Found(SupplierInfo),
+    Rejected { rejected: InboundRejection },
+}
+
+// NOTE: not an untagged enum since serde buffers those in a human readable form, which breaks
+// the compact encoding of the ip in cbor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct WireSupplierInfoResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ip: Option<Ipv4Addr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    price: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    addr: Option<SupplierAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rejected: Option<InboundRejection>,
+}
+
+impl TryFrom<WireSupplierInfoResponse> for SupplierInfoResponse {
+    type Error = String;
+
+    fn try_from(value: WireSupplierInfoResponse) -> Result<Self, Self::Error> {
+        if let Some(rejected) = value.rejected {
+            return Ok(Self::Rejected { rejected });
+        }
+        match value {
+            WireSupplierInfoResponse {
+                ip: Some(ip),
+                port: Some(port),
+                price: Some(price),
+                username: Some(username),
+                addr,
+                ..
+            } => Ok(Self::Found(
+                WireSupplierInfo {
+                    ip,
+                    port,
+                    price,
+                    username,
+                    addr,
+                }
+                .into(),
+            )),
+            _ => {
+                Err("supplier info response is neither a supplier info nor a rejection".to_owned())
+            }
+        }
+    }
+}
+
+impl From<SupplierInfoResponse> for WireSupplierInfoResponse {
+    fn from(value: SupplierInfoResponse) -> Self {
+        match value {
+            SupplierInfoResponse::Found(supplier_info) => {
+                let WireSupplierInfo {
+                    ip,
+                    port,
+                    price,
+                    username,
+                    addr,
+                } = supplier_info.into();
+                Self {
+                    ip: Some(ip),
+                    port: Some(port),
+                    price: Some(price),
+                    username: Some(username),
+                    addr,
+                    rejected: None,
+                }
+            }
+            SupplierInfoResponse::Rejected { rejected } => Self {
+                ip: None,
+                port: None,
+                price: None,
+                username: None,
+                addr: None,
+                rejected: Some(rejected),
+            },
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct FileMetadata {
@@ -128,9 +308,134 @@ pub(crate) struct FileMetadata {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "WireSupplierInfo", into = "WireSupplierInfo")]
 pub struct SupplierInfo {
-    pub ip: Ipv4Addr,
+    pub addr: SupplierAddr,
     pub port: u16,
     pub price: i64,
     pub username: String,
 }
+
+/// The encoding of [`SupplierInfo`] on the wire and in the registry. Peers from before supplier
+/// addresses were generalized only know about `ip` and ignore `addr`, so `ip` is always filled in
+/// (with the unspecified address if the supplier has no IPv4 address) and `addr` is only sent
+/// when `ip` does not already say everything.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct WireSupplierInfo {
+    ip: Ipv4Addr,
+    port: u16,
+    price: i64,
+    username: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    addr: Option<SupplierAddr>,
+}
+
+impl From<WireSupplierInfo> for SupplierInfo {
+    fn from(value: WireSupplierInfo) -> Self {
+        Self {
+            addr: value.addr.unwrap_or(SupplierAddr::Ip(value.ip.into())),
+            port: value.port,
+            price: value.price,
+            username: value.username,
+        }
+    }
+}
+
+impl From<SupplierInfo> for WireSupplierInfo {
+    fn from(value: SupplierInfo) -> Self {
+        let ip = value.addr.legacy_ipv4();
+        Self {
+            ip: ip.unwrap_or(Ipv4Addr::UNSPECIFIED),
+            port: value.port,
+            price: value.price,
+            username: value.username,
+            addr: match value.addr {
+                SupplierAddr::Ip(IpAddr::V4(_)) => None,
+                addr => Some(addr),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use pretty_assertions::assert_eq;
+    use serde::{Deserialize, Serialize};
+
+    use super::{SupplierInfo, SupplierInfoResponse};
+    use crate::{address::SupplierAddr, rate_limit::InboundRejection};
+
+    /// [`SupplierInfo`] as it was before supplier addresses were generalized.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    struct OldSupplierInfo {
+        ip: Ipv4Addr,
+        port: u16,
+        price: i64,
+        username: String,
+    }
+
+    fn round_trip<T: Serialize, U: for<'de> Deserialize<'de>>(value: &T) -> U {
+        cbor4ii::serde::from_slice(&cbor4ii::serde::to_vec(Vec::new(), value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_old_peers_read_new_supplier_info() {
+        let old: OldSupplierInfo = round_trip(&SupplierInfo {
+            addr: "2001:db8::1".parse().unwrap(),
+            port: 8080,
+            price: 10,
+            username: "supplier".to_owned(),
+        });
+        assert_eq!(old.ip, Ipv4Addr::UNSPECIFIED);
+        let old: OldSupplierInfo = round_trip(&SupplierInfo {
+            addr: SupplierAddr::from([10, 0, 0, 1]),
+            port: 8080,
+            price: 10,
+            username: "supplier".to_owned(),
+        });
+        assert_eq!(old.ip, Ipv4Addr::new(10, 0, 0, 1));
+    }
+
+    #[test]
+    fn test_new_peers_read_old_supplier_info() {
+        let old = OldSupplierInfo {
+            ip: Ipv4Addr::new(10, 0, 0, 1),
+            port: 8080,
+            price: 10,
+            username: "supplier".to_owned(),
+        };
+        let new: SupplierInfo = round_trip(&old);
+        assert_eq!(new.addr, SupplierAddr::from([10, 0, 0, 1]));
+        assert_eq!(new.port, old.port);
+
+        let dns = SupplierInfo {
+            addr: SupplierAddr::dns("files.example.com").unwrap(),
+            ..new
+        };
+        assert_eq!(round_trip::<_, SupplierInfo>(&dns), dns);
+    }
+
+    #[test]
+    fn test_supplier_info_response_compat() {
+        let old = OldSupplierInfo {
+            ip: Ipv4Addr::new(10, 0, 0, 1),
+            port: 8080,
+            price: 10,
+            username: "supplier".to_owned(),
+        };
+        let SupplierInfoResponse::Found(new) = round_trip(&old) else {
+            panic!("a bare supplier info should decode as found");
+        };
+        assert_eq!(new.addr, SupplierAddr::from([10, 0, 0, 1]));
+        assert_eq!(
+            round_trip::<_, OldSupplierInfo>(&SupplierInfoResponse::Found(new)),
+            old
+        );
+        let rejected = SupplierInfoResponse::Rejected {
+            rejected: InboundRejection::RateLimited,
+        };
+        assert_eq!(round_trip::<_, SupplierInfoResponse>(&rejected), rejected);
+    }
+}
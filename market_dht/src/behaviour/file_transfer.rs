@@ -0,0 +1,296 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use futures::{stream::FuturesUnordered, StreamExt};
+use libp2p::{
+    request_response::{
+        self, cbor, Config, InboundRequestId, OutboundRequestId, ProtocolSupport, ResponseChannel,
+    },
+    swarm::NetworkBehaviour,
+    PeerId, StreamProtocol,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{io::AsyncReadExt, task::JoinHandle};
+use tracing::{error, info, info_span, warn};
+
+use crate::{
+    coordinator::LocalMarketMap,
+    file_hash::FileHash,
+    req_res::{FileTransferRequestData, FileTransferResponseData, RequestHandler, ResponseData},
+};
+
+use super::{macros::send_response, parent_span};
+
+/// Size of a single chunk sent over the file transfer protocol.
+pub(crate) const CHUNK_SIZE: u64 = 256 * 1024;
+
+pub(crate) const FILE_TRANSFER_PROTOCOL_NAME: StreamProtocol =
+    StreamProtocol::new("/orcanet/file_transfer/1.0.0");
+
+#[derive(Debug, Default)]
+pub(crate) struct FileTransferHandler {
+    serve_dir: Option<PathBuf>,
+    pending_requests: HashMap<OutboundRequestId, RequestHandler>,
+    // NOTE: chunks are read on the blocking pool so that a slow disk does not stall the swarm
+    pending_reads: FuturesUnordered<JoinHandle<ChunkRead>>,
+}
+
+/// A chunk that was read for an inbound request and still has to be sent.
+#[derive(Debug)]
+pub(crate) struct ChunkRead {
+    peer: PeerId,
+    request_id: InboundRequestId,
+    channel: ResponseChannel<FileChunkResponse>,
+    response: FileChunkResponse,
+}
+
+impl FileTransferHandler {
+    pub(crate) fn new(serve_dir: Option<PathBuf>) -> Self {
+        Self {
+            serve_dir,
+            pending_requests: Default::default(),
+            pending_reads: Default::default(),
+        }
+    }
+
+    pub(crate) fn has_pending_reads(&self) -> bool {
+        !self.pending_reads.is_empty()
+    }
+
+    /// Waits for the next chunk read to finish. Never finishes while there are no pending reads,
+    /// see [`FileTransferHandler::has_pending_reads`].
+    pub(crate) async fn next_read(&mut self) -> Option<ChunkRead> {
+        match self.pending_reads.next().await? {
+            Ok(read) => Some(read),
+            Err(err) => {
+                error!("Failed to read a chunk: {err}");
+                None
+            }
+        }
+    }
+
+    /// Sends a chunk once it was read, see [`FileTransferHandler::next_read`].
+    pub(crate) fn send_chunk(
+        &mut self,
+        ChunkRead {
+            peer,
+            request_id,
+            channel,
+            response,
+        }: ChunkRead,
+        file_transfer: Option<&mut FileTransferBehaviour>,
+    ) {
+        let Some(FileTransferBehaviour { req_res }) = file_transfer else {
+            return;
+        };
+        if req_res.send_response(channel, response).is_err() {
+            error!("[RequestId {request_id}] Failed to send chunk to {peer}!");
+        }
+    }
+
+    pub(crate) fn handle_request(
+        &mut self,
+        request: FileTransferRequestData,
+        request_handler: RequestHandler,
+        file_transfer: Option<&mut FileTransferBehaviour>,
+    ) {
+        let Some(FileTransferBehaviour { req_res }) = file_transfer else {
+            send_response!(request_handler, anyhow!("File transfer is not enabled"));
+            return;
+        };
+        match request {
+            FileTransferRequestData::GetChunk {
+                file_hash,
+                peer_id,
+                index,
+            } => {
+                let qid = req_res.send_request(&peer_id, FileChunkRequest { file_hash, index });
+                info!(request_id = %qid, %peer_id, index, "Sent chunk request");
+                self.pending_requests.insert(qid, request_handler);
+            }
+        }
+    }
+
+    pub(crate) fn handle_event(
+        &mut self,
+        FileTransferBehaviourEvent::ReqRes(event): FileTransferBehaviourEvent,
+        market_map: &mut LocalMarketMap,
+        file_transfer: Option<&mut FileTransferBehaviour>,
+    ) {
+        let Some(FileTransferBehaviour { req_res }) = file_transfer else {
+            return;
+        };
+        match event {
+            request_response::Event::Message { peer, message } => match message {
+                request_response::Message::Request {
+                    request_id,
+                    request,
+                    channel,
+                } => {
+                    let Some(path) = self.chunk_path(&request.file_hash, market_map) else {
+                        if req_res
+                            .send_response(channel, FileChunkResponse::NotFound)
+                            .is_err()
+                        {
+                            error!("[RequestId {request_id}] Failed to send chunk to {peer}!");
+                        }
+                        return;
+                    };
+                    let FileChunkRequest { file_hash, index } = request;
+                    self.pending_reads
+                        .push(tokio::task::spawn_blocking(move || {
+                            let response = match read_chunk_from(&path, index) {
+                                Ok((data, file_size)) => {
+                                    FileChunkResponse::Chunk { data, file_size }
+                                }
+                                Err(err) => {
+                                    error!("Failed to read chunk {index} of {file_hash:?}: {err}");
+                                    FileChunkResponse::NotFound
+                                }
+                            };
+                            ChunkRead {
+                                peer,
+                                request_id,
+                                channel,
+                                response,
+                            }
+                        }));
+                }
+                request_response::Message::Response {
+                    request_id,
+                    response,
+                } => {
+                    let span = info_span!(
+                        parent: parent_span(&self.pending_requests, &request_id),
+                        "outbound_request",
+                        %request_id,
+                        %peer,
+                    );
+                    let _entered = span.enter();
+                    let response = match response {
+                        FileChunkResponse::Chunk { data, file_size } => {
+                            Ok(ResponseData::FileTransferResponse(
+                                FileTransferResponseData::Chunk { data, file_size },
+                            ))
+                        }
+                        FileChunkResponse::NotFound => {
+                            Err(anyhow!("Peer {peer} does not serve the requested file"))
+                        }
+                    };
+                    send_response!(self.pending_requests, request_id, response);
+                }
+            },
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                let span = info_span!(
+                    parent: parent_span(&self.pending_requests, &request_id),
+                    "outbound_request",
+                    %request_id,
+                    %peer,
+                );
+                let _entered = span.enter();
+                error!("Outbound failure: {}", error);
+                send_response!(self.pending_requests, request_id, Err(error.into()));
+            }
+            request_response::Event::InboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                error!(
+                    "[RequestId {request_id}] Could not send chunk to {peer}: {}",
+                    error
+                );
+            }
+            request_response::Event::ResponseSent { peer, request_id } => {
+                info!("[RequestId {request_id}] Chunk sent to {peer}");
+            }
+        }
+    }
+
+    /// Where the chunks of `file_hash` are read from, if this node serves it.
+    fn chunk_path(&self, file_hash: &FileHash, market_map: &LocalMarketMap) -> Option<PathBuf> {
+        let serve_dir = self.serve_dir.as_deref()?;
+        if market_map.get_if_not_expired(file_hash).is_none() {
+            warn!("Chunk requested for a file that is not registered: {file_hash:?}");
+            return None;
+        }
+        Some(
+            market_map
+                .served_path(file_hash)
+                .map_or_else(|| served_path(serve_dir, file_hash), Path::to_path_buf),
+        )
+    }
+}
+
+/// Registered files are served from `serve_dir/<hex encoded file hash>`.
+pub(crate) fn served_path(serve_dir: &Path, file_hash: &FileHash) -> PathBuf {
+    serve_dir.join(file_hash.to_hex())
+}
+
+/// Streams the file at `path` and computes its SHA-256 digest, which is the canonical hash a file
+/// is registered under.
+pub(crate) async fn sha256_digest(path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; CHUNK_SIZE as usize];
+    loop {
+        let num_read = file.read(&mut buf).await?;
+        if num_read == 0 {
+            break;
+        }
+        hasher.update(&buf[..num_read]);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+fn read_chunk_from(path: &Path, index: u64) -> io::Result<(Vec<u8>, u64)> {
+    let mut file = File::open(path)?;
+    let file_size = file.metadata()?.len();
+    let offset = index.saturating_mul(CHUNK_SIZE).min(file_size);
+    file.seek(SeekFrom::Start(offset))?;
+    let mut data = Vec::with_capacity(CHUNK_SIZE.min(file_size - offset) as usize);
+    file.take(CHUNK_SIZE).read_to_end(&mut data)?;
+    Ok((data, file_size))
+}
+
+#[derive(NetworkBehaviour)]
+pub(crate) struct FileTransferBehaviour {
+    req_res: cbor::Behaviour<FileChunkRequest, FileChunkResponse>,
+}
+
+impl FileTransferBehaviour {
+    pub(crate) fn new<I: IntoIterator<Item = (StreamProtocol, ProtocolSupport)>>(
+        protocols: I,
+        config: Config,
+    ) -> Self {
+        Self {
+            req_res: cbor::Behaviour::new(protocols, config),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FileChunkRequest {
+    file_hash: FileHash,
+    index: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum FileChunkResponse {
+    Chunk {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+        file_size: u64,
+    },
+    NotFound,
+}
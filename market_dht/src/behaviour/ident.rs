@@ -1,19 +1,56 @@
+use std::{collections::HashMap, net::IpAddr};
+
 use libp2p::{
     identify::{self, Behaviour as IdentifyBehaviour},
     swarm::NetworkBehaviour,
+    Multiaddr, PeerId,
 };
-use log::{error, info, warn};
+use tracing::{error, info, warn};
+
+use libp2p::StreamProtocol;
 
-use crate::behaviour::kademlia::KAD_PROTOCOL_NAME;
+use crate::address::multiaddr_ip;
 
 use super::kademlia::{Kad, KadStore};
 
 pub(crate) const IDENTIFY_PROTOCOL_NAME: &str = "/orcanet/id/1.0.0";
+// NOTE: a single peer can claim to observe any address, so it takes several to agree
+const MIN_OBSERVERS: usize = 3;
 
-#[derive(Debug, Default)]
-pub(crate) struct IdentifyHandler {}
+#[derive(Debug)]
+pub(crate) struct IdentifyHandler {
+    // only peers that speak our Kademlia protocol are added to the routing table
+    kad_protocol: StreamProtocol,
+    // the IP that each connected peer last observed this node at
+    observed_ips: HashMap<PeerId, IpAddr>,
+}
 
 impl IdentifyHandler {
+    pub(crate) fn new(kad_protocol: StreamProtocol) -> Self {
+        Self {
+            kad_protocol,
+            observed_ips: HashMap::new(),
+        }
+    }
+
+    /// The IPs that at least [`MIN_OBSERVERS`] connected peers observed this node at.
+    pub(crate) fn observed_addrs(&self) -> Vec<Multiaddr> {
+        let mut observers = HashMap::<IpAddr, usize>::new();
+        for ip in self.observed_ips.values() {
+            *observers.entry(*ip).or_default() += 1;
+        }
+        observers
+            .into_iter()
+            .filter(|(_, observers)| *observers >= MIN_OBSERVERS)
+            .map(|(ip, _)| Multiaddr::from(ip))
+            .collect()
+    }
+
+    /// Forgets what `peer_id` observed once the last connection to it is closed.
+    pub(crate) fn connection_closed(&mut self, peer_id: &PeerId) {
+        self.observed_ips.remove(peer_id);
+    }
+
     pub(crate) fn handle_identify_event<TKadStore: KadStore>(
         &mut self,
         IdentifyEvent::Identify(event): IdentifyEvent,
@@ -26,11 +63,15 @@ impl IdentifyHandler {
                     identify::Info {
                         listen_addrs,
                         protocols,
+                        observed_addr,
                         ..
                     },
             } => {
                 warn!("Peer {peer_id} identified with listen addresses: {listen_addrs:?} and protocols: {protocols:?}");
-                if protocols.iter().any(|proto| proto == &KAD_PROTOCOL_NAME) {
+                if let Some(ip) = multiaddr_ip(&observed_addr) {
+                    self.observed_ips.insert(peer_id, ip);
+                }
+                if protocols.iter().any(|proto| proto == &self.kad_protocol) {
                     for addr in listen_addrs {
                         kademlia.kad_mut().add_address(&peer_id, addr);
                     }
@@ -60,3 +101,36 @@ impl Identify {
         Self { identify }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use libp2p::{Multiaddr, PeerId};
+    use pretty_assertions::assert_eq;
+
+    use super::{IdentifyHandler, MIN_OBSERVERS};
+    use crate::behaviour::kademlia::KAD_PROTOCOL_NAME;
+
+    #[test]
+    fn test_observed_addrs_need_several_observers() {
+        let mut handler = IdentifyHandler::new(KAD_PROTOCOL_NAME);
+        let liar = PeerId::random();
+        handler.observed_ips.insert(liar, [6, 6, 6, 6].into());
+        let observers = (0..MIN_OBSERVERS)
+            .map(|_| PeerId::random())
+            .collect::<Vec<_>>();
+        for peer_id in &observers[1..] {
+            handler.observed_ips.insert(*peer_id, [1, 2, 3, 4].into());
+        }
+        assert_eq!(handler.observed_addrs(), Vec::<Multiaddr>::new());
+
+        handler
+            .observed_ips
+            .insert(observers[0], [1, 2, 3, 4].into());
+        assert_eq!(
+            handler.observed_addrs(),
+            vec!["/ip4/1.2.3.4".parse::<Multiaddr>().unwrap()]
+        );
+        handler.connection_closed(&observers[0]);
+        assert_eq!(handler.observed_addrs(), Vec::<Multiaddr>::new());
+    }
+}
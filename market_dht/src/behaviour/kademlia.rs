@@ -1,37 +1,133 @@
-use std::collections::HashMap;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    num::NonZeroUsize,
+    sync::Mutex,
+    task::{Context, Poll},
+    time::{Instant, SystemTime},
+};
 
 use anyhow::anyhow;
 use libp2p::{
+    core::Endpoint,
     kad::{
         self,
-        store::{MemoryStore, RecordStore},
+        store::{self, MemoryStore, RecordStore},
         AddProviderError, AddProviderOk, Behaviour as KadBehaviour, GetClosestPeersOk,
-        GetProvidersOk, InboundRequest, ProgressStep, QueryId, QueryResult, QueryStats,
+        GetProvidersOk, GetRecordError, GetRecordOk, InboundRequest, PeerRecord, ProgressStep,
+        ProviderRecord, PutRecordOk, QueryId, QueryResult, Quorum, Record, RecordKey, K_VALUE,
+    },
+    swarm::{
+        ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+        THandlerOutEvent, ToSwarm,
     },
-    swarm::NetworkBehaviour,
-    PeerId, StreamProtocol,
+    Multiaddr, PeerId, StreamProtocol,
 };
-use log::{debug, error, info, warn};
 use thiserror::Error;
+use tracing::{debug, error, info, info_span, warn};
 
 use crate::{
-    behaviour::send_response,
+    behaviour::{parent_span, send_response},
     boot_nodes::BootNodes,
+    config::InboundLimits,
     coordinator::LocalMarketMap,
-    req_res::{KadRequestData, KadResponseData, RequestHandler, ResponseData},
+    file_hash::FileHash,
+    net::PROVIDER_RECORD_TTL,
+    rate_limit::{InboundLimiter, InboundMetrics},
+    reputation::ProviderRanking,
+    req_res::{DhtRecord, KadRequestData, KadResponseData, RequestHandler, ResponseData},
+    search::{is_posting_key, posting_key, Postings},
+    stats::RequestKind,
+    sybil::{Admission, ProviderRejections, SybilGuard},
 };
 
-use super::file_req_res::FileHash;
-
 pub(crate) const KAD_PROTOCOL_NAME: StreamProtocol = StreamProtocol::new("/orcanet/kad/1.0.0");
-pub(crate) trait KadStore: RecordStore + Send + Sync + 'static {}
+pub(crate) trait KadStore: RecordStore + Send + Sync + 'static {
+    /// Tells the store which peer sent the Kademlia request that is being handled, and `None` once
+    /// it has been handled, see [`Kad`].
+    fn set_inbound_source(&mut self, _source: Option<PeerId>) {}
+
+    /// The keys that remote peers looked up the providers of since the last call, along with the
+    /// peer that looked each of them up, if the store keeps track of them.
+    fn take_provider_lookups(&mut self) -> Vec<(RecordKey, PeerId)> {
+        Vec::new()
+    }
+}
+
+// NOTE: lookups are taken as soon as Kademlia reports them, so this is only ever reached if that
+// stops being the case
+const MAX_PENDING_LOOKUPS: usize = 1024;
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub(crate) struct KadHandler {
+    local_peer_id: PeerId,
     pending_queries: HashMap<QueryId, RequestHandler>,
+    // NOTE: Kademlia is configured to leave storing inbound records to us so that they can be
+    // limited per peer
+    limiter: InboundLimiter,
+    sybil_guard: SybilGuard,
+    // the key of each pending GetRecord query, how many records are needed to finish early and
+    // the records found so far
+    found_records: HashMap<QueryId, (RecordKey, NonZeroUsize, Vec<DhtRecord>)>,
+    // the file hash of each GetProviders query that no peer named a provider for yet
+    provider_queries: HashMap<QueryId, FileHash>,
+    // the StartProviding queries of listings that did not exist before, which are dropped again
+    // if the query fails
+    new_listings: HashSet<QueryId>,
+    republish_failures: u64,
 }
 
 impl KadHandler {
+    pub(crate) fn new(
+        local_peer_id: PeerId,
+        inbound_limits: InboundLimits,
+        sybil_guard: SybilGuard,
+    ) -> Self {
+        Self {
+            local_peer_id,
+            pending_queries: Default::default(),
+            limiter: InboundLimiter::new(inbound_limits),
+            sybil_guard,
+            found_records: Default::default(),
+            provider_queries: Default::default(),
+            new_listings: Default::default(),
+            republish_failures: 0,
+        }
+    }
+
+    pub(crate) const fn sybil_guard_mut(&mut self) -> &mut SybilGuard {
+        &mut self.sybil_guard
+    }
+
+    /// Adds the peers that were kept out of the routing table for being too new once they have
+    /// been connected for long enough.
+    pub(crate) fn route_matured_peers<TKadStore: KadStore>(
+        &mut self,
+        Kad { kad }: &mut Kad<TKadStore>,
+    ) {
+        for (peer, addresses) in self.sybil_guard.take_matured(Instant::now()) {
+            info!(
+                "Adding peer {peer} to the routing table now that it has been around for a while"
+            );
+            for addr in addresses {
+                kad.add_address(&peer, addr);
+            }
+        }
+    }
+
+    pub(crate) const fn inbound_metrics(&self) -> InboundMetrics {
+        self.limiter.metrics()
+    }
+
+    pub(crate) const fn rejected_providers(&self) -> ProviderRejections {
+        self.sybil_guard.rejected_providers()
+    }
+
+    /// How many provider records and records failed to be republished since the node started.
+    pub(crate) const fn republish_failures(&self) -> u64 {
+        self.republish_failures
+    }
+
     pub(crate) fn handle_kad_request<TKadStore: KadStore>(
         &mut self,
         Kad { kad }: &mut Kad<TKadStore>,
@@ -51,35 +147,123 @@ impl KadHandler {
             }
             KadRequestData::ClosestPeers { key } => {
                 let qid = kad.get_closest_peers(key);
-                self.pending_queries.insert(qid, request_handler);
+                self.track_query(qid, request_handler);
             }
-            KadRequestData::RegisterFile { file_metadata } => {
+            KadRequestData::RegisterFile {
+                file_metadata,
+                served_path,
+            } => {
                 // TODO: do something about the cloning here
                 let key = file_metadata.file_hash;
+                if let Err(err) = market_map.check_capacity(&key) {
+                    send_response!(request_handler, err.into());
+                    return;
+                }
                 match kad.start_providing(key.0.clone().into()) {
                     Ok(qid) => {
-                        self.pending_queries.insert(qid, request_handler);
-                        market_map.insert(key, file_metadata.supplier_info);
+                        self.track_query(qid, request_handler);
+                        if market_map.insert(key, file_metadata.supplier_info, served_path) {
+                            self.new_listings.insert(qid);
+                        }
                     }
                     Err(err) => {
                         send_response!(request_handler, err.into());
                     }
                 }
             }
-            KadRequestData::GetProviders { key } => {
-                let qid = kad.get_providers(key.into());
-                self.pending_queries.insert(qid, request_handler);
+            KadRequestData::GetProviders { file_hash } => {
+                let qid = kad.get_providers(file_hash.0.clone().into());
+                self.track_query(qid, request_handler);
+                self.provider_queries.insert(qid, file_hash);
+            }
+            KadRequestData::PutRecord {
+                key,
+                value,
+                quorum,
+                ttl,
+            } => {
+                let mut record = Record::new(key, value);
+                let now = Instant::now();
+                let published_by_other = kad
+                    .store_mut()
+                    .get(&record.key)
+                    .filter(|existing| !existing.is_expired(now))
+                    .and_then(|existing| existing.publisher)
+                    .is_some_and(|publisher| publisher != self.local_peer_id);
+                if published_by_other {
+                    // NOTE: the other copies would be kept anyway, see `handle_inbound_request`
+                    send_response!(
+                        request_handler,
+                        anyhow!("Only the publisher of a record can overwrite it")
+                    );
+                    return;
+                }
+                record.expires = ttl.map(|ttl| now + ttl);
+                match kad.put_record(record, quorum) {
+                    Ok(qid) => {
+                        self.track_query(qid, request_handler);
+                    }
+                    Err(err) => {
+                        send_response!(request_handler, err.into());
+                    }
+                }
+            }
+            KadRequestData::GetRecord { key, quorum } => {
+                let key = RecordKey::from(key);
+                let qid = kad.get_record(key.clone());
+                self.track_query(qid, request_handler);
+                self.found_records
+                    .insert(qid, (key, quorum_size(quorum), Vec::new()));
+            }
+            KadRequestData::IndexFile {
+                file_hash,
+                keywords,
+            } => match market_map.set_keywords(&file_hash, keywords.clone()) {
+                Ok(expires_at) => {
+                    publish_postings(kad, self.local_peer_id, &file_hash, &keywords, expires_at);
+                    request_handler.respond(Ok(ResponseData::KadResponse(
+                        KadResponseData::IndexFile { keywords },
+                    )));
+                }
+                Err(err) => {
+                    send_response!(request_handler, err.into());
+                }
+            },
+            KadRequestData::RemoveRecord { key } => {
+                let record_key = key.clone().into();
+                match kad.store_mut().get(&record_key) {
+                    Some(record) if record.publisher != Some(self.local_peer_id) => {
+                        send_response!(
+                            request_handler,
+                            anyhow!("Only the publisher of a record can remove it")
+                        );
+                    }
+                    _ => {
+                        // NOTE: the copies held by other peers are left to expire
+                        kad.remove_record(&record_key);
+                        request_handler.respond(Ok(ResponseData::KadResponse(
+                            KadResponseData::RemoveRecord { key },
+                        )));
+                    }
+                }
             }
         }
     }
+    fn track_query(&mut self, qid: QueryId, request_handler: RequestHandler) {
+        info!(query_id = %qid, "Started Kademlia query");
+        self.pending_queries.insert(qid, request_handler);
+    }
+
     pub(crate) fn handle_kad_event<TKadStore: KadStore>(
         &mut self,
-        KadEvent::Kad(event): KadEvent<TKadStore>,
+        KadEvent::Kad(event): KadEvent,
+        kad: &mut Kad<TKadStore>,
         market_map: &mut LocalMarketMap,
+        ranking: ProviderRanking<'_>,
     ) {
         match event {
             kad::Event::InboundRequest { request } => {
-                self.handle_inbound_request(request);
+                self.handle_inbound_request(request, kad, market_map);
             }
             kad::Event::OutboundQueryProgressed {
                 id,
@@ -87,15 +271,43 @@ impl KadHandler {
                 stats,
                 step,
             } => {
-                self.handle_outbound_query(id, result, stats, step, market_map);
+                let span = info_span!(
+                    parent: parent_span(&self.pending_queries, &id),
+                    "kad_query",
+                    query_id = %id,
+                    step = step.count,
+                );
+                let _entered = span.enter();
+                debug!("Query {} progressed with stats: {:?}", id, stats);
+                self.handle_outbound_query(id, result, step, kad, market_map, ranking);
             }
             kad::Event::RoutingUpdated {
-                peer, addresses, ..
+                peer,
+                is_new_peer,
+                addresses,
+                ..
             } => {
-                warn!(
-                    "Routing updated for peer {} with addresses: {addresses:?}",
-                    peer
-                );
+                match self.sybil_guard.admit_route(
+                    peer,
+                    is_new_peer,
+                    addresses.iter().cloned(),
+                    Instant::now(),
+                ) {
+                    Admission::Keep => {
+                        warn!(
+                            "Routing updated for peer {} with addresses: {addresses:?}",
+                            peer
+                        );
+                    }
+                    Admission::Remove(rejection) => {
+                        warn!("Removing peer {peer} from the routing table: {rejection}");
+                        kad.kad_mut().remove_peer(&peer);
+                    }
+                    Admission::Defer => {
+                        info!("Holding peer {peer} back from the routing table until it has been connected for longer");
+                        kad.kad_mut().remove_peer(&peer);
+                    }
+                }
             }
             kad::Event::ModeChanged { new_mode } => {
                 info!("Kademlia mode changed to {}", new_mode);
@@ -111,15 +323,15 @@ impl KadHandler {
         }
     }
 
-    fn handle_outbound_query(
+    fn handle_outbound_query<TKadStore: KadStore>(
         &mut self,
         qid: kad::QueryId,
         result: QueryResult,
-        stats: QueryStats,
         step: ProgressStep,
+        Kad { kad }: &mut Kad<TKadStore>,
         market_map: &mut LocalMarketMap,
+        ranking: ProviderRanking<'_>,
     ) {
-        debug!("Query {} progressed with stats: {:?}", qid, stats);
         match result {
             QueryResult::Bootstrap(res) => match res {
                 Ok(kad::BootstrapOk {
@@ -143,68 +355,77 @@ impl KadHandler {
                     }
                 }
             },
-            QueryResult::GetClosestPeers(result) => {
-                // NOTE: maybe only care for the last part of the step?
-                if step.last {
-                    let response = {
-                        match result {
-                            Ok(GetClosestPeersOk { key, peers }) => {
-                                Ok(ResponseData::KadResponse(KadResponseData::ClosestPeers {
-                                    key,
-                                    peers,
-                                }))
-                            }
-                            Err(err) => Err(err.into()),
+            // NOTE: maybe only care for the last part of the step?
+            QueryResult::GetClosestPeers(result) if step.last => {
+                let response = {
+                    match result {
+                        Ok(GetClosestPeersOk { key, peers }) => {
+                            Ok(ResponseData::KadResponse(KadResponseData::ClosestPeers {
+                                key,
+                                peers,
+                            }))
                         }
-                    };
-                    send_response!(self.pending_queries, qid, response);
-                }
+                        Err(err) => Err(err.into()),
+                    }
+                };
+                send_response!(self.pending_queries, qid, response);
             }
             QueryResult::GetProviders(result) => match result {
-                Ok(ok_res) => match ok_res {
-                    GetProvidersOk::FoundProviders { key, providers } => {
-                        info!("GetProviders query succeeded for key {key:?}!");
+                // every peer that answers reports the providers it knows of, which are none for
+                // the peers that were not sent the provider record, so only the end of the query
+                // tells that nobody provides the key
+                Ok(GetProvidersOk::FoundProviders { providers, .. }) if providers.is_empty() => {}
+                Ok(GetProvidersOk::FoundProviders { key, providers }) => {
+                    info!("GetProviders query succeeded for key {key:?}!");
+                    self.provider_queries.remove(&qid);
+                    let providers = ranking.rank(providers, Instant::now());
+                    send_response!(
+                        self.pending_queries,
+                        qid,
+                        Ok(ResponseData::KadResponse(KadResponseData::GetProviders {
+                            file_hash: FileHash(key.to_vec()),
+                            providers
+                        }))
+                    );
+                }
+                Ok(GetProvidersOk::FinishedWithNoAdditionalRecord { .. }) => {
+                    if let Some(file_hash) = self.provider_queries.remove(&qid) {
+                        info!("GetProviders query found no providers for {file_hash:?}");
                         send_response!(
                             self.pending_queries,
                             qid,
                             Ok(ResponseData::KadResponse(KadResponseData::GetProviders {
-                                key: key.to_vec(),
-                                providers
+                                file_hash,
+                                providers: Vec::new()
                             }))
                         );
                     }
-                    // TODO: maybe do something with the below event; i don't undderstand if it'll
-                    // be useful atm
-                    GetProvidersOk::FinishedWithNoAdditionalRecord { .. } => {
-                        warn!("GetProviders query finished with no additional record");
-                        send_response!(
-                            self.pending_queries,
-                            qid,
-                            Err(anyhow!(
-                                "GetProviders query finished with no additional record"
-                            ))
-                        );
-                    }
-                },
+                }
                 Err(err) => {
                     error!("GetProviders query failed with error: {}", err);
+                    self.provider_queries.remove(&qid);
                     send_response!(self.pending_queries, qid, Err(err.into()));
                 }
             },
             QueryResult::StartProviding(result) => match result {
                 Ok(AddProviderOk { key }) => {
                     info!("StartProviding query succeeded for key: {:?}", key);
+                    self.new_listings.remove(&qid);
+                    refresh_listing(kad, self.local_peer_id, market_map, &FileHash(key.to_vec()));
                     send_response!(
                         self.pending_queries,
                         qid,
                         Ok(ResponseData::KadResponse(KadResponseData::RegisterFile {
-                            key: key.to_vec()
+                            key: FileHash(key.to_vec())
                         }))
                     );
                 }
                 Err(AddProviderError::Timeout { key }) => {
                     error!("StartProviding query timed out for key: {:?}", key);
-                    market_map.remove(&FileHash(key.to_vec()));
+                    // a listing that was registered before stays valid until it expires
+                    if self.new_listings.remove(&qid) {
+                        market_map.remove(&FileHash(key.to_vec()));
+                    }
                     send_response!(
                         self.pending_queries,
                         qid,
@@ -212,19 +433,91 @@ impl KadHandler {
                     );
                 }
             },
+            QueryResult::PutRecord(result) => {
+                let response = match result {
+                    Ok(PutRecordOk { key }) => {
+                        info!("PutRecord query succeeded for key: {:?}", key);
+                        Ok(ResponseData::KadResponse(KadResponseData::PutRecord {
+                            key: key.to_vec(),
+                        }))
+                    }
+                    Err(err) => {
+                        error!("PutRecord query failed: {err}");
+                        Err(err.into())
+                    }
+                };
+                send_response!(self.pending_queries, qid, response);
+            }
+            QueryResult::GetRecord(result) => {
+                let Some((_, needed, records)) = self.found_records.get_mut(&qid) else {
+                    return;
+                };
+                match result {
+                    Ok(GetRecordOk::FoundRecord(PeerRecord { peer, record })) => {
+                        if !record.is_expired(Instant::now()) {
+                            records.push(DhtRecord::new(record, peer));
+                        }
+                        if records.len() >= needed.get() {
+                            if let Some(mut query) = kad.query_mut(&qid) {
+                                query.finish();
+                            }
+                        }
+                        if !step.last {
+                            return;
+                        }
+                    }
+                    Ok(GetRecordOk::FinishedWithNoAdditionalRecord { .. }) => {}
+                    Err(err) => {
+                        warn!("GetRecord query ended with: {err}");
+                    }
+                }
+                let (key, _, records) = self
+                    .found_records
+                    .remove(&qid)
+                    .expect("the query to still be pending");
+                let response = if records.is_empty() {
+                    Err(GetRecordError::NotFound {
+                        key,
+                        closest_peers: Vec::new(),
+                    }
+                    .into())
+                } else {
+                    Ok(ResponseData::KadResponse(KadResponseData::GetRecord {
+                        records,
+                    }))
+                };
+                send_response!(self.pending_queries, qid, response);
+            }
+            QueryResult::RepublishRecord(result) => match result {
+                Ok(PutRecordOk { key }) => {
+                    info!("Successfully republished the record {key:?}!");
+                }
+                Err(err) => {
+                    error!("Failed to republish the record {:?}: {err}", err.key());
+                    self.republish_failures += 1;
+                }
+            },
             QueryResult::RepublishProvider(result) => match result {
                 Ok(AddProviderOk { key }) => {
                     info!("Successfully republished the key {key:?}!");
+                    refresh_listing(kad, self.local_peer_id, market_map, &FileHash(key.to_vec()));
                 }
-                Err(AddProviderError::Timeout { key }) => {
-                    error!("Timed out! Failed to republish the key {key:?}!");
+                Err(err) => {
+                    error!("Failed to republish the key {:?}: {err}", err.key());
+                    self.republish_failures += 1;
+                    market_map.publish_failed(&FileHash(err.key().to_vec()), err.to_string());
                 }
             },
             _ => {}
         }
     }
 
-    fn handle_inbound_request(&mut self, request: InboundRequest) {
+    fn handle_inbound_request<TKadStore: KadStore>(
+        &mut self,
+        request: InboundRequest,
+        Kad { kad }: &mut Kad<TKadStore>,
+        market_map: &mut LocalMarketMap,
+    ) {
         match request {
             InboundRequest::FindNode { num_closer_peers } => {
                 info!(
@@ -240,18 +533,114 @@ impl KadHandler {
                     "GetProvider request handled. Number of closest peers found: {}. Number of provider peers found: {}",
                     num_closer_peers, num_provider_peers
                 );
+                // NOTE: Kademlia reports neither the key nor the peer that asked, but the store
+                // saw both
+                for (key, peer) in kad.store_mut().take_provider_lookups() {
+                    market_map.record_request(
+                        RequestKind::ProviderLookup,
+                        &FileHash(key.to_vec()),
+                        Some(peer),
+                    );
+                }
             }
-            InboundRequest::AddProvider { .. } => {
-                info!("AddProvider request handled");
-            }
+            InboundRequest::AddProvider {
+                record: Some(record),
+            } => match self.limiter.check(record.provider) {
+                Ok(()) => {
+                    let existing_providers = kad
+                        .store_mut()
+                        .providers(&record.key)
+                        .into_iter()
+                        .map(|record| record.provider)
+                        .collect::<Vec<_>>();
+                    if let Err(rejection) = self.sybil_guard.check_provider(
+                        record.provider,
+                        &existing_providers,
+                        Instant::now(),
+                    ) {
+                        warn!(
+                            "Dropped provider record from {}: {rejection}",
+                            record.provider
+                        );
+                    } else if let Err(err) = kad.store_mut().add_provider(record) {
+                        error!("Failed to store provider record: {err}");
+                    } else {
+                        info!("AddProvider request handled");
+                    }
+                }
+                Err(rejection) => {
+                    warn!(
+                        "Dropped provider record from {}: {rejection}",
+                        record.provider
+                    );
+                }
+            },
+            InboundRequest::PutRecord {
+                source,
+                record: Some(record),
+                ..
+            } => match self.limiter.check(source) {
+                Ok(()) if is_posting_key(record.key.as_ref()) => {
+                    // every supplier publishes its own postings under the same key, so they are
+                    // merged rather than replaced, and only the postings of the sender are taken
+                    let mut record = record;
+                    let mut postings = Postings::decode(&record.value).published_by(source);
+                    if let Some(existing) = kad.store_mut().get(&record.key) {
+                        postings.merge(Postings::decode(&existing.value));
+                        record.expires = record.expires.max(existing.expires);
+                    }
+                    let max_expires = Instant::now() + PROVIDER_RECORD_TTL;
+                    record.expires = Some(
+                        record
+                            .expires
+                            .map_or(max_expires, |expires| expires.min(max_expires)),
+                    );
+                    record.value = postings.encode();
+                    record.publisher = None;
+                    if let Err(err) = kad.store_mut().put(record) {
+                        error!("Failed to store postings from {source}: {err}");
+                    }
+                }
+                Ok(()) => {
+                    let existing_publisher = kad
+                        .store_mut()
+                        .get(&record.key)
+                        .filter(|existing| !existing.is_expired(Instant::now()))
+                        .and_then(|existing| existing.publisher);
+                    // NOTE: the publisher of a record is whatever the sender claims, so records
+                    // are only taken from the peer that they name as their publisher, and only
+                    // overwritten by the same peer. Copies that other peers replicate are
+                    // dropped too, the publisher republishes its records on its own.
+                    if record.publisher != Some(source) {
+                        warn!(
+                            "Dropped record from {source} since it names {:?} as its publisher",
+                            record.publisher
+                        );
+                    } else if existing_publisher.is_some_and(|publisher| publisher != source) {
+                        warn!(
+                            "Dropped record from {source} since it was published by someone else"
+                        );
+                    } else if let Err(err) = kad.store_mut().put(record) {
+                        error!("Failed to store record from {source}: {err}");
+                    }
+                }
+                Err(rejection) => {
+                    warn!("Dropped record from {source}: {rejection}");
+                }
+            },
             // InboundRequest::GetRecord { .. } => {}
-            // InboundRequest::PutRecord { .. } => {}
             _ => {}
         }
     }
 }
 
-#[derive(NetworkBehaviour)]
+#[derive(Debug)]
+pub(crate) enum KadEvent {
+    Kad(kad::Event),
+}
+
+/// Kademlia along with the source of the inbound requests, which it does not report itself. The
+/// store is told the peer that each request came from while Kademlia handles it.
 pub(crate) struct Kad<TKadStore> {
     kad: KadBehaviour<TKadStore>,
 }
@@ -278,11 +667,83 @@ impl<TKadStore: KadStore> Kad<TKadStore> {
         &self.kad
     }
 
-    pub(crate) fn kad_mut(&mut self) -> &mut KadBehaviour<TKadStore> {
+    pub(crate) const fn kad_mut(&mut self) -> &mut KadBehaviour<TKadStore> {
         &mut self.kad
     }
 }
 
+impl<TKadStore: KadStore> NetworkBehaviour for Kad<TKadStore> {
+    type ConnectionHandler = THandler<KadBehaviour<TKadStore>>;
+    type ToSwarm = KadEvent;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.kad
+            .handle_pending_inbound_connection(connection_id, local_addr, remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.kad
+            .handle_established_inbound_connection(connection_id, peer, local_addr, remote_addr)
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.kad.handle_pending_outbound_connection(
+            connection_id,
+            maybe_peer,
+            addresses,
+            effective_role,
+        )
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.kad
+            .handle_established_outbound_connection(connection_id, peer, addr, role_override)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        self.kad.on_swarm_event(event)
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        self.kad.store_mut().set_inbound_source(Some(peer_id));
+        self.kad
+            .on_connection_handler_event(peer_id, connection_id, event);
+        self.kad.store_mut().set_inbound_source(None);
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<ToSwarm<KadEvent, THandlerInEvent<Self>>> {
+        self.kad.poll(cx).map(|event| event.map_out(KadEvent::Kad))
+    }
+}
+
 #[derive(Debug, Error)]
 pub(crate) enum KadError {
     #[error("Failed to bootstrap Kademlia: {0}")]
@@ -296,3 +757,157 @@ pub(crate) enum BootstrapMode {
 }
 
 impl KadStore for MemoryStore {}
+
+/// A [`MemoryStore`] that keeps track of which keys remote peers looked up the providers of, so
+/// that inbound provider lookups can be counted per key and peer. Lookups of this node itself are
+/// not counted.
+pub(crate) struct LookupStore {
+    inner: MemoryStore,
+    inbound_source: Option<PeerId>,
+    // NOTE: `RecordStore::providers` only gets a shared reference
+    provider_lookups: Mutex<Vec<(RecordKey, PeerId)>>,
+}
+
+impl std::fmt::Debug for LookupStore {
+    // NOTE: `MemoryStore` does not implement `Debug`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LookupStore")
+            .field("inbound_source", &self.inbound_source)
+            .field("provider_lookups", &self.provider_lookups)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LookupStore {
+    pub(crate) fn new(local_peer_id: PeerId) -> Self {
+        Self {
+            inner: MemoryStore::new(local_peer_id),
+            inbound_source: None,
+            provider_lookups: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl RecordStore for LookupStore {
+    type RecordsIter<'a> = <MemoryStore as RecordStore>::RecordsIter<'a>;
+    type ProvidedIter<'a> = <MemoryStore as RecordStore>::ProvidedIter<'a>;
+
+    fn get(&self, k: &RecordKey) -> Option<Cow<'_, Record>> {
+        self.inner.get(k)
+    }
+
+    fn put(&mut self, r: Record) -> store::Result<()> {
+        self.inner.put(r)
+    }
+
+    fn remove(&mut self, k: &RecordKey) {
+        self.inner.remove(k)
+    }
+
+    fn records(&self) -> Self::RecordsIter<'_> {
+        self.inner.records()
+    }
+
+    fn add_provider(&mut self, record: ProviderRecord) -> store::Result<()> {
+        self.inner.add_provider(record)
+    }
+
+    fn providers(&self, key: &RecordKey) -> Vec<ProviderRecord> {
+        if let Some(source) = self.inbound_source {
+            let mut provider_lookups = self
+                .provider_lookups
+                .lock()
+                .unwrap_or_else(|err| err.into_inner());
+            if provider_lookups.len() < MAX_PENDING_LOOKUPS {
+                provider_lookups.push((key.clone(), source));
+            }
+        }
+        self.inner.providers(key)
+    }
+
+    fn provided(&self) -> Self::ProvidedIter<'_> {
+        self.inner.provided()
+    }
+
+    fn remove_provider(&mut self, k: &RecordKey, p: &PeerId) {
+        self.inner.remove_provider(k, p)
+    }
+}
+
+impl KadStore for LookupStore {
+    fn set_inbound_source(&mut self, source: Option<PeerId>) {
+        self.inbound_source = source;
+    }
+
+    fn take_provider_lookups(&mut self) -> Vec<(RecordKey, PeerId)> {
+        std::mem::take(
+            self.provider_lookups
+                .get_mut()
+                .unwrap_or_else(|err| err.into_inner()),
+        )
+    }
+}
+
+/// Extends a listing after its provider record was published, along with the postings that it
+/// can be searched by.
+fn refresh_listing<TKadStore: KadStore>(
+    kad: &mut KadBehaviour<TKadStore>,
+    local_peer_id: PeerId,
+    market_map: &mut LocalMarketMap,
+    file_hash: &FileHash,
+) {
+    market_map.refresh(file_hash);
+    if let Some((keywords, expires_at)) = market_map.keywords(file_hash) {
+        publish_postings(kad, local_peer_id, file_hash, keywords, expires_at);
+    }
+}
+
+/// Adds `file_hash` to the postings of every keyword. The postings that this node already holds
+/// are republished along with it, though peers only take the ones of `local_peer_id` from it.
+fn publish_postings<TKadStore: KadStore>(
+    kad: &mut KadBehaviour<TKadStore>,
+    local_peer_id: PeerId,
+    file_hash: &FileHash,
+    keywords: &[String],
+    expires_at: SystemTime,
+) {
+    for keyword in keywords {
+        let key = posting_key(keyword).into();
+        let mut postings = kad
+            .store_mut()
+            .get(&key)
+            .map(|record| Postings::decode(&record.value))
+            .unwrap_or_default();
+        postings.insert(local_peer_id, file_hash.clone(), expires_at);
+        let mut record = Record::new(key, postings.encode());
+        record.expires = Some(Instant::now() + PROVIDER_RECORD_TTL);
+        if let Err(err) = kad.put_record(record, Quorum::One) {
+            error!("Failed to publish the postings of {keyword}: {err}");
+        }
+    }
+}
+
+/// How many records a GetRecord query collects before it finishes early.
+fn quorum_size(quorum: Quorum) -> NonZeroUsize {
+    match quorum {
+        Quorum::One => NonZeroUsize::MIN,
+        Quorum::Majority => NonZeroUsize::new(K_VALUE.get() / 2 + 1).expect("n + 1 != 0"),
+        Quorum::All => K_VALUE,
+        Quorum::N(n) => n.min(K_VALUE),
+    }
+}
+
+impl DhtRecord {
+    fn new(record: Record, from: Option<PeerId>) -> Self {
+        let now = Instant::now();
+        Self {
+            key: record.key.to_vec(),
+            value: record.value,
+            publisher: record.publisher,
+            expires_at: record
+                .expires
+                .map(|expires| SystemTime::now() + expires.saturating_duration_since(now)),
+            from,
+        }
+    }
+}
@@ -33,7 +33,7 @@ impl BootNodes {
         }
     }
 
-    pub fn iter(&self) -> BootNodesIter {
+    pub fn iter(&self) -> BootNodesIter<'_> {
         BootNodesIter {
             inner: self.0.iter(),
         }
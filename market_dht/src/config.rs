@@ -1,8 +1,24 @@
+use std::{
+    collections::HashSet,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use libp2p::{identity::Keypair, pnet::PreSharedKey};
+
 use crate::boot_nodes::BootNodes;
+use crate::metrics::Registry;
 use crate::multiaddr;
 use crate::Multiaddr;
+use crate::PeerId;
 
 const BRIDGE_THREAD_NAME: &str = "coordinator_netbridge_thread";
+const DEFAULT_REQUEST_QUEUE_CAPACITY: NonZeroUsize = match NonZeroUsize::new(1024) {
+    Some(n) => n,
+    None => unreachable!(),
+};
 
 #[derive(Debug, Clone)]
 #[non_exhaustive]
@@ -10,6 +26,21 @@ pub struct Config {
     pub(crate) boot_nodes: Option<BootNodes>,
     pub(crate) listener: Multiaddr,
     pub(crate) thread_name: String,
+    pub(crate) file_transfer_dir: Option<PathBuf>,
+    pub(crate) registry_path: Option<PathBuf>,
+    pub(crate) max_listings: Option<usize>,
+    pub(crate) inbound_limits: InboundLimits,
+    pub(crate) allowed_peers: Option<HashSet<PeerId>>,
+    pub(crate) blocked_peers: HashSet<PeerId>,
+    pub(crate) pre_shared_key: Option<PreSharedKey>,
+    pub(crate) protocol_prefix: Option<String>,
+    pub(crate) min_supplier_score: Option<f64>,
+    pub(crate) sybil_limits: SybilLimits,
+    pub(crate) identity: Option<Keypair>,
+    pub(crate) metrics_registry: Option<Arc<Mutex<Registry>>>,
+    pub(crate) request_queue_capacity: NonZeroUsize,
+    pub(crate) transport: Transport,
+    pub(crate) worker_threads: Option<NonZeroUsize>,
 }
 
 impl Config {
@@ -28,6 +59,135 @@ impl Config {
     pub fn thread_name(&self) -> &str {
         &self.thread_name
     }
+
+    pub fn file_transfer_dir(&self) -> Option<&Path> {
+        self.file_transfer_dir.as_deref()
+    }
+
+    pub fn registry_path(&self) -> Option<&Path> {
+        self.registry_path.as_deref()
+    }
+
+    pub const fn max_listings(&self) -> Option<usize> {
+        self.max_listings
+    }
+
+    pub const fn inbound_limits(&self) -> &InboundLimits {
+        &self.inbound_limits
+    }
+
+    pub const fn allowed_peers(&self) -> Option<&HashSet<PeerId>> {
+        self.allowed_peers.as_ref()
+    }
+
+    pub const fn blocked_peers(&self) -> &HashSet<PeerId> {
+        &self.blocked_peers
+    }
+
+    pub const fn pre_shared_key(&self) -> Option<&PreSharedKey> {
+        self.pre_shared_key.as_ref()
+    }
+
+    pub fn protocol_prefix(&self) -> Option<&str> {
+        self.protocol_prefix.as_deref()
+    }
+
+    pub const fn min_supplier_score(&self) -> Option<f64> {
+        self.min_supplier_score
+    }
+
+    pub const fn sybil_limits(&self) -> &SybilLimits {
+        &self.sybil_limits
+    }
+
+    pub const fn identity(&self) -> Option<&Keypair> {
+        self.identity.as_ref()
+    }
+
+    pub const fn metrics_registry(&self) -> Option<&Arc<Mutex<Registry>>> {
+        self.metrics_registry.as_ref()
+    }
+
+    pub const fn request_queue_capacity(&self) -> NonZeroUsize {
+        self.request_queue_capacity
+    }
+
+    pub const fn transport(&self) -> Transport {
+        self.transport
+    }
+
+    pub const fn worker_threads(&self) -> Option<NonZeroUsize> {
+        self.worker_threads
+    }
+}
+
+/// How the node reaches other nodes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Transport {
+    #[default]
+    Tcp,
+    /// libp2p's in-process transport, which only reaches nodes in the same process. Listeners are
+    /// `/memory/<port>` addresses. Meant for tests and simulations, see [`crate::testing`].
+    Memory,
+}
+
+/// Limits on the requests that every remote peer can make of this node, enforced separately for
+/// the supplier info protocol and for Kademlia. Kademlia answers lookups on its own without
+/// telling us who asked, so there only the provider records and records that peers store with us
+/// are limited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InboundLimits {
+    /// How many requests a peer can make per `window`.
+    pub max_requests: u32,
+    pub window: Duration,
+    /// How many requests of a peer can be handled at the same time.
+    pub max_in_flight: usize,
+}
+
+impl Default for InboundLimits {
+    fn default() -> Self {
+        Self {
+            max_requests: 100,
+            window: Duration::from_secs(10),
+            max_in_flight: 16,
+        }
+    }
+}
+
+/// Defenses against Sybil attacks on the Kademlia DHT, all of which are off by default. See
+/// [`crate::sybil`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SybilLimits {
+    /// How many provider records of the same file hash are stored from peers connected from the
+    /// same IP address. Peers without a known IP address, like those on the in-memory transport,
+    /// all count as connected from the same address and subnet.
+    pub max_providers_per_ip: Option<usize>,
+    /// Like `max_providers_per_ip`, for peers in the same subnet.
+    pub max_providers_per_subnet: Option<usize>,
+    /// The length of an IPv4 subnet prefix, `/24` by default.
+    pub ipv4_subnet_prefix: u8,
+    /// The length of an IPv6 subnet prefix, `/48` by default.
+    pub ipv6_subnet_prefix: u8,
+    /// How many leading zero bits the SHA-256 hash of a PeerId needs for the peer to enter the
+    /// routing table and have its provider records stored. Boot nodes are exempt.
+    pub peer_id_difficulty: u32,
+    /// How long a peer has to stay connected before it enters the routing table, so that
+    /// long-lived peers are preferred over fresh identities. Boot nodes are exempt.
+    pub min_routing_age: Duration,
+}
+
+impl Default for SybilLimits {
+    fn default() -> Self {
+        Self {
+            max_providers_per_ip: None,
+            max_providers_per_subnet: None,
+            ipv4_subnet_prefix: 24,
+            ipv6_subnet_prefix: 48,
+            peer_id_difficulty: 0,
+            min_routing_age: Duration::ZERO,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -36,6 +196,21 @@ pub struct ConfigBuilder {
     boot_nodes: Option<BootNodes>,
     listener: Option<Multiaddr>,
     thread_name: Option<String>,
+    file_transfer_dir: Option<PathBuf>,
+    registry_path: Option<PathBuf>,
+    max_listings: Option<usize>,
+    inbound_limits: Option<InboundLimits>,
+    allowed_peers: Option<HashSet<PeerId>>,
+    blocked_peers: Option<HashSet<PeerId>>,
+    pre_shared_key: Option<PreSharedKey>,
+    protocol_prefix: Option<String>,
+    min_supplier_score: Option<f64>,
+    sybil_limits: Option<SybilLimits>,
+    identity: Option<Keypair>,
+    metrics_registry: Option<Arc<Mutex<Registry>>>,
+    request_queue_capacity: Option<NonZeroUsize>,
+    transport: Option<Transport>,
+    worker_threads: Option<NonZeroUsize>,
 }
 
 impl ConfigBuilder {
@@ -44,6 +219,21 @@ impl ConfigBuilder {
             boot_nodes: None,
             listener: None,
             thread_name: None,
+            file_transfer_dir: None,
+            registry_path: None,
+            max_listings: None,
+            inbound_limits: None,
+            allowed_peers: None,
+            blocked_peers: None,
+            pre_shared_key: None,
+            protocol_prefix: None,
+            min_supplier_score: None,
+            sybil_limits: None,
+            identity: None,
+            metrics_registry: None,
+            request_queue_capacity: None,
+            transport: None,
+            worker_threads: None,
         }
     }
 
@@ -62,15 +252,134 @@ impl ConfigBuilder {
         self
     }
 
+    /// Enables the file transfer protocol. Registered files are served from `dir`, where each
+    /// file is named by the hex encoding of its SHA-256 hash.
+    pub fn with_file_transfer_dir(mut self, dir: PathBuf) -> Self {
+        self.file_transfer_dir = Some(dir);
+        self
+    }
+
+    /// Persists the local market listings to `path` so that they are restored and re-announced
+    /// when the node restarts.
+    pub fn with_registry_path(mut self, path: PathBuf) -> Self {
+        self.registry_path = Some(path);
+        self
+    }
+
+    /// Limits how many files this node can list at once. Registering past the limit fails.
+    pub const fn with_max_listings(mut self, max_listings: usize) -> Self {
+        self.max_listings = Some(max_listings);
+        self
+    }
+
+    /// Overrides the default [`InboundLimits`].
+    pub const fn with_inbound_limits(mut self, inbound_limits: InboundLimits) -> Self {
+        self.inbound_limits = Some(inbound_limits);
+        self
+    }
+
+    /// Only connects to the given peers. The boot nodes are always allowed.
+    pub fn with_allowed_peers(mut self, peers: impl IntoIterator<Item = PeerId>) -> Self {
+        self.allowed_peers = Some(peers.into_iter().collect());
+        self
+    }
+
+    /// Never connects to the given peers and leaves them out of lookups. Peers can also be
+    /// blocked while running with [`crate::peer::Peer::block_peer`].
+    pub fn with_blocked_peers(mut self, peers: impl IntoIterator<Item = PeerId>) -> Self {
+        self.blocked_peers = Some(peers.into_iter().collect());
+        self
+    }
+
+    /// Makes this node part of a private network. Connections are only established with peers
+    /// that have the same key, and all traffic is encrypted with it on top of noise.
+    pub const fn with_pre_shared_key(mut self, key: PreSharedKey) -> Self {
+        self.pre_shared_key = Some(key);
+        self
+    }
+
+    /// Names the protocols `{prefix}/kad/1.0.0`, `{prefix}/file_req_res/1.0.0` and so on instead of
+    /// the names used by the public network. The prefix must start with a `/`.
+    pub fn with_protocol_prefix(mut self, prefix: String) -> Self {
+        self.protocol_prefix = Some(prefix);
+        self
+    }
+
+    /// Leaves suppliers whose [`crate::Reputation::score`] is below `score` out of
+    /// [`crate::peer::Peer::check_holders`]. By default every supplier that answers is returned.
+    pub const fn with_min_supplier_score(mut self, score: f64) -> Self {
+        self.min_supplier_score = Some(score);
+        self
+    }
+
+    /// Overrides the default [`SybilLimits`].
+    pub const fn with_sybil_limits(mut self, sybil_limits: SybilLimits) -> Self {
+        self.sybil_limits = Some(sybil_limits);
+        self
+    }
+
+    /// The key pair that the PeerId of this node is derived from. Without one a new identity is
+    /// generated, with enough proof of work for [`SybilLimits::peer_id_difficulty`].
+    pub fn with_identity(mut self, identity: Keypair) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Registers the metrics of the node with `registry`, see [`crate::metrics`]. Nothing is
+    /// recorded without one.
+    pub fn with_metrics_registry(mut self, registry: Arc<Mutex<Registry>>) -> Self {
+        self.metrics_registry = Some(registry);
+        self
+    }
+
+    /// How many requests can wait for the node before [`crate::peer::Peer`] refuses new ones
+    /// with [`crate::peer::PeerError::Busy`]. Defaults to 1024.
+    pub const fn with_request_queue_capacity(mut self, capacity: NonZeroUsize) -> Self {
+        self.request_queue_capacity = Some(capacity);
+        self
+    }
+
+    /// Without a listener, the node listens on a random port of the transport.
+    pub const fn with_transport(mut self, transport: Transport) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// How many worker threads the runtime of the node gets, one per CPU by default. Running many
+    /// nodes in one process calls for fewer.
+    pub const fn with_worker_threads(mut self, threads: NonZeroUsize) -> Self {
+        self.worker_threads = Some(threads);
+        self
+    }
+
     pub fn build(self) -> Config {
+        let transport = self.transport.unwrap_or_default();
         Config {
             boot_nodes: self.boot_nodes,
-            listener: self
-                .listener
-                .unwrap_or(multiaddr!(Ip4([0, 0, 0, 0]), Tcp(0u16))),
+            listener: self.listener.unwrap_or_else(|| match transport {
+                Transport::Tcp => multiaddr!(Ip4([0, 0, 0, 0]), Tcp(0u16)),
+                Transport::Memory => multiaddr!(Memory(0u64)),
+            }),
             thread_name: self
                 .thread_name
                 .unwrap_or_else(|| BRIDGE_THREAD_NAME.to_owned()),
+            file_transfer_dir: self.file_transfer_dir,
+            registry_path: self.registry_path,
+            max_listings: self.max_listings,
+            inbound_limits: self.inbound_limits.unwrap_or_default(),
+            allowed_peers: self.allowed_peers,
+            blocked_peers: self.blocked_peers.unwrap_or_default(),
+            pre_shared_key: self.pre_shared_key,
+            protocol_prefix: self.protocol_prefix,
+            min_supplier_score: self.min_supplier_score,
+            sybil_limits: self.sybil_limits.unwrap_or_default(),
+            identity: self.identity,
+            metrics_registry: self.metrics_registry,
+            request_queue_capacity: self
+                .request_queue_capacity
+                .unwrap_or(DEFAULT_REQUEST_QUEUE_CAPACITY),
+            transport,
+            worker_threads: self.worker_threads,
         }
     }
 }
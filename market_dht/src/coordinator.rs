@@ -1,47 +1,122 @@
 use std::{
-    collections::HashMap,
-    time::{Duration, Instant},
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
 };
 use thiserror::Error;
 
 use futures::StreamExt;
-use libp2p::{kad::store::MemoryStore, swarm::SwarmEvent, Multiaddr, Swarm};
-use log::{error, info, warn};
-use tokio::{sync::mpsc, time};
+use libp2p::{kad, swarm::SwarmEvent, Multiaddr, PeerId, StreamProtocol, Swarm};
+use tokio::{
+    sync::{broadcast, mpsc},
+    task::{self, JoinHandle},
+    time,
+};
+use tracing::{error, info, info_span, warn};
 
 use crate::{
+    address::{multiaddr_ip, IpScope, SupplierAddr},
     behaviour::{
-        file_req_res::{FileHash, FileReqResHandler, SupplierInfo},
-        ident::IdentifyHandler,
-        kademlia::{BootstrapMode, KadHandler},
+        announce::{AnnounceEvent, AnnounceHandler, Announcement, MarketAnnouncement},
+        file_req_res::{FileReqResHandler, SupplierInfo},
+        file_transfer::FileTransferHandler,
+        ident::{IdentifyEvent, IdentifyHandler},
+        kademlia::{BootstrapMode, KadEvent, KadHandler, LookupStore},
         MarketBehaviour, MarketBehaviourEvent,
     },
     boot_nodes::BootNodes,
+    config::{InboundLimits, SybilLimits},
+    file_hash::FileHash,
+    metrics::{InboundProtocol, NodeMetrics},
     net::PROVIDER_RECORD_TTL,
-    req_res::{Request, RequestData, RequestHandler, ResponseData},
+    peer::PeerError,
+    registry::{Registry, RegistryError},
+    reputation::ProviderRanking,
+    req_res::{
+        Eviction, EvictionReason, KadRequestData, ListingError, ListingInfo, ListingUpdate,
+        Metrics, PublishStatus, Request, RequestData, RequestHandler, RequestQueueMetrics,
+        ResponseData,
+    },
+    stats::{ListingStats, RequestKind, RequestStats},
+    sybil::SybilGuard,
 };
 
 const BOOTSTRAP_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 10);
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+const ROUTING_PROMOTION_INTERVAL: Duration = Duration::from_secs(1);
+const METRICS_UPDATE_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_EVICTION_HISTORY: usize = 256;
+// NOTE: changes are batched so that a burst of registrations is written to the registry once
+const REGISTRY_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
 
 pub(crate) struct Coordinator {
-    swarm: Swarm<MarketBehaviour<MemoryStore>>,
+    swarm: Swarm<MarketBehaviour<LookupStore>>,
     kad_handler: KadHandler,
     identify_handler: IdentifyHandler,
     file_req_res_handler: FileReqResHandler,
+    file_transfer_handler: FileTransferHandler,
+    announce_handler: AnnounceHandler,
     market_map: LocalMarketMap,
-    request_receiver: mpsc::UnboundedReceiver<Request>,
+    // NOTE: mirrors the block list behaviour, which does not expose the peers it blocks
+    blocked_peers: HashSet<PeerId>,
+    min_supplier_score: Option<f64>,
+    metrics: Option<NodeMetrics>,
+    request_receiver: mpsc::Receiver<Request>,
+    // NOTE: only used to see how full the request queue is, the coordinator shuts down once every
+    // strong sender is gone
+    request_sender: mpsc::WeakSender<Request>,
+    request_queue_capacity: usize,
+    // set once a shutdown was requested, answered after everything else is gone
+    shutdown_request: Option<RequestHandler>,
+}
+
+/// The parts of the [`crate::config::Config`] that the coordinator itself acts on.
+#[derive(Debug)]
+pub(crate) struct CoordinatorConfig {
+    pub(crate) listen_addr: Multiaddr,
+    pub(crate) boot_nodes: Option<BootNodes>,
+    pub(crate) file_transfer_dir: Option<PathBuf>,
+    pub(crate) inbound_limits: InboundLimits,
+    pub(crate) blocked_peers: HashSet<PeerId>,
+    pub(crate) kad_protocol: StreamProtocol,
+    pub(crate) min_supplier_score: Option<f64>,
+    pub(crate) sybil_limits: SybilLimits,
+    pub(crate) announcements: broadcast::Sender<MarketAnnouncement>,
+    pub(crate) request_queue_capacity: usize,
+    pub(crate) metrics: Option<NodeMetrics>,
 }
 
 impl Coordinator {
     pub(crate) fn new(
-        mut swarm: Swarm<MarketBehaviour<MemoryStore>>,
-        listen_addr: Multiaddr,
-        boot_nodes: Option<BootNodes>,
-        request_receiver: mpsc::UnboundedReceiver<Request>,
+        mut swarm: Swarm<MarketBehaviour<LookupStore>>,
+        CoordinatorConfig {
+            listen_addr,
+            boot_nodes,
+            file_transfer_dir,
+            inbound_limits,
+            blocked_peers,
+            kad_protocol,
+            min_supplier_score,
+            sybil_limits,
+            announcements,
+            request_queue_capacity,
+            metrics,
+        }: CoordinatorConfig,
+        market_map: LocalMarketMap,
+        request_receiver: mpsc::Receiver<Request>,
+        request_sender: mpsc::WeakSender<Request>,
     ) -> Result<Self, CoordinatorError> {
         swarm
             .listen_on(listen_addr)
             .map_err(|err| CoordinatorError::SpawnError(err.to_string()))?;
+        let boot_node_ids = boot_nodes
+            .as_deref()
+            .unwrap_or_default()
+            .iter()
+            .map(|node| node.peer_id)
+            .collect::<Vec<_>>();
         if let Some(boot_nodes) = boot_nodes {
             swarm
                 .behaviour_mut()
@@ -49,44 +124,134 @@ impl Coordinator {
                 .bootstrap(BootstrapMode::WithNodes(boot_nodes))
                 .map_err(|err| CoordinatorError::SpawnError(err.to_string()))?;
         }
+        // re-announce the listings restored from the registry since the provider records did not
+        // survive the restart
+        for file_hash in market_map.file_hashes() {
+            if let Err(err) = swarm
+                .behaviour_mut()
+                .kademlia_mut()
+                .kad_mut()
+                .start_providing(file_hash.0.clone().into())
+            {
+                error!("Failed to re-announce restored listing {file_hash}: {err}");
+            } else {
+                info!("Re-announcing restored listing {file_hash}");
+            }
+        }
+        let announce_handler = AnnounceHandler::new(*swarm.local_peer_id(), announcements);
+        announce_handler
+            .subscribe(swarm.behaviour_mut().announce_mut())
+            .map_err(|err| CoordinatorError::SpawnError(err.to_string()))?;
+        let kad_handler = KadHandler::new(
+            *swarm.local_peer_id(),
+            inbound_limits,
+            SybilGuard::new(sybil_limits, boot_node_ids),
+        );
         Ok(Self {
             swarm,
-            kad_handler: Default::default(),
-            identify_handler: Default::default(),
-            file_req_res_handler: Default::default(),
-            market_map: Default::default(),
+            kad_handler,
+            identify_handler: IdentifyHandler::new(kad_protocol),
+            file_req_res_handler: FileReqResHandler::new(inbound_limits),
+            file_transfer_handler: FileTransferHandler::new(file_transfer_dir),
+            announce_handler,
+            market_map,
+            blocked_peers,
+            min_supplier_score,
+            metrics,
             request_receiver,
+            request_sender,
+            request_queue_capacity,
+            shutdown_request: None,
         })
     }
 
     pub(crate) async fn run(mut self) {
         let mut bootstrap_refresh_interval = time::interval(BOOTSTRAP_REFRESH_INTERVAL);
+        let mut expiry_sweep_interval = time::interval(EXPIRY_SWEEP_INTERVAL);
+        let mut routing_promotion_interval = time::interval(ROUTING_PROMOTION_INTERVAL);
+        let mut metrics_update_interval = time::interval(METRICS_UPDATE_INTERVAL);
+        let mut registry_flush_interval = time::interval(REGISTRY_FLUSH_INTERVAL);
 
         loop {
             tokio::select! {
                 _ = bootstrap_refresh_interval.tick() => {
                     self.handle_bootstrap_refresh();
                 }
+                _ = expiry_sweep_interval.tick() => {
+                    self.handle_expiry_sweep();
+                }
+                _ = routing_promotion_interval.tick() => {
+                    self.kad_handler
+                        .route_matured_peers(self.swarm.behaviour_mut().kademlia_mut());
+                }
+                _ = metrics_update_interval.tick(), if self.metrics.is_some() => {
+                    self.handle_metrics_update();
+                }
+                _ = registry_flush_interval.tick(), if self.market_map.has_unsaved_changes() => {
+                    self.market_map.flush();
+                }
                 request = self.request_receiver.recv() => {
-                    if let Some(request) = request {
-                        self.handle_request(request.0, request.1);
+                    if let Some((request_data, request_handler)) = request {
+                        let span = info_span!(
+                            parent: request_handler.span(),
+                            "request",
+                            request = request_data.name(),
+                            request_id = request_handler.request_id(),
+                        );
+                        let _entered = span.enter();
+                        let request_handler = match &self.metrics {
+                            Some(metrics) => request_handler.timed(metrics.timer(request_data.name())),
+                            None => request_handler,
+                        };
+                        self.handle_request(request_data, request_handler.in_span(span.clone()));
+                        if self.shutdown_request.is_some() {
+                            info!("Shutdown requested, shutting down coordinator");
+                            break;
+                        }
                     } else {
-                        error!("request receiver channel closed, shutting down coordinator");
+                        info!("Every peer handle was dropped, shutting down coordinator");
                         break;
                     }
                 }
                 swarm_event = self.swarm.select_next_some() => {
                     self.handle_swarm_event(swarm_event).await;
                 }
+                Some(read) = self.file_transfer_handler.next_read(),
+                    if self.file_transfer_handler.has_pending_reads() => {
+                    self.file_transfer_handler
+                        .send_chunk(read, self.swarm.behaviour_mut().file_transfer_mut());
+                }
             }
         }
+        self.market_map.close().await;
+        self.shut_down();
     }
 
-    fn handle_event(&mut self, event: MarketBehaviourEvent<MemoryStore>) {
+    fn handle_event(&mut self, event: MarketBehaviourEvent<LookupStore>) {
         match event {
-            MarketBehaviourEvent::Kademlia(event) => self
-                .kad_handler
-                .handle_kad_event(event, &mut self.market_map),
+            MarketBehaviourEvent::Kademlia(event) => {
+                if let KadEvent::Kad(kad::Event::RoutingUpdated { peer, .. }) = &event {
+                    if self.blocked_peers.contains(peer) {
+                        warn!("Removing blocked peer {peer} from the routing table");
+                        self.swarm
+                            .behaviour_mut()
+                            .kademlia_mut()
+                            .kad_mut()
+                            .remove_peer(peer);
+                        return;
+                    }
+                }
+                self.kad_handler.handle_kad_event(
+                    event,
+                    self.swarm.behaviour_mut().kademlia_mut(),
+                    &mut self.market_map,
+                    ProviderRanking {
+                        blocked_peers: &self.blocked_peers,
+                        reputation: self.file_req_res_handler.reputation(),
+                        min_score: self.min_supplier_score,
+                    },
+                )
+            }
             MarketBehaviourEvent::Identify(event) => self
                 .identify_handler
                 .handle_identify_event(event, self.swarm.behaviour_mut().kademlia_mut()),
@@ -97,6 +262,32 @@ impl Coordinator {
                     self.swarm.behaviour_mut().file_req_res_mut(),
                 );
             }
+            MarketBehaviourEvent::FileTransfer(event) => {
+                self.file_transfer_handler.handle_event(
+                    event,
+                    &mut self.market_map,
+                    self.swarm.behaviour_mut().file_transfer_mut(),
+                );
+            }
+            MarketBehaviourEvent::Announce(event) => self
+                .announce_handler
+                .handle_event(event, &self.blocked_peers),
+            MarketBehaviourEvent::BlockedPeers(event) => match event {},
+            MarketBehaviourEvent::AllowedPeers(event) => match event {},
+        }
+    }
+
+    /// Refuses the requests that are still queued and drops the swarm, which closes every
+    /// connection and listener, before the shutdown request is answered.
+    fn shut_down(mut self) {
+        self.request_receiver.close();
+        while let Ok((_, request_handler)) = self.request_receiver.try_recv() {
+            request_handler.respond(Err(PeerError::ShutDown.into()));
+        }
+        let shutdown_request = self.shutdown_request.take();
+        drop(self);
+        if let Some(request_handler) = shutdown_request {
+            request_handler.respond(Ok(ResponseData::Shutdown));
         }
     }
 
@@ -111,6 +302,47 @@ impl Coordinator {
         }
     }
 
+    fn handle_expiry_sweep(&mut self) {
+        for file_hash in self.market_map.evict_expired() {
+            info!("Listing {file_hash} expired, no longer providing it");
+            let behaviour = self.swarm.behaviour_mut();
+            behaviour
+                .kademlia_mut()
+                .kad_mut()
+                .stop_providing(&file_hash.0.clone().into());
+            self.announce_handler.announce(
+                behaviour.announce_mut(),
+                Announcement::Withdrawn { file_hash },
+            );
+        }
+    }
+
+    fn handle_metrics_update(&mut self) {
+        let Some(metrics) = &self.metrics else {
+            return;
+        };
+        let connections = self
+            .swarm
+            .network_info()
+            .connection_counters()
+            .num_established();
+        let kad = self.swarm.behaviour_mut().kademlia_mut().kad_mut();
+        let routing_table_size = kad.kbuckets().map(|bucket| bucket.num_entries()).sum();
+        let pending_queries = kad.iter_queries().count();
+        metrics.set_gauges(connections as usize, routing_table_size, pending_queries);
+        metrics.set_inbound(
+            InboundProtocol::FileReqRes,
+            self.file_req_res_handler.inbound_metrics(),
+        );
+        metrics.set_inbound(
+            InboundProtocol::Kademlia,
+            self.kad_handler.inbound_metrics(),
+        );
+        metrics.set_republish_failures(self.kad_handler.republish_failures());
+        metrics.set_rejected_providers(self.kad_handler.rejected_providers());
+        metrics.set_request_queue_depth(self.request_queue_metrics().depth);
+    }
+
     fn handle_request(&mut self, request_data: RequestData, request_handler: RequestHandler) {
         match request_data {
             RequestData::GetAllListeners => {
@@ -125,12 +357,48 @@ impl Coordinator {
                 let is_connected = self.swarm.is_connected(&peer_id);
                 request_handler.respond(Ok(ResponseData::IsConnectedTo { is_connected }));
             }
-            RequestData::KadRequest(request) => self.kad_handler.handle_kad_request(
-                self.swarm.behaviour_mut().kademlia_mut(),
-                request_handler,
-                request,
-                &mut self.market_map,
-            ),
+            RequestData::KadRequest(request) => {
+                let registered = match &request {
+                    KadRequestData::RegisterFile { file_metadata, .. } => {
+                        self.warn_if_unroutable(&file_metadata.supplier_info.addr);
+                        let file_hash = file_metadata.file_hash.clone();
+                        let previous = self.market_map.get_if_not_expired(&file_hash);
+                        Some((file_hash, previous))
+                    }
+                    _ => None,
+                };
+                self.kad_handler.handle_kad_request(
+                    self.swarm.behaviour_mut().kademlia_mut(),
+                    request_handler,
+                    request,
+                    &mut self.market_map,
+                );
+                // NOTE: the listing is only kept if the provider record could be stored locally, and
+                // registering a listed file again only changes its supplier info
+                if let Some((file_hash, previous)) = registered {
+                    let announcement =
+                        match (previous, self.market_map.get_if_not_expired(&file_hash)) {
+                            (None, Some(supplier_info)) => Some(Announcement::NewListing {
+                                file_hash,
+                                supplier_info,
+                            }),
+                            (Some(old), Some(supplier_info))
+                                if old.price != supplier_info.price =>
+                            {
+                                Some(Announcement::PriceChanged {
+                                    file_hash,
+                                    old_price: old.price,
+                                    supplier_info,
+                                })
+                            }
+                            _ => None,
+                        };
+                    if let Some(announcement) = announcement {
+                        self.announce_handler
+                            .announce(self.swarm.behaviour_mut().announce_mut(), announcement);
+                    }
+                }
+            }
             RequestData::ReqResRequest(request) => {
                 self.file_req_res_handler.handle_request(
                     request,
@@ -138,15 +406,152 @@ impl Coordinator {
                     self.swarm.behaviour_mut().file_req_res_mut(),
                 );
             }
+            RequestData::FileTransferRequest(request) => {
+                self.file_transfer_handler.handle_request(
+                    request,
+                    request_handler,
+                    self.swarm.behaviour_mut().file_transfer_mut(),
+                );
+            }
+            RequestData::BlockPeer(peer_id) => {
+                let behaviour = self.swarm.behaviour_mut();
+                behaviour.blocked_peers_mut().block_peer(peer_id);
+                behaviour.kademlia_mut().kad_mut().remove_peer(&peer_id);
+                self.blocked_peers.insert(peer_id);
+                request_handler.respond(Ok(self.blocked_peers_response()));
+            }
+            RequestData::UnblockPeer(peer_id) => {
+                self.swarm
+                    .behaviour_mut()
+                    .blocked_peers_mut()
+                    .unblock_peer(peer_id);
+                self.blocked_peers.remove(&peer_id);
+                request_handler.respond(Ok(self.blocked_peers_response()));
+            }
+            RequestData::GetBlockedPeers => {
+                request_handler.respond(Ok(self.blocked_peers_response()));
+            }
+            RequestData::GetMetrics => {
+                request_handler.respond(Ok(ResponseData::Metrics {
+                    metrics: Metrics {
+                        file_req_res: self.file_req_res_handler.inbound_metrics(),
+                        kademlia: self.kad_handler.inbound_metrics(),
+                        request_queue: self.request_queue_metrics(),
+                    },
+                }));
+            }
+            RequestData::GetEvictions => {
+                request_handler.respond(Ok(ResponseData::Evictions {
+                    evictions: self.market_map.evictions(),
+                }));
+            }
+            RequestData::DetectSupplierAddr => {
+                // confirmed external addresses and the IPs that enough peers observed this node
+                // at come first, the listeners are only a fallback for nodes that nobody has seen
+                let observed_addrs = self.identify_handler.observed_addrs();
+                let addr = select_supplier_ip(
+                    self.swarm
+                        .external_addresses()
+                        .chain(&observed_addrs)
+                        .chain(self.swarm.listeners()),
+                )
+                .map(SupplierAddr::Ip);
+                request_handler.respond(Ok(ResponseData::DetectSupplierAddr { addr }));
+            }
+            RequestData::Shutdown => {
+                self.shutdown_request = Some(request_handler);
+            }
+            RequestData::GetListingStats => {
+                request_handler.respond(Ok(ResponseData::ListingStats {
+                    stats: self.market_map.listing_stats(),
+                }));
+            }
+            RequestData::GetLocalListings => {
+                request_handler.respond(Ok(ResponseData::LocalListings {
+                    listings: self.market_map.listings(),
+                }));
+            }
             RequestData::GetLocalSupplierInfo { file_hash } => {
                 request_handler.respond(Ok(ResponseData::GetLocalSupplierInfo {
                     supplier_info: self.market_map.get_if_not_expired(&file_hash),
                 }));
             }
+            RequestData::UpdateListing { file_hash, update } => {
+                let response = self.market_map.update(&file_hash, update);
+                if let Ok((old, new)) = &response {
+                    if old.price != new.price {
+                        self.announce_handler.announce(
+                            self.swarm.behaviour_mut().announce_mut(),
+                            Announcement::PriceChanged {
+                                file_hash,
+                                old_price: old.price,
+                                supplier_info: new.clone(),
+                            },
+                        );
+                    }
+                }
+                request_handler.respond(
+                    response
+                        .map(|(old, new)| ResponseData::UpdateListing { old, new })
+                        .map_err(Into::into),
+                );
+            }
+        }
+    }
+
+    fn request_queue_metrics(&self) -> RequestQueueMetrics {
+        RequestQueueMetrics {
+            depth: self
+                .request_sender
+                .upgrade()
+                .map_or(0, |sender| sender.max_capacity() - sender.capacity()),
+            capacity: self.request_queue_capacity,
+        }
+    }
+
+    fn blocked_peers_response(&self) -> ResponseData {
+        ResponseData::BlockedPeers {
+            blocked_peers: self.blocked_peers.iter().copied().collect(),
+        }
+    }
+
+    fn warn_if_unroutable(&self, addr: &SupplierAddr) {
+        let Some(ip) = addr.ip() else {
+            return;
+        };
+        match IpScope::of(&ip) {
+            IpScope::Loopback => {
+                warn!("Supplier address {addr} is a loopback address, other peers cannot reach it");
+            }
+            IpScope::Private
+                if self
+                    .swarm
+                    .external_addresses()
+                    .filter_map(multiaddr_ip)
+                    .any(|ip| IpScope::of(&ip) == IpScope::Public) =>
+            {
+                warn!("Supplier address {addr} is a private address but this node is on a public network, peers outside of the local network cannot reach it");
+            }
+            _ => {}
         }
     }
 
-    async fn handle_swarm_event(&mut self, event: SwarmEvent<MarketBehaviourEvent<MemoryStore>>) {
+    async fn handle_swarm_event(&mut self, event: SwarmEvent<MarketBehaviourEvent<LookupStore>>) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record(&event);
+            match &event {
+                SwarmEvent::Behaviour(MarketBehaviourEvent::Kademlia(KadEvent::Kad(event))) => {
+                    metrics.record(event)
+                }
+                SwarmEvent::Behaviour(MarketBehaviourEvent::Identify(IdentifyEvent::Identify(
+                    event,
+                ))) => metrics.record(event),
+                SwarmEvent::Behaviour(MarketBehaviourEvent::Announce(
+                    AnnounceEvent::Gossipsub(event),
+                )) => metrics.record(event),
+                _ => {}
+            }
+        }
         match event {
             SwarmEvent::Behaviour(event) => {
                 self.handle_event(event);
@@ -154,10 +559,16 @@ impl Coordinator {
             SwarmEvent::ConnectionEstablished {
                 peer_id,
                 connection_id,
+                endpoint,
                 num_established,
                 established_in,
                 ..
             } => {
+                self.kad_handler.sybil_guard_mut().connection_established(
+                    peer_id,
+                    endpoint.get_remote_address(),
+                    Instant::now(),
+                );
                 info!("[ConnId {connection_id}] - Connection established with peer: {peer_id}. Number of established connections: {num_established}. Established in: {established_in:?}");
             }
             SwarmEvent::ConnectionClosed {
@@ -178,6 +589,10 @@ impl Coordinator {
                 // TODO: something we need to focus on when we allow user to use more listening
                 // addresses maybe?
                 if num_established == 0 {
+                    self.kad_handler
+                        .sybil_guard_mut()
+                        .connection_closed(&peer_id);
+                    self.identify_handler.connection_closed(&peer_id);
                     self.swarm
                         .behaviour_mut()
                         .kademlia_mut()
@@ -242,10 +657,12 @@ impl Coordinator {
                 warn!("[ConnId {connection_id}] - Dialing peer: {:?}", peer_id);
             }
             SwarmEvent::NewExternalAddrCandidate { address } => {
-                // TODO: this will be useful when we deal with NAT remotely since upnp emits a
-                // SwarmEvent::ExternalAddressConfirmed event in which we will use to actually add
-                // the address in I think
-                self.swarm.add_external_address(address);
+                // NOTE: candidates are whatever a single peer claims to see, so they are only
+                // counted towards the observed addresses, see `IdentifyHandler::observed_addrs`
+                info!("New external address candidate: {address}");
+            }
+            SwarmEvent::ExternalAddrConfirmed { address } => {
+                info!("External address confirmed: {address}");
             }
             SwarmEvent::ExternalAddrExpired { address } => {
                 self.swarm.remove_external_address(&address);
@@ -257,36 +674,355 @@ impl Coordinator {
     }
 }
 
+/// Picks the most reachable IP out of `addrs`, preferring the earliest one among equals.
+fn select_supplier_ip<'a>(addrs: impl Iterator<Item = &'a Multiaddr>) -> Option<IpAddr> {
+    addrs
+        .filter_map(multiaddr_ip)
+        .filter(|ip| !ip.is_unspecified())
+        .min_by_key(|ip| Reverse(IpScope::of(ip)))
+}
+
+/// A file that this node supplies. The listing stays alive for as long as Kademlia keeps
+/// republishing its provider record: remote nodes drop the record [`PROVIDER_RECORD_TTL`] after
+/// the last successful publish and so does the local listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct LocalListing {
+    pub(crate) supplier_info: SupplierInfo,
+    pub(crate) registered_at: SystemTime,
+    pub(crate) published_at: SystemTime,
+    // NOTE: not persisted since it only describes what happened in this session
+    pub(crate) last_publish: PublishStatus,
+    /// What the listing can be searched for by, see [`crate::peer::Peer::index_file`].
+    pub(crate) keywords: Vec<String>,
+    /// Where the file is served from if it was registered by path, see
+    /// [`crate::peer::Peer::register_path`]. Goes away along with the listing.
+    pub(crate) served_path: Option<PathBuf>,
+}
+
+impl LocalListing {
+    pub(crate) fn new(supplier_info: SupplierInfo) -> Self {
+        let now = SystemTime::now();
+        Self {
+            supplier_info,
+            registered_at: now,
+            published_at: now,
+            last_publish: PublishStatus::Pending,
+            keywords: Vec::new(),
+            served_path: None,
+        }
+    }
+
+    pub(crate) fn expires_at(&self) -> SystemTime {
+        self.published_at + PROVIDER_RECORD_TTL
+    }
+
+    fn is_expired(&self) -> bool {
+        SystemTime::now() >= self.expires_at()
+    }
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct LocalMarketMap {
-    inner: HashMap<FileHash, (SupplierInfo, CreationTime)>,
+    inner: HashMap<FileHash, LocalListing>,
+    registry: Option<Registry>,
+    // set whenever the listings change, cleared once they are handed to a write
+    unsaved: bool,
+    // NOTE: one write at a time so that an older snapshot never replaces a newer one
+    pending_write: Option<JoinHandle<()>>,
+    max_listings: Option<usize>,
+    evictions: VecDeque<Eviction>,
+    stats: RequestStats,
 }
 
 impl LocalMarketMap {
+    /// If a `registry` is given, the listings that have not expired yet are restored from it and
+    /// it is kept up to date from then on.
+    pub(crate) fn new(
+        registry: Option<Registry>,
+        max_listings: Option<usize>,
+    ) -> Result<Self, RegistryError> {
+        let inner = match &registry {
+            Some(registry) => registry
+                .load()?
+                .into_iter()
+                .filter(|(_, listing)| !listing.is_expired())
+                .collect(),
+            None => HashMap::new(),
+        };
+        Ok(Self {
+            inner,
+            registry,
+            unsaved: false,
+            pending_write: None,
+            max_listings,
+            evictions: VecDeque::new(),
+            stats: RequestStats::default(),
+        })
+    }
+
+    /// Fails if registering `file_hash` would go over the maximum number of listings. Updating an
+    /// existing listing is always allowed.
+    pub(crate) fn check_capacity(&self, file_hash: &FileHash) -> Result<(), ListingError> {
+        match self.max_listings {
+            Some(max_listings)
+                if self.inner.len() >= max_listings && !self.inner.contains_key(file_hash) =>
+            {
+                Err(ListingError::ListingLimitReached { max_listings })
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub(crate) fn remove(&mut self, file_hash: &FileHash) {
-        self.inner.remove(file_hash);
+        if self.inner.remove(file_hash).is_some() {
+            self.stats.remove_file(file_hash);
+            self.persist();
+        }
     }
 
-    pub(crate) fn insert(&mut self, file_hash: FileHash, supplier_info: SupplierInfo) {
+    /// Lists `file_hash`, or updates the supplier info of a listing that has not expired yet. An
+    /// existing listing keeps its keywords, when it was registered and where it is served from,
+    /// unless `served_path` names a new path. Returns whether the listing is new.
+    pub(crate) fn insert(
+        &mut self,
+        file_hash: FileHash,
+        supplier_info: SupplierInfo,
+        served_path: Option<PathBuf>,
+    ) -> bool {
+        let is_new = match self
+            .inner
+            .get_mut(&file_hash)
+            .filter(|listing| !listing.is_expired())
+        {
+            Some(listing) => {
+                listing.supplier_info = supplier_info;
+                listing.served_path = served_path.or(listing.served_path.take());
+                false
+            }
+            None => {
+                let listing = LocalListing {
+                    served_path,
+                    ..LocalListing::new(supplier_info)
+                };
+                self.inner.insert(file_hash, listing);
+                true
+            }
+        };
+        self.persist();
+        is_new
+    }
+
+    /// Called whenever the provider record of `file_hash` was successfully republished, which
+    /// extends the listing by another [`PROVIDER_RECORD_TTL`].
+    pub(crate) fn refresh(&mut self, file_hash: &FileHash) {
+        if let Some(listing) = self.inner.get_mut(file_hash) {
+            let now = SystemTime::now();
+            listing.published_at = now;
+            listing.last_publish = PublishStatus::Succeeded { at: now };
+            self.persist();
+        }
+    }
+
+    /// Records a failed republication of `file_hash`. The listing itself is left alone since it
+    /// stays valid until it expires.
+    pub(crate) fn publish_failed(&mut self, file_hash: &FileHash, error: String) {
+        if let Some(listing) = self.inner.get_mut(file_hash) {
+            listing.last_publish = PublishStatus::Failed {
+                at: SystemTime::now(),
+                error,
+            };
+        }
+    }
+
+    /// Every listing that has not expired yet, oldest registration first.
+    pub(crate) fn listings(&self) -> Vec<ListingInfo> {
+        let mut listings = self
+            .inner
+            .iter()
+            .filter(|(_, listing)| !listing.is_expired())
+            .map(|(file_hash, listing)| ListingInfo {
+                file_hash: file_hash.clone(),
+                supplier_info: listing.supplier_info.clone(),
+                registered_at: listing.registered_at,
+                expires_at: listing.expires_at(),
+                last_publish: listing.last_publish.clone(),
+            })
+            .collect::<Vec<_>>();
+        listings.sort_by_key(|listing| listing.registered_at);
+        listings
+    }
+
+    /// Applies `update` to a listing that has not expired and returns its supplier info from
+    /// before and after the update. The provider record does not carry the supplier info, so
+    /// nothing has to be republished.
+    pub(crate) fn update(
+        &mut self,
+        file_hash: &FileHash,
+        update: ListingUpdate,
+    ) -> Result<(SupplierInfo, SupplierInfo), ListingError> {
+        let listing = self
+            .inner
+            .get_mut(file_hash)
+            .filter(|listing| !listing.is_expired())
+            .ok_or_else(|| ListingError::NotListed {
+                file_hash: file_hash.clone(),
+            })?;
+        let old = listing.supplier_info.clone();
+        update.apply(&mut listing.supplier_info);
+        let new = listing.supplier_info.clone();
+        self.persist();
+        Ok((old, new))
+    }
+
+    /// Sets the keywords of a listing that has not expired and returns when the listing expires.
+    pub(crate) fn set_keywords(
+        &mut self,
+        file_hash: &FileHash,
+        keywords: Vec<String>,
+    ) -> Result<SystemTime, ListingError> {
+        let listing = self
+            .inner
+            .get_mut(file_hash)
+            .filter(|listing| !listing.is_expired())
+            .ok_or_else(|| ListingError::NotListed {
+                file_hash: file_hash.clone(),
+            })?;
+        listing.keywords = keywords;
+        let expires_at = listing.expires_at();
+        self.persist();
+        Ok(expires_at)
+    }
+
+    /// Serves a listing that has not expired from `path` instead of the file transfer directory.
+    pub(crate) fn served_path(&self, file_hash: &FileHash) -> Option<&Path> {
+        self.inner.get(file_hash)?.served_path.as_deref()
+    }
+
+    /// The keywords of a listing along with when it expires.
+    pub(crate) fn keywords(&self, file_hash: &FileHash) -> Option<(&[String], SystemTime)> {
         self.inner
-            .insert(file_hash, (supplier_info, CreationTime::now()));
+            .get(file_hash)
+            .map(|listing| (listing.keywords.as_slice(), listing.expires_at()))
     }
 
-    pub(crate) fn get_if_not_expired(&mut self, file_hash: &FileHash) -> Option<SupplierInfo> {
-        if let Some((supplier_info, creation_time)) = self.inner.get(file_hash) {
-            let elapsed_time = Instant::now().duration_since(*creation_time);
-            if elapsed_time >= PROVIDER_RECORD_TTL {
-                self.inner.remove(file_hash);
+    pub(crate) fn file_hashes(&self) -> impl Iterator<Item = &FileHash> {
+        self.inner.keys()
+    }
+
+    /// Expired listings are left in place for [`LocalMarketMap::evict_expired`] to clean up since
+    /// it also stops providing them.
+    pub(crate) fn get_if_not_expired(&self, file_hash: &FileHash) -> Option<SupplierInfo> {
+        if let Some(listing) = self.inner.get(file_hash) {
+            if listing.is_expired() {
                 None
             } else {
                 // NOTE: okay to clone here since we just clone all the time
                 // but will prob refactor to not clone later
-                Some(supplier_info.clone())
+                Some(listing.supplier_info.clone())
             }
         } else {
             None
         }
     }
+
+    /// Removes every expired listing and returns their file hashes.
+    pub(crate) fn evict_expired(&mut self) -> Vec<FileHash> {
+        let expired = self
+            .inner
+            .iter()
+            .filter(|(_, listing)| listing.is_expired())
+            .map(|(file_hash, _)| file_hash.clone())
+            .collect::<Vec<_>>();
+        if expired.is_empty() {
+            return expired;
+        }
+        for file_hash in &expired {
+            self.inner.remove(file_hash);
+            self.stats.remove_file(file_hash);
+            if self.evictions.len() == MAX_EVICTION_HISTORY {
+                self.evictions.pop_front();
+            }
+            self.evictions.push_back(Eviction {
+                file_hash: file_hash.clone(),
+                reason: EvictionReason::Expired,
+                evicted_at: SystemTime::now(),
+            });
+        }
+        self.persist();
+        expired
+    }
+
+    /// Counts an inbound request for `file_hash` if it is listed by this node.
+    pub(crate) fn record_request(
+        &mut self,
+        kind: RequestKind,
+        file_hash: &FileHash,
+        peer: Option<PeerId>,
+    ) {
+        if self.get_if_not_expired(file_hash).is_some() {
+            self.stats.record(kind, file_hash, peer);
+        }
+    }
+
+    pub(crate) fn listing_stats(&self) -> ListingStats {
+        self.stats.stats(
+            self.inner
+                .iter()
+                .filter(|(_, listing)| !listing.is_expired())
+                .map(|(file_hash, _)| file_hash),
+        )
+    }
+
+    pub(crate) fn evictions(&self) -> Vec<Eviction> {
+        self.evictions.iter().cloned().collect()
+    }
+
+    /// Marks the listings to be written to the registry on the next
+    /// [`LocalMarketMap::flush`].
+    const fn persist(&mut self) {
+        if self.registry.is_some() {
+            self.unsaved = true;
+        }
+    }
+
+    pub(crate) const fn has_unsaved_changes(&self) -> bool {
+        self.unsaved
+    }
+
+    /// Writes the listings to the registry on the blocking pool. If the previous write is still
+    /// going, the listings stay unsaved until a later flush.
+    pub(crate) fn flush(&mut self) {
+        let Some(registry) = &self.registry else {
+            return;
+        };
+        if self
+            .pending_write
+            .as_ref()
+            .is_some_and(|write| !write.is_finished())
+        {
+            return;
+        }
+        let snapshot = registry.snapshot(&self.inner);
+        self.unsaved = false;
+        self.pending_write = Some(task::spawn_blocking(move || {
+            if let Err(err) = snapshot.write() {
+                error!("Failed to persist the local market listings: {err}");
+            }
+        }));
+    }
+
+    /// Waits for the write in flight and saves whatever is still unsaved, for shutting down.
+    pub(crate) async fn close(&mut self) {
+        if let Some(write) = self.pending_write.take() {
+            let _ = write.await;
+        }
+        if self.unsaved {
+            self.flush();
+            if let Some(write) = self.pending_write.take() {
+                let _ = write.await;
+            }
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -295,4 +1031,219 @@ pub(crate) enum CoordinatorError {
     SpawnError(String),
 }
 
-pub(crate) type CreationTime = Instant;
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use pretty_assertions::assert_eq;
+
+    use libp2p::Multiaddr;
+
+    use super::{select_supplier_ip, LocalListing, LocalMarketMap};
+    use crate::{
+        behaviour::file_req_res::SupplierInfo,
+        file_hash::FileHash,
+        net::PROVIDER_RECORD_TTL,
+        registry::Registry,
+        req_res::{EvictionReason, ListingError, ListingUpdate, PublishStatus},
+    };
+
+    fn supplier_info() -> SupplierInfo {
+        SupplierInfo {
+            addr: [127, 0, 0, 1].into(),
+            port: 8080,
+            price: 10,
+            username: "supplier".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_capacity_limit() {
+        let mut market_map = LocalMarketMap::new(None, Some(1)).unwrap();
+        let first = FileHash::from_digest([1u8; 32]).unwrap();
+        let second = FileHash::from_digest([2u8; 32]).unwrap();
+        market_map.check_capacity(&first).unwrap();
+        market_map.insert(first.clone(), supplier_info(), None);
+        // updating an existing listing does not count against the limit
+        market_map.check_capacity(&first).unwrap();
+        assert!(market_map.check_capacity(&second).is_err());
+    }
+
+    #[test]
+    fn test_evict_expired() {
+        let mut market_map = LocalMarketMap::new(None, None).unwrap();
+        let expired = FileHash::from_digest([1u8; 32]).unwrap();
+        let alive = FileHash::from_digest([2u8; 32]).unwrap();
+        let mut listing = LocalListing::new(supplier_info());
+        listing.published_at = SystemTime::now() - PROVIDER_RECORD_TTL - Duration::from_secs(1);
+        market_map.inner.insert(expired.clone(), listing);
+        market_map.insert(alive.clone(), supplier_info(), None);
+        assert_eq!(market_map.get_if_not_expired(&expired), None);
+
+        assert_eq!(market_map.evict_expired(), vec![expired.clone()]);
+        assert!(market_map.get_if_not_expired(&alive).is_some());
+        let evictions = market_map.evictions();
+        assert_eq!(evictions.len(), 1);
+        assert_eq!(evictions[0].file_hash, expired);
+        assert_eq!(evictions[0].reason, EvictionReason::Expired);
+        assert!(market_map.evict_expired().is_empty());
+    }
+
+    #[test]
+    fn test_refresh_keeps_listing_alive() {
+        let mut market_map = LocalMarketMap::new(None, None).unwrap();
+        let file_hash = FileHash::from_digest([1u8; 32]).unwrap();
+        let mut listing = LocalListing::new(supplier_info());
+        listing.registered_at = SystemTime::now() - PROVIDER_RECORD_TTL - Duration::from_secs(1);
+        listing.published_at = listing.registered_at;
+        market_map.inner.insert(file_hash.clone(), listing);
+        assert_eq!(market_map.get_if_not_expired(&file_hash), None);
+
+        market_map.refresh(&file_hash);
+        assert_eq!(
+            market_map.get_if_not_expired(&file_hash),
+            Some(supplier_info())
+        );
+        assert!(market_map.evict_expired().is_empty());
+    }
+
+    #[test]
+    fn test_partial_update() {
+        let mut market_map = LocalMarketMap::new(None, None).unwrap();
+        let file_hash = FileHash::from_digest([1u8; 32]).unwrap();
+        let update = ListingUpdate {
+            price: Some(20),
+            port: Some(9090),
+            ..Default::default()
+        };
+        assert_eq!(
+            market_map.update(&file_hash, update.clone()).unwrap_err(),
+            ListingError::NotListed {
+                file_hash: file_hash.clone()
+            }
+        );
+
+        market_map.insert(file_hash.clone(), supplier_info(), None);
+        let (old, new) = market_map.update(&file_hash, update).unwrap();
+        assert_eq!(old, supplier_info());
+        assert_eq!(
+            new,
+            SupplierInfo {
+                port: 9090,
+                price: 20,
+                ..supplier_info()
+            }
+        );
+        assert_eq!(market_map.get_if_not_expired(&file_hash), Some(new));
+    }
+
+    #[test]
+    fn test_reregistering_keeps_the_listing() {
+        let mut market_map = LocalMarketMap::new(None, None).unwrap();
+        let file_hash = FileHash::from_digest([1u8; 32]).unwrap();
+        assert!(market_map.insert(
+            file_hash.clone(),
+            supplier_info(),
+            Some("/srv/market/notes.txt".into())
+        ));
+        market_map
+            .set_keywords(&file_hash, vec!["notes".to_owned()])
+            .unwrap();
+        let registered_at = market_map.inner[&file_hash].registered_at;
+
+        let supplier_info = SupplierInfo {
+            price: 20,
+            ..supplier_info()
+        };
+        assert!(!market_map.insert(file_hash.clone(), supplier_info.clone(), None));
+        let listing = &market_map.inner[&file_hash];
+        assert_eq!(listing.supplier_info, supplier_info);
+        assert_eq!(listing.keywords, vec!["notes".to_owned()]);
+        assert_eq!(listing.registered_at, registered_at);
+        assert_eq!(
+            market_map.served_path(&file_hash),
+            Some("/srv/market/notes.txt".as_ref())
+        );
+    }
+
+    #[test]
+    fn test_listings_report_publish_status() {
+        let mut market_map = LocalMarketMap::new(None, None).unwrap();
+        let first = FileHash::from_digest([1u8; 32]).unwrap();
+        let second = FileHash::from_digest([2u8; 32]).unwrap();
+        let mut listing = LocalListing::new(supplier_info());
+        listing.registered_at -= Duration::from_secs(60);
+        market_map.inner.insert(first.clone(), listing);
+        market_map.insert(second.clone(), supplier_info(), None);
+        market_map.refresh(&first);
+        market_map.publish_failed(&second, "timed out".to_owned());
+
+        let listings = market_map.listings();
+        assert_eq!(
+            listings
+                .iter()
+                .map(|listing| listing.file_hash.clone())
+                .collect::<Vec<_>>(),
+            vec![first, second]
+        );
+        assert!(matches!(
+            listings[0].last_publish,
+            PublishStatus::Succeeded { .. }
+        ));
+        assert_eq!(
+            listings[0].expires_at,
+            market_map.inner[&listings[0].file_hash].published_at + PROVIDER_RECORD_TTL
+        );
+        assert!(
+            matches!(&listings[1].last_publish, PublishStatus::Failed { error, .. } if error == "timed out")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_registry_writes_are_batched() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("registry.json");
+        let mut market_map = LocalMarketMap::new(Some(Registry::new(path.clone())), None).unwrap();
+        market_map.insert(
+            FileHash::from_digest([1u8; 32]).unwrap(),
+            supplier_info(),
+            None,
+        );
+        market_map.insert(
+            FileHash::from_digest([2u8; 32]).unwrap(),
+            supplier_info(),
+            None,
+        );
+        // nothing is written until the next flush
+        assert_eq!(market_map.has_unsaved_changes(), true);
+        assert_eq!(path.exists(), false);
+
+        market_map.close().await;
+        assert_eq!(market_map.has_unsaved_changes(), false);
+        let restored = LocalMarketMap::new(Some(Registry::new(path.clone())), None).unwrap();
+        assert_eq!(restored.listings().len(), 2);
+    }
+
+    #[test]
+    fn test_select_supplier_ip() {
+        let addrs = [
+            "/ip4/0.0.0.0/tcp/4444",
+            "/ip4/127.0.0.1/tcp/4444",
+            "/ip4/192.168.1.10/tcp/4444",
+            "/ip4/10.0.0.1/tcp/4444",
+        ]
+        .map(|addr| addr.parse::<Multiaddr>().unwrap());
+        assert_eq!(
+            select_supplier_ip(addrs.iter()),
+            Some([192, 168, 1, 10].into())
+        );
+        let public = "/ip6/2606:4700::1111/udp/4444/quic-v1"
+            .parse::<Multiaddr>()
+            .unwrap();
+        assert_eq!(
+            select_supplier_ip(addrs.iter().chain([&public])),
+            Some("2606:4700::1111".parse().unwrap())
+        );
+        assert_eq!(select_supplier_ip(addrs[..1].iter()), None);
+    }
+}
@@ -0,0 +1,219 @@
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use multibase::Base;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Length of a SHA-256 digest in bytes.
+pub const DIGEST_LEN: usize = 32;
+
+const SHA2_256_CODE: u64 = 0x12;
+const RAW_CODEC: u64 = 0x55;
+const CID_VERSION: u64 = 1;
+
+/// The canonical identifier of a file in the market, which is its SHA-256 digest. Every encoding
+/// that a client may hand us (hex, base58 multihash or CIDv1) resolves to the same raw digest,
+/// which is what is used as the Kademlia key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[repr(transparent)]
+pub struct FileHash(pub(crate) Vec<u8>);
+
+impl FileHash {
+    pub fn from_digest(digest: impl Into<Vec<u8>>) -> Result<Self, FileHashError> {
+        let digest = digest.into();
+        if digest.len() != DIGEST_LEN {
+            return Err(FileHashError::InvalidLength { len: digest.len() });
+        }
+        Ok(Self(digest))
+    }
+
+    /// Parses a 64 character hex encoded SHA-256 digest.
+    pub fn from_hex(hex: &str) -> Result<Self, FileHashError> {
+        if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+            return Err(FileHashError::InvalidEncoding {
+                reason: "hex must be an even number of ascii characters".to_owned(),
+            });
+        }
+        let digest = (0..hex.len())
+            .step_by(2)
+            .map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| FileHashError::InvalidEncoding {
+                reason: err.to_string(),
+            })?;
+        Self::from_digest(digest)
+    }
+
+    /// Parses a base58btc encoded sha2-256 multihash, e.g. `Qm...` (also known as a CIDv0).
+    pub fn from_multihash(multihash: &str) -> Result<Self, FileHashError> {
+        let bytes =
+            Base::Base58Btc
+                .decode(multihash)
+                .map_err(|err| FileHashError::InvalidEncoding {
+                    reason: err.to_string(),
+                })?;
+        Self::from_multihash_bytes(&bytes)
+    }
+
+    /// Parses a multibase encoded CIDv1 whose multihash is sha2-256. The content codec is not
+    /// part of the canonical hash and is ignored.
+    pub fn from_cid(cid: &str) -> Result<Self, FileHashError> {
+        let (_, bytes) = multibase::decode(cid).map_err(|err| FileHashError::InvalidEncoding {
+            reason: err.to_string(),
+        })?;
+        let (version, rest) = read_varint(&bytes)?;
+        if version != CID_VERSION {
+            return Err(FileHashError::InvalidEncoding {
+                reason: format!("unsupported CID version {version}"),
+            });
+        }
+        let (_codec, rest) = read_varint(rest)?;
+        Self::from_multihash_bytes(rest)
+    }
+
+    pub fn digest(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    pub fn to_multihash(&self) -> String {
+        Base::Base58Btc.encode(self.multihash_bytes())
+    }
+
+    /// Formats the hash as a base32 CIDv1 with the raw codec.
+    pub fn to_cid(&self) -> String {
+        let mut bytes = vec![CID_VERSION as u8, RAW_CODEC as u8];
+        bytes.extend(self.multihash_bytes());
+        multibase::encode(Base::Base32Lower, bytes)
+    }
+
+    fn multihash_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(DIGEST_LEN + 2);
+        bytes.push(SHA2_256_CODE as u8);
+        bytes.push(DIGEST_LEN as u8);
+        bytes.extend_from_slice(&self.0);
+        bytes
+    }
+
+    fn from_multihash_bytes(bytes: &[u8]) -> Result<Self, FileHashError> {
+        let (code, rest) = read_varint(bytes)?;
+        if code != SHA2_256_CODE {
+            return Err(FileHashError::UnsupportedHash { code });
+        }
+        let (len, digest) = read_varint(rest)?;
+        if len as usize != digest.len() {
+            return Err(FileHashError::InvalidLength { len: digest.len() });
+        }
+        Self::from_digest(digest)
+    }
+}
+
+impl FromStr for FileHash {
+    type Err = FileHashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() == DIGEST_LEN * 2 {
+            Self::from_hex(s)
+        } else if s.starts_with("Qm") {
+            Self::from_multihash(s)
+        } else {
+            Self::from_cid(s)
+        }
+    }
+}
+
+impl Display for FileHash {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+impl TryFrom<Vec<u8>> for FileHash {
+    type Error = FileHashError;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        Self::from_digest(value)
+    }
+}
+
+impl AsRef<[u8]> for FileHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+fn read_varint(bytes: &[u8]) -> Result<(u64, &[u8]), FileHashError> {
+    let mut value = 0u64;
+    for (idx, byte) in bytes.iter().enumerate().take(9) {
+        value |= u64::from(byte & 0x7f) << (7 * idx);
+        if byte & 0x80 == 0 {
+            return Ok((value, &bytes[idx + 1..]));
+        }
+    }
+    Err(FileHashError::InvalidEncoding {
+        reason: "truncated or overlong varint".to_owned(),
+    })
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq, Hash)]
+pub enum FileHashError {
+    #[error("Expected a {DIGEST_LEN} byte SHA-256 digest but got {len} bytes")]
+    InvalidLength { len: usize },
+    #[error("Failed to decode file hash: {reason}")]
+    InvalidEncoding { reason: String },
+    #[error("Unsupported multihash code {code:#x}, only sha2-256 is supported")]
+    UnsupportedHash { code: u64 },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileHash, FileHashError};
+    use pretty_assertions::assert_eq;
+
+    // sha256("hello world")
+    const HEX: &str = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+    #[test]
+    fn test_hex_round_trip() {
+        let hash: FileHash = HEX.parse().unwrap();
+        assert_eq!(hash.to_hex(), HEX);
+        assert_eq!(hash.to_string(), HEX);
+        assert_eq!(FileHash::from_hex(&HEX.to_uppercase()).unwrap(), hash);
+    }
+
+    #[test]
+    fn test_encodings_resolve_to_same_key() {
+        let hash = FileHash::from_hex(HEX).unwrap();
+        let multihash = hash.to_multihash();
+        let cid = hash.to_cid();
+        assert!(multihash.starts_with("Qm"));
+        assert!(cid.starts_with("bafkrei"));
+        assert_eq!(multihash.parse::<FileHash>().unwrap(), hash);
+        assert_eq!(cid.parse::<FileHash>().unwrap(), hash);
+    }
+
+    #[test]
+    fn test_known_cid() {
+        let hash = FileHash::from_hex(HEX).unwrap();
+        assert_eq!(
+            hash.to_cid(),
+            "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e"
+        );
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        assert_eq!(
+            FileHash::from_hex("ab12"),
+            Err(FileHashError::InvalidLength { len: 2 })
+        );
+        assert!("sample_hash".parse::<FileHash>().is_err());
+        assert!(FileHash::from_digest(vec![0; 20]).is_err());
+    }
+}
@@ -8,16 +8,40 @@
 )]
 #![deny(unsafe_code, unreachable_pub)]
 
+pub use behaviour::announce::{Announcement, MarketAnnouncement, MarketSubscription, MarketTopic};
+pub use behaviour::file_req_res::SupplierInfo;
+pub use libp2p::identity::Keypair;
+pub use libp2p::kad::Quorum;
 pub use libp2p::multiaddr::{multiaddr, Protocol};
+pub use libp2p::pnet::PreSharedKey;
 pub use libp2p::Multiaddr;
 pub use libp2p::PeerId;
-pub use req_res::{FileReqResResponseData, KadResponseData, ResponseData};
+pub use rate_limit::{InboundMetrics, InboundRejection};
+pub use reputation::Reputation;
+pub use req_res::{
+    DhtRecord, Eviction, EvictionReason, FileReqResResponseData, FileTransferResponseData,
+    KadResponseData, ListingError, ListingInfo, ListingUpdate, Metrics, PublishStatus,
+    RequestQueueMetrics, ResponseData,
+};
+pub use stats::{FileStats, ListingStats, PeerStats, RequestCounts, WindowedCounts};
 
+pub mod address;
 pub mod boot_nodes;
 pub mod config;
+pub mod file_hash;
+pub mod metrics;
 pub mod net;
 pub mod peer;
+pub mod sybil;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 mod behaviour;
 mod coordinator;
+mod memory_links;
+mod rate_limit;
+mod registry;
+mod reputation;
 mod req_res;
+mod search;
+mod stats;
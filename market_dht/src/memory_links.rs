@@ -0,0 +1,169 @@
+//! The links between nodes on the in-memory transport, which can be cut like a failing network
+//! would cut them, see [`crate::testing::TestNetwork::fail_link`].
+//!
+//! Nodes are told apart by the port of their `/memory/<port>` listener. A cut link refuses the
+//! dials between the two nodes and drops the connections they already had. Only the dialing side
+//! knows which node it is connected to, so only that side watches the link; the other side sees
+//! the connection close once the dialer drops it.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    task::{Context, Poll},
+};
+
+use futures::{task::AtomicWaker, AsyncRead, AsyncWrite};
+use libp2p::{
+    core::{
+        transport::{memory::Channel, MemoryTransport},
+        ConnectedPoint,
+    },
+    multiaddr::Protocol,
+    Multiaddr, Transport,
+};
+
+/// The links between every pair of ports of the process, keyed with the lower port first.
+static LINKS: Mutex<BTreeMap<(u64, u64), Link>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Default)]
+struct Link {
+    cut: bool,
+    connections: Vec<Weak<Cut>>,
+}
+
+/// Tells a connection that its link was cut, waking it up if it is waiting for data.
+#[derive(Debug, Default)]
+struct Cut {
+    cut: AtomicBool,
+    waker: AtomicWaker,
+}
+
+const fn link_key(a: u64, b: u64) -> (u64, u64) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+/// Cuts the link between the nodes listening on ports `a` and `b` until [`restore_link`].
+#[cfg(feature = "testing")]
+pub(crate) fn cut_link(a: u64, b: u64) {
+    let mut links = LINKS.lock().unwrap();
+    let link = links.entry(link_key(a, b)).or_default();
+    link.cut = true;
+    for cut in link.connections.drain(..).filter_map(|cut| cut.upgrade()) {
+        cut.cut.store(true, Ordering::Release);
+        cut.waker.wake();
+    }
+}
+
+#[cfg(feature = "testing")]
+pub(crate) fn restore_link(a: u64, b: u64) {
+    LINKS.lock().unwrap().remove(&link_key(a, b));
+}
+
+/// Watches a new connection from `local` to `remote`, or `None` if their link is cut.
+fn connect(local: u64, remote: u64) -> Option<Arc<Cut>> {
+    let mut links = LINKS.lock().unwrap();
+    let link = links.entry(link_key(local, remote)).or_default();
+    if link.cut {
+        return None;
+    }
+    let cut = Arc::new(Cut::default());
+    link.connections.retain(|cut| cut.strong_count() > 0);
+    link.connections.push(Arc::downgrade(&cut));
+    Some(cut)
+}
+
+pub(crate) fn memory_port(addr: &Multiaddr) -> Option<u64> {
+    addr.iter().find_map(|protocol| match protocol {
+        Protocol::Memory(port) => Some(port),
+        _ => None,
+    })
+}
+
+/// The in-memory transport of the node listening on `/memory/<local_port>`. Without a port, as
+/// when listening on `/memory/0`, the links of the node cannot be cut.
+pub(crate) fn transport(
+    local_port: Option<u64>,
+) -> impl Transport<
+    Output = LinkStream,
+    Error = impl std::error::Error + Send + Sync + 'static,
+    Dial = impl Send + 'static,
+    ListenerUpgrade = impl Send + 'static,
+> + Send
+       + Unpin
+       + 'static {
+    MemoryTransport::default().and_then(move |channel, endpoint| {
+        let remote_port = match endpoint {
+            ConnectedPoint::Dialer { address, .. } => memory_port(&address),
+            ConnectedPoint::Listener { .. } => None,
+        };
+        let cut = match local_port.zip(remote_port) {
+            Some((local, remote)) => connect(local, remote)
+                .map(Some)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::ConnectionRefused, "The link is cut")),
+            None => Ok(None),
+        };
+        async move { cut.map(|cut| LinkStream { channel, cut }) }
+    })
+}
+
+/// A connection over the in-memory transport that fails once its link is cut.
+pub(crate) struct LinkStream {
+    channel: Channel<Vec<u8>>,
+    cut: Option<Arc<Cut>>,
+}
+
+impl LinkStream {
+    fn check_link(&self, cx: &Context<'_>) -> io::Result<()> {
+        let Some(cut) = &self.cut else {
+            return Ok(());
+        };
+        cut.waker.register(cx.waker());
+        if cut.cut.load(Ordering::Acquire) {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "The link was cut",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl AsyncRead for LinkStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.check_link(cx)?;
+        Pin::new(&mut self.channel).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for LinkStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.check_link(cx)?;
+        Pin::new(&mut self.channel).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.check_link(cx)?;
+        Pin::new(&mut self.channel).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.channel).poll_close(cx)
+    }
+}
@@ -0,0 +1,308 @@
+//! Prometheus metrics of a node, see [`crate::config::ConfigBuilder::with_metrics_registry`].
+//!
+//! The libp2p metrics of the swarm, Kademlia, identify and gossipsub are registered under the
+//! `libp2p` prefix and the metrics of the market itself under the `market` prefix.
+
+use std::{fmt, time::Instant};
+
+use libp2p::metrics::{Metrics as Libp2pMetrics, Recorder};
+use prometheus_client::{
+    encoding::EncodeLabelSet,
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{exponential_buckets, Histogram},
+    },
+};
+
+pub use prometheus_client::{encoding::text::encode, registry::Registry};
+
+use crate::{rate_limit::InboundMetrics, sybil::ProviderRejections};
+
+const METRICS_PREFIX: &str = "market";
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RequestLabels {
+    request: &'static str,
+    /// `ok` or `error`
+    outcome: &'static str,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RequestKindLabels {
+    request: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum InboundProtocol {
+    FileReqRes,
+    Kademlia,
+}
+
+impl InboundProtocol {
+    const fn name(&self) -> &'static str {
+        match self {
+            Self::FileReqRes => "file_req_res",
+            Self::Kademlia => "kademlia",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RejectionLabels {
+    reason: &'static str,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct InboundLabels {
+    protocol: &'static str,
+    outcome: &'static str,
+}
+
+type DurationFamily = Family<RequestKindLabels, Histogram, fn() -> Histogram>;
+
+fn duration_histogram() -> Histogram {
+    // 1ms up to about 30s
+    Histogram::new(exponential_buckets(0.001, 2.0, 16))
+}
+
+/// Everything the coordinator records. The counters share their values with the registry, so
+/// recording needs no access to it.
+pub(crate) struct NodeMetrics {
+    libp2p: Libp2pMetrics,
+    connections: Gauge,
+    routing_table_size: Gauge,
+    pending_queries: Gauge,
+    request_queue_depth: Gauge,
+    requests: Family<RequestLabels, Counter>,
+    request_duration: DurationFamily,
+    inbound_requests: Family<InboundLabels, Counter>,
+    republish_failures: Counter,
+    rejected_providers: Family<RejectionLabels, Counter>,
+}
+
+impl fmt::Debug for NodeMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NodeMetrics").finish_non_exhaustive()
+    }
+}
+
+impl NodeMetrics {
+    pub(crate) fn new(registry: &mut Registry) -> Self {
+        let libp2p = Libp2pMetrics::new(registry);
+        let registry = registry.sub_registry_with_prefix(METRICS_PREFIX);
+        let connections = Gauge::default();
+        registry.register(
+            "connections",
+            "Number of established connections",
+            connections.clone(),
+        );
+        let routing_table_size = Gauge::default();
+        registry.register(
+            "routing_table_size",
+            "Number of peers in the Kademlia routing table",
+            routing_table_size.clone(),
+        );
+        let pending_queries = Gauge::default();
+        registry.register(
+            "pending_queries",
+            "Number of Kademlia queries that are still running",
+            pending_queries.clone(),
+        );
+        let request_queue_depth = Gauge::default();
+        registry.register(
+            "request_queue_depth",
+            "Number of requests waiting for the coordinator to pick them up",
+            request_queue_depth.clone(),
+        );
+        let requests = Family::default();
+        registry.register(
+            "requests",
+            "Requests handled by the coordinator by type and outcome",
+            requests.clone(),
+        );
+        let request_duration = DurationFamily::new_with_constructor(duration_histogram);
+        registry.register(
+            "request_duration_seconds",
+            "Time from a request reaching the coordinator until it was answered",
+            request_duration.clone(),
+        );
+        let inbound_requests = Family::default();
+        registry.register(
+            "inbound_requests",
+            "Requests from remote peers by protocol and whether they were accepted",
+            inbound_requests.clone(),
+        );
+        let republish_failures = Counter::default();
+        registry.register(
+            "republish_failures",
+            "Provider records and records that could not be republished",
+            republish_failures.clone(),
+        );
+        let rejected_providers = Family::default();
+        registry.register(
+            "rejected_provider_records",
+            "Inbound provider records that were dropped for breaking a Sybil limit, by reason",
+            rejected_providers.clone(),
+        );
+        Self {
+            libp2p,
+            connections,
+            routing_table_size,
+            pending_queries,
+            request_queue_depth,
+            requests,
+            request_duration,
+            inbound_requests,
+            republish_failures,
+            rejected_providers,
+        }
+    }
+
+    pub(crate) fn record<TEvent>(&self, event: &TEvent)
+    where
+        Libp2pMetrics: Recorder<TEvent>,
+    {
+        self.libp2p.record(event)
+    }
+
+    pub(crate) fn timer(&self, request: &'static str) -> RequestTimer {
+        RequestTimer {
+            request,
+            started: Instant::now(),
+            requests: self.requests.clone(),
+            request_duration: self.request_duration.clone(),
+        }
+    }
+
+    /// Takes the current state of the node, which is kept elsewhere, over into the metrics.
+    pub(crate) fn set_gauges(
+        &self,
+        connections: usize,
+        routing_table_size: usize,
+        pending_queries: usize,
+    ) {
+        self.connections.set(connections as i64);
+        self.routing_table_size.set(routing_table_size as i64);
+        self.pending_queries.set(pending_queries as i64);
+    }
+
+    pub(crate) fn set_request_queue_depth(&self, depth: usize) {
+        self.request_queue_depth.set(depth as i64);
+    }
+
+    /// The inbound limiters count on their own since the node started, so their totals are
+    /// copied over as they are.
+    pub(crate) fn set_inbound(&self, protocol: InboundProtocol, metrics: InboundMetrics) {
+        for (outcome, count) in [
+            ("accepted", metrics.accepted),
+            ("rate_limited", metrics.rate_limited),
+            ("too_many_in_flight", metrics.too_many_in_flight),
+        ] {
+            let labels = InboundLabels {
+                protocol: protocol.name(),
+                outcome,
+            };
+            set_counter(&self.inbound_requests.get_or_create(&labels), count);
+        }
+    }
+
+    pub(crate) fn set_republish_failures(&self, count: u64) {
+        set_counter(&self.republish_failures, count)
+    }
+
+    pub(crate) fn set_rejected_providers(&self, rejections: ProviderRejections) {
+        for (reason, count) in [
+            ("insufficient_work", rejections.insufficient_work),
+            ("too_many_from_ip", rejections.too_many_from_ip),
+            ("too_many_from_subnet", rejections.too_many_from_subnet),
+            ("too_many_without_ip", rejections.too_many_without_ip),
+        ] {
+            let labels = RejectionLabels { reason };
+            set_counter(&self.rejected_providers.get_or_create(&labels), count);
+        }
+    }
+}
+
+fn set_counter(counter: &Counter, count: u64) {
+    counter
+        .inner()
+        .store(count, std::sync::atomic::Ordering::Relaxed);
+}
+
+/// Records how long a request took once it is answered, see
+/// [`crate::req_res::RequestHandler::respond`].
+#[derive(Debug)]
+pub(crate) struct RequestTimer {
+    request: &'static str,
+    started: Instant,
+    requests: Family<RequestLabels, Counter>,
+    request_duration: DurationFamily,
+}
+
+impl RequestTimer {
+    pub(crate) fn finish(self, ok: bool) {
+        let outcome = if ok { "ok" } else { "error" };
+        self.requests
+            .get_or_create(&RequestLabels {
+                request: self.request,
+                outcome,
+            })
+            .inc();
+        self.request_duration
+            .get_or_create(&RequestKindLabels {
+                request: self.request,
+            })
+            .observe(self.started.elapsed().as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{encode, InboundProtocol, NodeMetrics, Registry};
+    use crate::{rate_limit::InboundMetrics, sybil::ProviderRejections};
+
+    #[test]
+    fn test_encode() {
+        let mut registry = Registry::default();
+        let metrics = NodeMetrics::new(&mut registry);
+        metrics.set_gauges(3, 2, 1);
+        metrics.set_inbound(
+            InboundProtocol::FileReqRes,
+            InboundMetrics {
+                accepted: 5,
+                rate_limited: 1,
+                too_many_in_flight: 0,
+            },
+        );
+        metrics.set_republish_failures(4);
+        metrics.set_rejected_providers(ProviderRejections {
+            too_many_from_ip: 2,
+            ..Default::default()
+        });
+        metrics.timer("get_providers").finish(true);
+        metrics.timer("get_providers").finish(false);
+
+        let mut encoded = String::new();
+        encode(&mut encoded, &registry).unwrap();
+        let lines = encoded.lines().collect::<Vec<_>>();
+        for expected in [
+            "market_connections 3",
+            "market_routing_table_size 2",
+            "market_pending_queries 1",
+            "market_inbound_requests_total{protocol=\"file_req_res\",outcome=\"accepted\"} 5",
+            "market_inbound_requests_total{protocol=\"file_req_res\",outcome=\"rate_limited\"} 1",
+            "market_republish_failures_total 4",
+            "market_rejected_provider_records_total{reason=\"too_many_from_ip\"} 2",
+            "market_requests_total{request=\"get_providers\",outcome=\"ok\"} 1",
+            "market_requests_total{request=\"get_providers\",outcome=\"error\"} 1",
+            "market_request_duration_seconds_count{request=\"get_providers\"} 2",
+        ] {
+            assert_eq!(lines.contains(&expected), true, "missing {expected}");
+        }
+        assert_eq!(lines.iter().any(|line| line.starts_with("libp2p_")), true);
+    }
+}
@@ -1,37 +1,127 @@
 use std::{thread, time::Duration};
 
+use either::Either;
 use libp2p::{
+    allow_block_list,
+    core::{muxing::StreamMuxerBox, transport::Boxed, upgrade::Version},
     identify::{Behaviour as IdentifyBehaviour, Config as IdentifyConfig},
-    kad::{store::MemoryStore, Behaviour as KadBehaviour, Config as KadConfig},
-    noise, yamux,
+    kad::{Behaviour as KadBehaviour, Config as KadConfig, Mode as KadMode, StoreInserts},
+    noise,
+    pnet::PnetConfig,
+    pnet::PreSharedKey,
+    request_response::ProtocolSupport,
+    tcp, yamux, Multiaddr, PeerId, Transport,
 };
 use thiserror::Error;
-use tokio::{runtime::Runtime, sync::mpsc};
+use tokio::{
+    runtime,
+    sync::{broadcast, mpsc},
+};
 
 use crate::{
     behaviour::{
-        file_req_res::{FileReqResBehaviour, FILE_REQ_RES_PROTOCOL},
-        ident::IDENTIFY_PROTOCOL_NAME,
-        kademlia::KAD_PROTOCOL_NAME,
-        MarketBehaviour,
+        announce::{Announce, ANNOUNCEMENT_BUFFER},
+        file_req_res::FileReqResBehaviour,
+        file_transfer::FileTransferBehaviour,
+        kademlia::LookupStore,
+        MarketBehaviour, ProtocolNames,
     },
-    config::Config,
-    coordinator::Coordinator,
+    config::{self, Config},
+    coordinator::{Coordinator, CoordinatorConfig, LocalMarketMap},
+    memory_links,
+    metrics::NodeMetrics,
     peer::Peer,
+    registry::Registry,
+    sybil::generate_identity,
 };
 
 const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
 pub(crate) const PROVIDER_RECORD_TTL: Duration = Duration::from_secs(60 * 60);
 const PROVIDER_REPUBLICATION: Duration = Duration::from_secs(60 * 5);
+const TRANSPORT_TIMEOUT: Duration = Duration::from_secs(20);
+
+type BoxedTransport = Boxed<(PeerId, StreamMuxerBox)>;
+
+fn build_transport(
+    transport: config::Transport,
+    listener: &Multiaddr,
+    pre_shared_key: Option<PreSharedKey>,
+    key: &libp2p::identity::Keypair,
+) -> Result<BoxedTransport, Box<dyn std::error::Error + Send + Sync>> {
+    match transport {
+        config::Transport::Tcp => upgrade(
+            tcp::tokio::Transport::new(tcp::Config::default()),
+            pre_shared_key,
+            key,
+        ),
+        config::Transport::Memory => upgrade(
+            memory_links::transport(memory_links::memory_port(listener)),
+            pre_shared_key,
+            key,
+        ),
+    }
+}
+
+fn upgrade<T>(
+    transport: T,
+    pre_shared_key: Option<PreSharedKey>,
+    key: &libp2p::identity::Keypair,
+) -> Result<BoxedTransport, Box<dyn std::error::Error + Send + Sync>>
+where
+    T: Transport + Send + Unpin + 'static,
+    T::Output: futures::AsyncRead + futures::AsyncWrite + Send + Unpin + 'static,
+    T::Error: Send + Sync + 'static,
+    T::Dial: Send + 'static,
+    T::ListenerUpgrade: Send + 'static,
+{
+    // the pre-shared key handshake comes first so that peers outside of the private
+    // network cannot even get to noise
+    let transport = match pre_shared_key {
+        Some(psk) => Either::Left(
+            transport.and_then(move |socket, _| PnetConfig::new(psk).handshake(socket)),
+        ),
+        None => Either::Right(transport),
+    };
+    Ok(transport
+        .upgrade(Version::V1Lazy)
+        .authenticate(noise::Config::new(key)?)
+        .multiplex(yamux::Config::default())
+        .timeout(TRANSPORT_TIMEOUT)
+        .boxed())
+}
 
 pub fn spawn_bridge(config: Config) -> Result<Peer, NetworkBridgeError> {
-    let swarm = libp2p::SwarmBuilder::with_new_identity()
+    let Config {
+        boot_nodes,
+        listener,
+        thread_name,
+        file_transfer_dir,
+        registry_path,
+        max_listings,
+        inbound_limits,
+        allowed_peers,
+        blocked_peers,
+        pre_shared_key,
+        protocol_prefix,
+        min_supplier_score,
+        sybil_limits,
+        identity,
+        metrics_registry,
+        request_queue_capacity,
+        transport,
+        worker_threads,
+    } = config;
+    let identity = identity.unwrap_or_else(|| generate_identity(sybil_limits.peer_id_difficulty));
+    let protocol_names = ProtocolNames::new(protocol_prefix.as_deref())
+        .map_err(|err| NetworkBridgeError::Init(format!("Invalid protocol prefix: {err}")))?;
+    let kad_protocol = protocol_names.kad.clone();
+    let market_map = LocalMarketMap::new(registry_path.map(Registry::new), max_listings)
+        .map_err(|err| NetworkBridgeError::Init(err.to_string()))?;
+    let file_transfer_enabled = file_transfer_dir.is_some();
+    let served_dir = file_transfer_dir.clone();
+    let swarm = libp2p::SwarmBuilder::with_existing_identity(identity)
         .with_tokio()
-        .with_tcp(
-            Default::default(),
-            noise::Config::new,
-            yamux::Config::default,
-        )
+        .with_other_transport(|key| build_transport(transport, &listener, pre_shared_key, key))
         .map_err(|err| NetworkBridgeError::Init(err.to_string()))?
         .with_dns()
         .map_err(|err| NetworkBridgeError::Init(err.to_string()))?
@@ -39,38 +129,104 @@ pub fn spawn_bridge(config: Config) -> Result<Peer, NetworkBridgeError> {
             let peer_id = key.public().to_peer_id();
             // TODO: maybe configure something?
             let mut config = KadConfig::default();
-            config.set_protocol_names(vec![KAD_PROTOCOL_NAME]);
+            config.set_protocol_names(vec![protocol_names.kad]);
             // NOTE: skeptical here?
             config.set_provider_publication_interval(Some(PROVIDER_REPUBLICATION));
             config.set_provider_record_ttl(Some(PROVIDER_RECORD_TTL));
-            let kad_behaviour =
-                KadBehaviour::with_config(peer_id, MemoryStore::new(peer_id), config);
-            let config = IdentifyConfig::new(IDENTIFY_PROTOCOL_NAME.to_string(), key.public());
+            // inbound records are stored by the KadHandler once they pass the inbound limits
+            config.set_record_filtering(StoreInserts::FilterBoth);
+            let mut kad_behaviour =
+                KadBehaviour::with_config(peer_id, LookupStore::new(peer_id), config);
+            // NOTE: Kademlia would only serve once it has an external address, and those are no
+            // longer taken from the candidates that single peers report
+            kad_behaviour.set_mode(Some(KadMode::Server));
+            let config = IdentifyConfig::new(protocol_names.identify, key.public());
             let identify_behaviour = IdentifyBehaviour::new(config);
-            let file_req_res = FileReqResBehaviour::new(FILE_REQ_RES_PROTOCOL, Default::default());
-            MarketBehaviour::new(kad_behaviour, identify_behaviour, file_req_res)
+            let file_req_res = FileReqResBehaviour::new(
+                [(protocol_names.file_req_res, ProtocolSupport::Full)],
+                Default::default(),
+            );
+            let file_transfer = file_transfer_enabled.then(|| {
+                FileTransferBehaviour::new(
+                    [(protocol_names.file_transfer, ProtocolSupport::Full)],
+                    Default::default(),
+                )
+            });
+            let mut blocked_peers_behaviour = allow_block_list::Behaviour::default();
+            for peer in &blocked_peers {
+                blocked_peers_behaviour.block_peer(*peer);
+            }
+            let allowed_peers_behaviour = allowed_peers.map(|allowed_peers| {
+                let mut behaviour = allow_block_list::Behaviour::default();
+                let boot_node_ids = boot_nodes
+                    .as_deref()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|node| node.peer_id);
+                for peer in allowed_peers.into_iter().chain(boot_node_ids) {
+                    behaviour.allow_peer(peer);
+                }
+                behaviour
+            });
+            let announce = Announce::new(key, protocol_names.gossipsub)?;
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(MarketBehaviour::new(
+                kad_behaviour,
+                identify_behaviour,
+                file_req_res,
+                file_transfer,
+                announce,
+                blocked_peers_behaviour,
+                allowed_peers_behaviour,
+            ))
         })
         .map_err(|err| NetworkBridgeError::Init(err.to_string()))?
         .with_swarm_config(|c| c.with_idle_connection_timeout(KEEP_ALIVE_TIMEOUT))
         .build();
 
+    let (announcements, _) = broadcast::channel(ANNOUNCEMENT_BUFFER);
+    let coordinator_config = CoordinatorConfig {
+        listen_addr: listener,
+        boot_nodes,
+        file_transfer_dir,
+        inbound_limits,
+        blocked_peers,
+        kad_protocol,
+        min_supplier_score,
+        sybil_limits,
+        announcements: announcements.clone(),
+        request_queue_capacity: request_queue_capacity.get(),
+        metrics: metrics_registry.map(|registry| {
+            NodeMetrics::new(
+                &mut registry
+                    .lock()
+                    .expect("the metrics registry to not be poisoned"),
+            )
+        }),
+    };
+
     // NOTE: this thread places the coordinator in a static context assuming the
     // thread lives for program life
-    let Config {
-        boot_nodes,
-        listener,
-        thread_name,
-    } = config;
     let peer_id = *swarm.local_peer_id();
 
-    let (receiver_tx, receiver_rx) = mpsc::unbounded_channel();
+    let (receiver_tx, receiver_rx) = mpsc::channel(request_queue_capacity.get());
+    let request_sender = receiver_tx.downgrade();
     let (ready_tx, ready_rx) = std::sync::mpsc::channel();
 
     thread::Builder::new()
         .name(thread_name)
         .spawn(move || {
-            Runtime::new().unwrap().block_on(async move {
-                match Coordinator::new(swarm, listener, boot_nodes, receiver_rx) {
+            let mut runtime = runtime::Builder::new_multi_thread();
+            if let Some(threads) = worker_threads {
+                runtime.worker_threads(threads.get());
+            }
+            runtime.enable_all().build().unwrap().block_on(async move {
+                match Coordinator::new(
+                    swarm,
+                    coordinator_config,
+                    market_map,
+                    receiver_rx,
+                    request_sender,
+                ) {
                     Ok(coordinator) => {
                         ready_tx
                             .send(Ok(()))
@@ -93,7 +249,7 @@ pub fn spawn_bridge(config: Config) -> Result<Peer, NetworkBridgeError> {
     if let Err(err) = res {
         Err(NetworkBridgeError::Init(err.to_string()))
     } else {
-        let peer = Peer::new(receiver_tx, peer_id);
+        let peer = Peer::new(receiver_tx, announcements, peer_id, served_dir);
         Ok(peer)
     }
 }
@@ -1,27 +1,114 @@
 use std::borrow::Cow;
-use std::net::Ipv4Addr;
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use tokio::sync::mpsc;
+use anyhow::bail;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::{
+    fs,
+    io::AsyncWriteExt,
+    sync::{
+        broadcast,
+        mpsc::{self, error::TrySendError},
+    },
+};
+use tracing::warn;
 
-use crate::behaviour::file_req_res::{FileHash, FileMetadata, SupplierInfo};
+use crate::address::SupplierAddr;
+use crate::behaviour::announce::{MarketAnnouncement, MarketSubscription, MarketTopic};
+use crate::behaviour::file_req_res::{FileMetadata, SupplierInfo};
+use crate::behaviour::file_transfer::{sha256_digest, CHUNK_SIZE};
+use crate::file_hash::FileHash;
 use crate::req_res::{
-    FileReqResRequestData, FileReqResResponseData, KadRequestData, KadResponseData, Request,
-    RequestData, RequestHandler, Response, ResponseData,
+    FileReqResRequestData, FileReqResResponseData, FileTransferRequestData,
+    FileTransferResponseData, KadRequestData, KadResponseData, ListingUpdate, Request, RequestData,
+    RequestHandler, Response, ResponseData,
 };
-use crate::PeerId;
+use crate::search::{self, Postings};
+use crate::{PeerId, Quorum};
 
 use self::macros::send;
 
-#[derive(Debug)]
+// NOTE: every supplier publishes its postings to the closest peers that it knows of, so more than
+// one copy is collected to see the postings of more suppliers
+const SEARCH_QUORUM: NonZeroUsize = match NonZeroUsize::new(3) {
+    Some(n) => n,
+    None => unreachable!(),
+};
+
+/// Errors that [`Peer`] reports before a request reaches the node. They come wrapped in the
+/// [`anyhow::Error`] of a [`Response`] and can be told apart with [`anyhow::Error::downcast_ref`].
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PeerError {
+    /// The request queue is full, see [`crate::config::ConfigBuilder::with_request_queue_capacity`].
+    /// Nothing was sent, so the request can be retried later.
+    #[error("The node is busy, {capacity} requests are already waiting")]
+    Busy { capacity: usize },
+    #[error("The node has shut down")]
+    ShutDown,
+}
+
+/// Why [`Peer::register_path`] refused to serve a path. Like [`PeerError`], it comes wrapped in
+/// the [`anyhow::Error`] of the [`Response`].
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RegisterPathError {
+    #[error("{} is outside of the file transfer directory {}", path.display(), dir.display())]
+    OutsideFileTransferDir { path: PathBuf, dir: PathBuf },
+}
+
+/// Why [`Peer::index_file`] or [`Peer::search`] could not make sense of their input. Like
+/// [`PeerError`], it comes wrapped in the [`anyhow::Error`] of the [`Response`].
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SearchError {
+    /// Keywords are made of letters and digits and are at least two characters long.
+    #[error("There are no keywords in {text:?}")]
+    NoKeywords { text: String },
+}
+
+/// A handle to a running node. Handles are cheap to clone and can be used from any number of
+/// tasks at once. The node shuts down once every handle is dropped or [`Peer::shutdown`] is
+/// called.
+#[derive(Debug, Clone)]
 pub struct Peer {
     id: PeerId,
-    sender: mpsc::UnboundedSender<Request>,
+    sender: mpsc::Sender<Request>,
+    announcements: broadcast::Sender<MarketAnnouncement>,
+    // the only directory that registered paths may be served from
+    file_transfer_dir: Option<PathBuf>,
+    request_id: Option<String>,
 }
 
 impl Peer {
     #[inline(always)]
-    pub(crate) const fn new(sender: mpsc::UnboundedSender<Request>, id: PeerId) -> Self {
-        Peer { sender, id }
+    pub(crate) const fn new(
+        sender: mpsc::Sender<Request>,
+        announcements: broadcast::Sender<MarketAnnouncement>,
+        id: PeerId,
+        file_transfer_dir: Option<PathBuf>,
+    ) -> Self {
+        Peer {
+            sender,
+            announcements,
+            id,
+            file_transfer_dir,
+            request_id: None,
+        }
+    }
+
+    /// A handle that tags the requests it sends with `request_id`. The spans that the node
+    /// handles them in carry it as their `request_id` field, so that a request of a client can be
+    /// told apart in the logs of the node.
+    pub fn with_request_id(&self, request_id: impl Into<String>) -> Self {
+        Self {
+            request_id: Some(request_id.into()),
+            ..self.clone()
+        }
     }
 
     #[inline(always)]
@@ -29,6 +116,13 @@ impl Peer {
         &self.id
     }
 
+    /// Starts receiving the market announcements on `topics`, both the ones from other suppliers
+    /// and the ones that this node publishes itself. Only announcements that come in after
+    /// subscribing are received.
+    pub fn subscribe(&self, topics: impl IntoIterator<Item = MarketTopic>) -> MarketSubscription {
+        MarketSubscription::new(topics, self.announcements.subscribe())
+    }
+
     #[inline(always)]
     pub async fn is_connected_to(&self, peer_id: PeerId) -> Response {
         send!(self, RequestData::IsConnectedTo(peer_id))
@@ -44,6 +138,63 @@ impl Peer {
         send!(self, RequestData::GetConnectedPeers)
     }
 
+    /// Closes every connection to `peer_id`, refuses new ones and leaves it out of lookups until
+    /// it is unblocked. Responds with every blocked peer.
+    #[inline(always)]
+    pub async fn block_peer(&self, peer_id: PeerId) -> Response {
+        send!(self, RequestData::BlockPeer(peer_id))
+    }
+
+    /// Responds with every peer that is still blocked.
+    #[inline(always)]
+    pub async fn unblock_peer(&self, peer_id: PeerId) -> Response {
+        send!(self, RequestData::UnblockPeer(peer_id))
+    }
+
+    #[inline(always)]
+    pub async fn get_blocked_peers(&self) -> Response {
+        send!(self, RequestData::GetBlockedPeers)
+    }
+
+    /// Returns the most recent listings that the node evicted on its own, oldest first.
+    #[inline(always)]
+    pub async fn get_evictions(&self) -> Response {
+        send!(self, RequestData::GetEvictions)
+    }
+
+    /// Returns every file this node currently supplies along with when it was registered, when it
+    /// expires and how the last (re)publish of its provider record went.
+    #[inline(always)]
+    pub async fn local_listings(&self) -> Response {
+        send!(self, RequestData::GetLocalListings)
+    }
+
+    /// Returns how often the listings of this node were requested by other peers, per file and
+    /// per requesting peer.
+    #[inline(always)]
+    pub async fn listing_stats(&self) -> Response {
+        send!(self, RequestData::GetListingStats)
+    }
+
+    /// Returns counters of how many inbound requests this node accepted and rejected.
+    #[inline(always)]
+    pub async fn get_metrics(&self) -> Response {
+        send!(self, RequestData::GetMetrics)
+    }
+
+    /// Shuts the node down for every handle, closing all of its connections and listeners. Requests
+    /// that are still waiting or in flight fail with [`PeerError::ShutDown`], as does everything
+    /// that is sent afterwards. Unlike other requests it waits for room in a full queue instead
+    /// of failing with [`PeerError::Busy`].
+    pub async fn shutdown(&self) -> Response {
+        let (request_handler, response_handler) = RequestHandler::new(self.request_id.clone());
+        self.sender
+            .send((RequestData::Shutdown, request_handler))
+            .await
+            .map_err(|_| PeerError::ShutDown)?;
+        response_handler.get_response_data().await
+    }
+
     #[inline(always)]
     pub async fn get_closest_local_peers(&self, key: Cow<'_, Vec<u8>>) -> Response {
         let key = get_owned_key(key);
@@ -53,94 +204,418 @@ impl Peer {
         )
     }
 
+    /// Looks up the peers closest to `file_hash`, which are the ones that hold its provider
+    /// records.
+    #[inline(always)]
+    pub async fn get_closest_peers(&self, file_hash: Cow<'_, FileHash>) -> Response {
+        send!(
+            self,
+            RequestData::KadRequest(KadRequestData::ClosestPeers {
+                key: file_hash.into_owned().0
+            })
+        )
+    }
+
+    /// Looks up the peers closest to `peer_id`. Looking up its own id is how a node learns about
+    /// the peers around it, like a bootstrap does.
     #[inline(always)]
-    pub async fn get_closest_peers(&self, key: Cow<'_, Vec<u8>>) -> Response {
+    pub async fn get_closest_peers_to(&self, peer_id: PeerId) -> Response {
+        send!(
+            self,
+            RequestData::KadRequest(KadRequestData::ClosestPeers {
+                key: peer_id.to_bytes()
+            })
+        )
+    }
+
+    /// Stores `value` under `key` in the DHT. The put succeeds once `quorum` peers stored the
+    /// record. Without a `ttl` the record expires after the Kademlia default of 36 hours, and it
+    /// is republished by this node until then.
+    pub async fn put_record(
+        &self,
+        key: Cow<'_, Vec<u8>>,
+        value: Vec<u8>,
+        quorum: Quorum,
+        ttl: Option<Duration>,
+    ) -> Response {
         let key = get_owned_key(key);
         send!(
             self,
-            RequestData::KadRequest(KadRequestData::ClosestPeers { key })
+            RequestData::KadRequest(KadRequestData::PutRecord {
+                key,
+                value,
+                quorum,
+                ttl
+            })
         )
     }
 
+    /// Looks up the records stored under `key`, stopping once `quorum` copies were found.
     #[inline(always)]
+    pub async fn get_record(&self, key: Cow<'_, Vec<u8>>, quorum: Quorum) -> Response {
+        let key = get_owned_key(key);
+        send!(
+            self,
+            RequestData::KadRequest(KadRequestData::GetRecord { key, quorum })
+        )
+    }
+
+    /// Removes a record that this node published from its own store and stops republishing it.
+    /// The copies that other peers hold are dropped once they expire.
+    #[inline(always)]
+    pub async fn remove_record(&self, key: Cow<'_, Vec<u8>>) -> Response {
+        let key = get_owned_key(key);
+        send!(
+            self,
+            RequestData::KadRequest(KadRequestData::RemoveRecord { key })
+        )
+    }
+
+    /// Registers this node as a supplier of `file_hash`. If `addr` is `None`, the most reachable
+    /// address that other peers observed for this node is used, falling back to its listen
+    /// addresses. Registering with an address that other peers cannot reach is logged as a
+    /// warning.
     pub async fn register_file(
         &self,
-        file_hash: Cow<'_, Vec<u8>>,
-        ip: impl Into<Ipv4Addr>,
+        file_hash: Cow<'_, FileHash>,
+        addr: Option<SupplierAddr>,
         port: u16,
         price: i64,
         username: String,
+    ) -> Response {
+        self.register(file_hash.into_owned(), addr, port, price, username, None)
+            .await
+    }
+
+    async fn register(
+        &self,
+        file_hash: FileHash,
+        addr: Option<SupplierAddr>,
+        port: u16,
+        price: i64,
+        username: String,
+        served_path: Option<PathBuf>,
     ) -> Response {
         // NOTE: the price is i64 because the protobuf file specified i64 for some reason
-        let file_hash = get_owned_key(file_hash);
+        let addr = match addr {
+            Some(addr) => addr,
+            None => match send!(self, RequestData::DetectSupplierAddr)? {
+                ResponseData::DetectSupplierAddr { addr: Some(addr) } => addr,
+                ResponseData::DetectSupplierAddr { addr: None } => {
+                    bail!("Could not detect an address for this node, please provide one")
+                }
+                res => bail!("Unexpected response {res:?}"),
+            },
+        };
         let supplier_info = SupplierInfo {
-            ip: ip.into(),
+            addr,
             port,
             price,
             username,
         };
         let file_metadata = FileMetadata {
-            file_hash: FileHash(file_hash),
+            file_hash,
             supplier_info,
         };
         send!(
             self,
-            RequestData::KadRequest(KadRequestData::RegisterFile { file_metadata })
+            RequestData::KadRequest(KadRequestData::RegisterFile {
+                file_metadata,
+                served_path
+            })
         )
     }
 
+    /// Registers this node as a supplier of `file_hash` like [`Peer::register_file`] and makes the
+    /// file searchable by the words in its `name`, see [`Peer::index_file`]. Names without any
+    /// keywords fail with a [`SearchError`] before anything is registered.
+    pub async fn register_named_file(
+        &self,
+        file_hash: Cow<'_, FileHash>,
+        name: &str,
+        addr: Option<SupplierAddr>,
+        port: u16,
+        price: i64,
+        username: String,
+    ) -> Response {
+        if search::keywords([name]).is_empty() {
+            bail!(SearchError::NoKeywords {
+                text: name.to_owned()
+            });
+        }
+        let res = self
+            .register_file(Cow::Borrowed(&file_hash), addr, port, price, username)
+            .await?;
+        self.index_file(file_hash, name, &[]).await?;
+        Ok(res)
+    }
+
+    /// Hashes the file at `path` with SHA-256 and registers it under that digest. The digest is
+    /// returned as the key of the [`KadResponseData::RegisterFile`] response, and the file can be
+    /// searched for by its name. `addr` is handled like in [`Peer::register_file`].
+    ///
+    /// If file transfer is enabled, the file is also served from `path`, which must then lie
+    /// within the file transfer directory, see
+    /// [`crate::config::ConfigBuilder::with_file_transfer_dir`]. Other paths, including symlinks
+    /// that lead out of the directory, fail with a [`RegisterPathError`] before the file is read.
+    pub async fn register_path(
+        &self,
+        path: impl AsRef<Path>,
+        addr: Option<SupplierAddr>,
+        port: u16,
+        price: i64,
+        username: String,
+    ) -> Response {
+        let (path, served_path) = match &self.file_transfer_dir {
+            Some(dir) => {
+                let path = servable_path(dir, path.as_ref()).await?;
+                (path.clone(), Some(path))
+            }
+            None => (path.as_ref().to_path_buf(), None),
+        };
+        let file_hash = FileHash(sha256_digest(&path).await?);
+        // the listing is served from the moment it is registered, so it is never listed without
+        // the file behind it
+        let res = self
+            .register(file_hash.clone(), addr, port, price, username, served_path)
+            .await?;
+        if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+            if let Err(err) = self.index_file(Cow::Owned(file_hash), name, &[]).await {
+                warn!("Failed to index {name}: {err}");
+            }
+        }
+        Ok(res)
+    }
+
+    /// Makes a file that this node has registered searchable by the words in its `name` and
+    /// `tags`, replacing the keywords it had before. Responds with the keywords. The postings are
+    /// republished along with the provider record of the file, and they expire with it.
+    pub async fn index_file(
+        &self,
+        file_hash: Cow<'_, FileHash>,
+        name: &str,
+        tags: &[String],
+    ) -> Response {
+        let file_hash = file_hash.into_owned();
+        let keywords =
+            search::keywords(std::iter::once(name).chain(tags.iter().map(String::as_str)));
+        if keywords.is_empty() {
+            bail!(SearchError::NoKeywords {
+                text: std::iter::once(name)
+                    .chain(tags.iter().map(String::as_str))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            });
+        }
+        send!(
+            self,
+            RequestData::KadRequest(KadRequestData::IndexFile {
+                file_hash,
+                keywords
+            })
+        )
+    }
+
+    /// Looks up the files whose name or tags contain every word of `query`. Responds with the
+    /// matching file hashes, which can be passed on to [`Peer::check_holders`]. Queries without
+    /// any keywords fail with a [`SearchError`].
+    pub async fn search(&self, query: &str) -> Response {
+        let keywords = search::keywords([query]);
+        if keywords.is_empty() {
+            bail!(SearchError::NoKeywords {
+                text: query.to_owned()
+            });
+        }
+        let mut file_hashes: Option<HashSet<FileHash>> = None;
+        for keyword in keywords {
+            let res = send!(
+                self,
+                RequestData::KadRequest(KadRequestData::GetRecord {
+                    key: search::posting_key(&keyword),
+                    quorum: Quorum::N(SEARCH_QUORUM),
+                })
+            );
+            let mut postings = Postings::default();
+            if let Ok(ResponseData::KadResponse(KadResponseData::GetRecord { records })) = res {
+                for record in records {
+                    postings.merge(Postings::decode(&record.value));
+                }
+            }
+            let matches = postings.file_hashes();
+            file_hashes = Some(match file_hashes {
+                Some(file_hashes) => file_hashes.intersection(&matches).cloned().collect(),
+                None => matches,
+            });
+            if file_hashes.as_ref().is_some_and(HashSet::is_empty) {
+                break;
+            }
+        }
+        let mut file_hashes = file_hashes
+            .unwrap_or_default()
+            .into_iter()
+            .collect::<Vec<_>>();
+        file_hashes.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(ResponseData::SearchResults { file_hashes })
+    }
+
+    /// Changes the supplier info of a file that this node has registered without touching the
+    /// fields that are `None` in `update`. Responds with the supplier info from before and after
+    /// the update.
     #[inline(always)]
-    pub async fn check_holders(&self, file_hash: Cow<'_, Vec<u8>>) -> Response {
-        let file_hash = get_owned_key(file_hash);
+    pub async fn update_listing(
+        &self,
+        file_hash: Cow<'_, FileHash>,
+        update: ListingUpdate,
+    ) -> Response {
+        let file_hash = file_hash.into_owned();
+        send!(self, RequestData::UpdateListing { file_hash, update })
+    }
+
+    /// Asks every provider of `file_hash` for its supplier info. Providers are ranked by their
+    /// [`crate::Reputation`] before they are asked, which is built up from how they answered these
+    /// requests before, and blocked providers are left out.
+    #[inline(always)]
+    pub async fn check_holders(&self, file_hash: Cow<'_, FileHash>) -> Response {
+        let file_hash = file_hash.into_owned();
         let res = send!(
             self,
-            RequestData::KadRequest(KadRequestData::GetProviders { key: file_hash })
+            RequestData::KadRequest(KadRequestData::GetProviders {
+                file_hash: file_hash.clone()
+            })
         )?;
-        if let ResponseData::KadResponse(KadResponseData::GetProviders { key, providers }) = res {
-            // NOTE: maybe refactor later
-            let mut resp_providers = Vec::with_capacity(providers.len() + 1);
-            for provider in providers {
-                if let Ok(ResponseData::ReqResResponse(FileReqResResponseData::GetSupplierInfo {
-                    supplier_info,
-                })) = send!(
-                    self,
-                    RequestData::ReqResRequest(FileReqResRequestData::GetSupplierInfo {
-                        file_hash: key.clone(),
-                        peer_id: provider
-                    })
-                ) {
-                    resp_providers.push((provider, supplier_info));
-                }
-            }
-            if let Ok(ResponseData::GetLocalSupplierInfo {
-                supplier_info: Some(info),
-            }) = send!(
+        let ResponseData::KadResponse(KadResponseData::GetProviders { providers, .. }) = res else {
+            bail!("Unexpected response to a provider lookup: {res:?}");
+        };
+        let mut suppliers = Vec::with_capacity(providers.len() + 1);
+        let mut reputations = HashMap::with_capacity(providers.len());
+        for (provider, reputation) in providers {
+            if let Ok(ResponseData::ReqResResponse(FileReqResResponseData::GetSupplierInfo {
+                supplier_info,
+            })) = send!(
                 self,
-                RequestData::GetLocalSupplierInfo {
-                    file_hash: FileHash(key)
-                }
+                RequestData::ReqResRequest(FileReqResRequestData::GetSupplierInfo {
+                    file_hash: file_hash.clone(),
+                    peer_id: provider
+                })
             ) {
-                resp_providers.push((self.id, info));
+                suppliers.push((provider, supplier_info));
+                reputations.insert(provider, reputation);
             }
-            Ok(ResponseData::ReqResResponse(
-                FileReqResResponseData::GetSuppliers {
-                    suppliers: resp_providers,
-                },
-            ))
-        } else {
-            panic!("Unexpected response");
         }
+        if let Ok(ResponseData::GetLocalSupplierInfo {
+            supplier_info: Some(info),
+        }) = send!(self, RequestData::GetLocalSupplierInfo { file_hash })
+        {
+            suppliers.push((self.id, info));
+        }
+        Ok(ResponseData::ReqResResponse(
+            FileReqResResponseData::GetSuppliers {
+                suppliers,
+                reputations,
+            },
+        ))
+    }
+
+    /// Downloads the file identified by `file_hash` from `peer_id` into `dest` chunk by chunk.
+    /// The chunks are written next to `dest` first and only moved over it once the downloaded
+    /// content hashes (SHA-256) to `file_hash`, so a failed download leaves `dest` untouched.
+    pub async fn download_file(
+        &self,
+        peer_id: PeerId,
+        file_hash: Cow<'_, FileHash>,
+        dest: impl AsRef<Path>,
+    ) -> Response {
+        let file_hash = file_hash.into_owned();
+        let dest = dest.as_ref();
+        let part = partial_download_path(dest);
+        let mut file = fs::File::create(&part).await?;
+        let num_bytes = match self.download_chunks(peer_id, &file_hash, &mut file).await {
+            Ok(num_bytes) => num_bytes,
+            Err(err) => {
+                drop(file);
+                let _ = fs::remove_file(&part).await;
+                return Err(err);
+            }
+        };
+        drop(file);
+        fs::rename(&part, dest).await?;
+        Ok(ResponseData::FileTransferResponse(
+            FileTransferResponseData::DownloadFile {
+                file_hash,
+                path: dest.to_path_buf(),
+                num_bytes,
+            },
+        ))
+    }
+
+    /// Writes the chunks of `file_hash` into `file` and returns how many bytes were written. The
+    /// size announced by the first chunk has to hold for every other chunk.
+    async fn download_chunks(
+        &self,
+        peer_id: PeerId,
+        file_hash: &FileHash,
+        file: &mut fs::File,
+    ) -> anyhow::Result<u64> {
+        let mut hasher = Sha256::new();
+        let mut expected_size = None;
+        let mut num_bytes = 0;
+        let mut index = 0;
+        loop {
+            let res = send!(
+                self,
+                RequestData::FileTransferRequest(FileTransferRequestData::GetChunk {
+                    file_hash: file_hash.clone(),
+                    peer_id,
+                    index,
+                })
+            )?;
+            let ResponseData::FileTransferResponse(FileTransferResponseData::Chunk {
+                data,
+                file_size,
+            }) = res
+            else {
+                bail!("Unexpected response to a chunk request: {res:?}");
+            };
+            if *expected_size.get_or_insert(file_size) != file_size {
+                bail!("Peer {peer_id} changed the size of the file in the middle of the download");
+            }
+            if data.len() as u64 > CHUNK_SIZE || num_bytes + data.len() as u64 > file_size {
+                bail!("Peer {peer_id} sent more than the {file_size} bytes it announced");
+            }
+            hasher.update(&data);
+            file.write_all(&data).await?;
+            num_bytes += data.len() as u64;
+            index += 1;
+            if num_bytes >= file_size {
+                break;
+            } else if data.is_empty() {
+                bail!("Peer {peer_id} stopped sending chunks at {num_bytes}/{file_size} bytes");
+            }
+        }
+        file.flush().await?;
+        if hasher.finalize().as_slice() != file_hash.digest() {
+            bail!("Downloaded content from {peer_id} does not match the requested file hash");
+        }
+        Ok(num_bytes)
     }
 
     #[inline(always)]
     async fn send_request(&self, request_data: RequestData) -> Response {
-        let (request_handler, response_handler) = RequestHandler::new();
-        self.sender.send((request_data, request_handler))?;
+        let (request_handler, response_handler) = RequestHandler::new(self.request_id.clone());
+        self.sender
+            .try_send((request_data, request_handler))
+            .map_err(|err| match err {
+                TrySendError::Full(_) => PeerError::Busy {
+                    capacity: self.sender.max_capacity(),
+                },
+                TrySendError::Closed(_) => PeerError::ShutDown,
+            })?;
         response_handler.get_response_data().await
     }
 }
 
 #[inline(always)]
+#[allow(clippy::owned_cow)]
 fn get_owned_key(key: Cow<'_, Vec<u8>>) -> Vec<u8> {
     match key {
         Cow::Borrowed(bo) => bo.to_owned(),
@@ -148,6 +623,23 @@ fn get_owned_key(key: Cow<'_, Vec<u8>>) -> Vec<u8> {
     }
 }
 
+/// `dest` with a `.part` extension appended, where a download is written until it is complete.
+fn partial_download_path(dest: &Path) -> PathBuf {
+    let mut part = dest.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// Resolves `path` and checks that it lies within the file transfer directory `dir`.
+async fn servable_path(dir: &Path, path: &Path) -> anyhow::Result<PathBuf> {
+    let dir = fs::canonicalize(dir).await?;
+    let path = fs::canonicalize(path).await?;
+    if !path.starts_with(&dir) {
+        bail!(RegisterPathError::OutsideFileTransferDir { path, dir });
+    }
+    Ok(path)
+}
+
 mod macros {
     macro_rules! send {
         ($self: ident, $request:expr) => {
@@ -0,0 +1,165 @@
+use std::{collections::HashMap, time::Instant};
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::config::InboundLimits;
+
+// NOTE: per peer state is only pruned once there is a fair amount of it
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// Why an inbound request from a remote peer was refused.
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum InboundRejection {
+    #[error("Peer rejected the request since too many requests were sent recently")]
+    RateLimited,
+    #[error("Peer rejected the request since too many requests are still being handled")]
+    TooManyInFlight,
+}
+
+/// Counts of inbound requests on one protocol since the node started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InboundMetrics {
+    pub accepted: u64,
+    pub rate_limited: u64,
+    pub too_many_in_flight: u64,
+}
+
+#[derive(Debug)]
+struct PeerState {
+    window_start: Instant,
+    num_requests: u32,
+    in_flight: usize,
+}
+
+/// Enforces [`InboundLimits`] for every remote peer separately on one inbound path. Requests are
+/// counted in fixed windows, and requests that are accepted stay in flight until
+/// [`InboundLimiter::finish`] is called.
+#[derive(Debug)]
+pub(crate) struct InboundLimiter {
+    limits: InboundLimits,
+    peers: HashMap<PeerId, PeerState>,
+    metrics: InboundMetrics,
+}
+
+impl InboundLimiter {
+    pub(crate) fn new(limits: InboundLimits) -> Self {
+        Self {
+            limits,
+            peers: HashMap::new(),
+            metrics: InboundMetrics::default(),
+        }
+    }
+
+    /// Accepts a request from `peer` and marks it as in flight, unless one of the limits is hit.
+    pub(crate) fn start(&mut self, peer: PeerId) -> Result<(), InboundRejection> {
+        self.start_at(peer, Instant::now())
+    }
+
+    /// Marks a request from `peer` that was accepted by [`InboundLimiter::start`] as done.
+    pub(crate) fn finish(&mut self, peer: &PeerId) {
+        if let Some(state) = self.peers.get_mut(peer) {
+            state.in_flight = state.in_flight.saturating_sub(1);
+        }
+    }
+
+    /// Accepts a request from `peer` that is handled right away and so is never in flight.
+    pub(crate) fn check(&mut self, peer: PeerId) -> Result<(), InboundRejection> {
+        self.start(peer)?;
+        self.finish(&peer);
+        Ok(())
+    }
+
+    pub(crate) const fn metrics(&self) -> InboundMetrics {
+        self.metrics
+    }
+
+    fn start_at(&mut self, peer: PeerId, now: Instant) -> Result<(), InboundRejection> {
+        if self.peers.len() >= PRUNE_THRESHOLD {
+            self.prune(now);
+        }
+        let InboundLimits {
+            max_requests,
+            window,
+            max_in_flight,
+        } = self.limits;
+        let state = self.peers.entry(peer).or_insert(PeerState {
+            window_start: now,
+            num_requests: 0,
+            in_flight: 0,
+        });
+        if now.duration_since(state.window_start) >= window {
+            state.window_start = now;
+            state.num_requests = 0;
+        }
+        if state.in_flight >= max_in_flight {
+            self.metrics.too_many_in_flight += 1;
+            return Err(InboundRejection::TooManyInFlight);
+        }
+        if state.num_requests >= max_requests {
+            self.metrics.rate_limited += 1;
+            return Err(InboundRejection::RateLimited);
+        }
+        state.num_requests += 1;
+        state.in_flight += 1;
+        self.metrics.accepted += 1;
+        Ok(())
+    }
+
+    fn prune(&mut self, now: Instant) {
+        let window = self.limits.window;
+        self.peers.retain(|_, state| {
+            state.in_flight > 0 || now.duration_since(state.window_start) < window
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use libp2p::PeerId;
+    use pretty_assertions::assert_eq;
+
+    use super::{InboundLimiter, InboundMetrics, InboundRejection};
+    use crate::config::InboundLimits;
+
+    #[test]
+    fn test_limits_are_per_peer() {
+        let mut limiter = InboundLimiter::new(InboundLimits {
+            max_requests: 2,
+            window: Duration::from_secs(10),
+            max_in_flight: 1,
+        });
+        let peer = PeerId::random();
+        let other = PeerId::random();
+        let now = Instant::now();
+        limiter.start_at(peer, now).unwrap();
+        assert_eq!(
+            limiter.start_at(peer, now),
+            Err(InboundRejection::TooManyInFlight)
+        );
+        limiter.start_at(other, now).unwrap();
+        limiter.finish(&peer);
+        limiter.start_at(peer, now).unwrap();
+        limiter.finish(&peer);
+        assert_eq!(
+            limiter.start_at(peer, now),
+            Err(InboundRejection::RateLimited)
+        );
+        // a new window starts over
+        limiter
+            .start_at(peer, now + Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(
+            limiter.metrics(),
+            InboundMetrics {
+                accepted: 4,
+                rate_limited: 1,
+                too_many_in_flight: 1,
+            }
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    behaviour::file_req_res::SupplierInfo,
+    coordinator::LocalListing,
+    file_hash::{FileHash, FileHashError},
+    req_res::PublishStatus,
+};
+
+/// On-disk copy of the local market listings so that they survive a restart. Timestamps are kept
+/// as wall-clock seconds since the unix epoch since an `Instant` is meaningless across processes.
+#[derive(Debug, Clone)]
+pub(crate) struct Registry {
+    path: PathBuf,
+}
+
+impl Registry {
+    pub(crate) const fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Loads every listing from the registry. A missing registry file is treated as empty.
+    pub(crate) fn load(&self) -> Result<Vec<(FileHash, LocalListing)>, RegistryError> {
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let entries: Vec<RegistryEntry> = serde_json::from_slice(&contents)?;
+        entries
+            .into_iter()
+            .map(|entry| {
+                let registered_at = from_unix_secs(entry.registered_at);
+                Ok((
+                    FileHash::from_hex(&entry.file_hash)?,
+                    LocalListing {
+                        supplier_info: entry.supplier_info,
+                        registered_at,
+                        published_at: entry.published_at.map_or(registered_at, from_unix_secs),
+                        last_publish: PublishStatus::Pending,
+                        keywords: entry.keywords,
+                        served_path: entry.served_path,
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    /// Takes the listings as they are written to the registry, so that serializing and writing
+    /// them can happen somewhere else, see [`Snapshot::write`].
+    pub(crate) fn snapshot(&self, listings: &HashMap<FileHash, LocalListing>) -> Snapshot {
+        let entries = listings
+            .iter()
+            .map(|(file_hash, listing)| RegistryEntry {
+                file_hash: file_hash.to_hex(),
+                supplier_info: listing.supplier_info.clone(),
+                registered_at: to_unix_secs(&listing.registered_at),
+                published_at: Some(to_unix_secs(&listing.published_at)),
+                keywords: listing.keywords.clone(),
+                served_path: listing.served_path.clone(),
+            })
+            .collect::<Vec<_>>();
+        Snapshot {
+            path: self.path.clone(),
+            entries,
+        }
+    }
+}
+
+/// The listings of a [`Registry`] at one point in time.
+#[derive(Debug)]
+pub(crate) struct Snapshot {
+    path: PathBuf,
+    entries: Vec<RegistryEntry>,
+}
+
+impl Snapshot {
+    /// Replaces the registry with the snapshot. This blocks, so it belongs on a blocking thread.
+    pub(crate) fn write(self) -> Result<(), RegistryError> {
+        // write to a temporary file first so that a crash never leaves a truncated registry
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(&self.entries)?)?;
+        fs::rename(tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+fn to_unix_secs(time: &SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn from_unix_secs(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RegistryEntry {
+    file_hash: String,
+    supplier_info: SupplierInfo,
+    registered_at: u64,
+    // registries written before listings were refreshed on republication don't have this
+    #[serde(default)]
+    published_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    keywords: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    served_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum RegistryError {
+    #[error("Failed to access the registry: {0}")]
+    Io(#[from] io::Error),
+    #[error("Failed to parse the registry: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error("Invalid file hash in the registry: {0}")]
+    FileHash(#[from] FileHashError),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use pretty_assertions::assert_eq;
+
+    use super::Registry;
+    use crate::{
+        behaviour::file_req_res::SupplierInfo, coordinator::LocalListing, file_hash::FileHash,
+        req_res::PublishStatus,
+    };
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = Registry::new(dir.path().join("registry.json"));
+        let file_hash = FileHash::from_digest([7u8; 32]).unwrap();
+        let supplier_info = SupplierInfo {
+            addr: [127, 0, 0, 1].into(),
+            port: 8080,
+            price: 10,
+            username: "supplier".to_owned(),
+        };
+        let listing = LocalListing {
+            supplier_info,
+            registered_at: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            published_at: UNIX_EPOCH + Duration::from_secs(1_700_000_300),
+            last_publish: PublishStatus::Pending,
+            keywords: vec!["rust".to_owned(), "book".to_owned()],
+            served_path: Some("/srv/market/rust-book.pdf".into()),
+        };
+        let listings = HashMap::from([(file_hash, listing)]);
+        registry.snapshot(&listings).write().unwrap();
+        let loaded = registry
+            .load()
+            .unwrap()
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        assert_eq!(loaded, listings);
+    }
+
+    #[test]
+    fn test_missing_registry_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = Registry::new(dir.path().join("registry.json"));
+        assert!(registry.load().unwrap().is_empty());
+    }
+}
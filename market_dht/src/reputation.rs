@@ -0,0 +1,272 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use libp2p::PeerId;
+
+// NOTE: how much a new latency sample moves the average, out of 1
+const LATENCY_WEIGHT: f64 = 0.25;
+/// How long it takes for a success or failure to count half as much.
+pub(crate) const HALF_LIFE: Duration = Duration::from_secs(60 * 60);
+/// How many peers the reputation is kept of at most.
+pub(crate) const MAX_TRACKED_PEERS: usize = 4096;
+
+/// What this node has seen of a remote supplier when asking it for supplier info.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Reputation {
+    pub successes: u64,
+    /// Requests that timed out, could not be sent or were not answered.
+    pub failures: u64,
+    /// Requests that the supplier turned down under its inbound limits. They do not count against
+    /// the score, since it is this node that sent too many.
+    pub rejections: u64,
+    /// Moving average of how long successful requests took.
+    pub avg_latency: Option<Duration>,
+}
+
+impl Reputation {
+    /// A score between 0 and 1, higher is better. Suppliers that were never asked score 0.5, and
+    /// slow suppliers are scored down, e.g. by half for an average latency of a second.
+    pub fn score(&self) -> f64 {
+        let success_rate =
+            (self.successes + 1) as f64 / (self.successes + self.failures + 2) as f64;
+        let latency_factor = self
+            .avg_latency
+            .map_or(1.0, |latency| 1.0 / (1.0 + latency.as_secs_f64()));
+        success_rate * latency_factor
+    }
+
+    fn record_success(&mut self, latency: Duration) {
+        self.successes += 1;
+        self.avg_latency = Some(match self.avg_latency {
+            Some(avg) => avg.mul_f64(1.0 - LATENCY_WEIGHT) + latency.mul_f64(LATENCY_WEIGHT),
+            None => latency,
+        });
+    }
+}
+
+/// Decides which of the providers that a lookup found it answers with, and in which order.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ProviderRanking<'a> {
+    pub(crate) blocked_peers: &'a HashSet<PeerId>,
+    pub(crate) reputation: &'a ReputationTracker,
+    pub(crate) min_score: Option<f64>,
+}
+
+impl ProviderRanking<'_> {
+    /// Leaves out the blocked providers and the ones that score too low, best score first.
+    pub(crate) fn rank(
+        &self,
+        providers: impl IntoIterator<Item = PeerId>,
+        now: Instant,
+    ) -> Vec<(PeerId, Reputation)> {
+        self.reputation.rank(
+            providers
+                .into_iter()
+                .filter(|provider| !self.blocked_peers.contains(provider)),
+            self.min_score,
+            now,
+        )
+    }
+}
+
+/// Keeps the [`Reputation`] of the peers that this node asked for supplier info most recently.
+/// Successes and failures are halved every [`HALF_LIFE`] so that old outcomes count less than
+/// recent ones, and the peer that was heard from longest ago is forgotten once
+/// [`MAX_TRACKED_PEERS`] are tracked.
+#[derive(Debug, Default)]
+pub(crate) struct ReputationTracker {
+    peers: HashMap<PeerId, Tracked>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Tracked {
+    reputation: Reputation,
+    // when the counts were last halved
+    decayed_at: Instant,
+    last_seen: Instant,
+}
+
+impl Tracked {
+    fn new(now: Instant) -> Self {
+        Self {
+            reputation: Reputation::default(),
+            decayed_at: now,
+            last_seen: now,
+        }
+    }
+
+    /// The reputation with the counts halved once for every [`HALF_LIFE`] since the last decay.
+    fn decayed(&self, now: Instant) -> (Reputation, Instant) {
+        let elapsed = now.saturating_duration_since(self.decayed_at).as_secs();
+        let half_lives = elapsed / HALF_LIFE.as_secs();
+        if half_lives == 0 {
+            return (self.reputation, self.decayed_at);
+        }
+        // NOTE: 64 halvings clear any count
+        let halvings = half_lives.min(64) as u32;
+        let mut reputation = self.reputation;
+        reputation.successes = reputation.successes.checked_shr(halvings).unwrap_or(0);
+        reputation.failures = reputation.failures.checked_shr(halvings).unwrap_or(0);
+        reputation.rejections = reputation.rejections.checked_shr(halvings).unwrap_or(0);
+        let decayed_at = now - Duration::from_secs(elapsed % HALF_LIFE.as_secs());
+        (reputation, decayed_at)
+    }
+}
+
+impl ReputationTracker {
+    pub(crate) fn record_success(&mut self, peer: PeerId, latency: Duration, now: Instant) {
+        self.entry(peer, now).record_success(latency);
+    }
+
+    pub(crate) fn record_failure(&mut self, peer: PeerId, now: Instant) {
+        self.entry(peer, now).failures += 1;
+    }
+
+    pub(crate) fn record_rejection(&mut self, peer: PeerId, now: Instant) {
+        self.entry(peer, now).rejections += 1;
+    }
+
+    /// The decayed reputation of `peer`, the default for peers that are not tracked.
+    pub(crate) fn get(&self, peer: &PeerId, now: Instant) -> Reputation {
+        self.peers
+            .get(peer)
+            .map(|tracked| tracked.decayed(now).0)
+            .unwrap_or_default()
+    }
+
+    /// Orders `peers` from the best to the worst score and leaves out the ones that score below
+    /// `min_score`.
+    pub(crate) fn rank(
+        &self,
+        peers: impl IntoIterator<Item = PeerId>,
+        min_score: Option<f64>,
+        now: Instant,
+    ) -> Vec<(PeerId, Reputation)> {
+        let mut ranked = peers
+            .into_iter()
+            .map(|peer| (peer, self.get(&peer, now)))
+            .filter(|(_, reputation)| min_score.is_none_or(|min| reputation.score() >= min))
+            .collect::<Vec<_>>();
+        ranked.sort_by(|(_, a), (_, b)| b.score().total_cmp(&a.score()));
+        ranked
+    }
+
+    /// The decayed reputation of `peer` to record an outcome in, making room for it if it is new.
+    fn entry(&mut self, peer: PeerId, now: Instant) -> &mut Reputation {
+        if !self.peers.contains_key(&peer) && self.peers.len() >= MAX_TRACKED_PEERS {
+            let forgotten = self
+                .peers
+                .iter()
+                .min_by_key(|(_, tracked)| tracked.last_seen)
+                .map(|(peer, _)| *peer);
+            if let Some(forgotten) = forgotten {
+                self.peers.remove(&forgotten);
+            }
+        }
+        let tracked = self.peers.entry(peer).or_insert_with(|| Tracked::new(now));
+        (tracked.reputation, tracked.decayed_at) = tracked.decayed(now);
+        tracked.last_seen = now;
+        &mut tracked.reputation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use libp2p::PeerId;
+    use pretty_assertions::assert_eq;
+
+    use super::{Reputation, ReputationTracker, HALF_LIFE, MAX_TRACKED_PEERS};
+
+    #[test]
+    fn test_rank_by_success_rate_and_latency() {
+        let now = Instant::now();
+        let mut tracker = ReputationTracker::default();
+        let (reliable, slow, flaky, unknown) = (
+            PeerId::random(),
+            PeerId::random(),
+            PeerId::random(),
+            PeerId::random(),
+        );
+        for _ in 0..4 {
+            tracker.record_success(reliable, Duration::from_millis(20), now);
+            tracker.record_success(slow, Duration::from_secs(3), now);
+            tracker.record_failure(flaky, now);
+        }
+        tracker.record_success(flaky, Duration::from_millis(20), now);
+        assert_eq!(Reputation::default().score(), 0.5);
+        assert_eq!(
+            tracker
+                .rank([flaky, unknown, slow, reliable], None, now)
+                .into_iter()
+                .map(|(peer, _)| peer)
+                .collect::<Vec<_>>(),
+            vec![reliable, unknown, flaky, slow]
+        );
+        assert_eq!(
+            tracker
+                .rank([flaky, unknown, slow, reliable], Some(0.4), now)
+                .into_iter()
+                .map(|(peer, _)| peer)
+                .collect::<Vec<_>>(),
+            vec![reliable, unknown]
+        );
+    }
+
+    #[test]
+    fn test_rejections_do_not_lower_the_score() {
+        let now = Instant::now();
+        let mut tracker = ReputationTracker::default();
+        let peer = PeerId::random();
+        tracker.record_success(peer, Duration::ZERO, now);
+        for _ in 0..8 {
+            tracker.record_rejection(peer, now);
+        }
+        let reputation = tracker.get(&peer, now);
+        assert_eq!((reputation.failures, reputation.rejections), (0, 8));
+        assert_eq!(reputation.score(), 2.0 / 3.0);
+    }
+
+    #[test]
+    fn test_old_outcomes_decay() {
+        let now = Instant::now();
+        let mut tracker = ReputationTracker::default();
+        let peer = PeerId::random();
+        for _ in 0..8 {
+            tracker.record_failure(peer, now);
+        }
+        assert_eq!(tracker.get(&peer, now + HALF_LIFE / 2).failures, 8);
+        assert_eq!(tracker.get(&peer, now + HALF_LIFE).failures, 4);
+        assert_eq!(tracker.get(&peer, now + HALF_LIFE * 2).failures, 2);
+
+        // a recovered peer is judged by its recent successes
+        let later = now + HALF_LIFE * 3;
+        tracker.record_success(peer, Duration::from_millis(20), later);
+        let reputation = tracker.get(&peer, later);
+        assert_eq!((reputation.successes, reputation.failures), (1, 1));
+        assert_eq!(tracker.get(&peer, later + HALF_LIFE * 100).failures, 0);
+    }
+
+    #[test]
+    fn test_least_recently_seen_peer_is_forgotten() {
+        let now = Instant::now();
+        let mut tracker = ReputationTracker::default();
+        let first = PeerId::random();
+        tracker.record_failure(first, now);
+        for index in 1..MAX_TRACKED_PEERS {
+            tracker.record_failure(PeerId::random(), now + Duration::from_millis(index as u64));
+        }
+        assert_eq!(tracker.peers.len(), MAX_TRACKED_PEERS);
+        assert_eq!(tracker.get(&first, now).failures, 1);
+
+        let newcomer = PeerId::random();
+        tracker.record_failure(newcomer, now + Duration::from_secs(60));
+        assert_eq!(tracker.peers.len(), MAX_TRACKED_PEERS);
+        assert_eq!(tracker.get(&first, now), Reputation::default());
+        assert_eq!(tracker.get(&newcomer, now).failures, 1);
+    }
+}
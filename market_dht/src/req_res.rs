@@ -1,10 +1,23 @@
-use std::collections::HashSet;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
 
 use anyhow::Result;
-use libp2p::{Multiaddr, PeerId};
+use libp2p::{kad::Quorum, Multiaddr, PeerId};
+use thiserror::Error;
 use tokio::sync::oneshot::{self};
+use tracing::{debug, Span};
 
-use crate::behaviour::file_req_res::{FileHash, FileMetadata, SupplierInfo};
+use crate::address::SupplierAddr;
+use crate::behaviour::file_req_res::{FileMetadata, SupplierInfo};
+use crate::file_hash::FileHash;
+use crate::metrics::RequestTimer;
+use crate::peer::PeerError;
+use crate::rate_limit::InboundMetrics;
+use crate::reputation::Reputation;
+use crate::stats::ListingStats;
 
 pub(crate) type Response = Result<ResponseData>;
 pub(crate) type Request = (RequestData, RequestHandler);
@@ -15,22 +28,32 @@ pub(crate) struct ResponseHandler {
 }
 
 impl ResponseHandler {
+    /// The coordinator only ever drops a request without answering it when it shuts down.
     pub(crate) async fn get_response_data(self) -> Response {
-        self.inner.await?
+        self.inner.await.map_err(|_| PeerError::ShutDown)?
     }
 }
 
+/// Answers a request once the coordinator is done with it. Requests are handled in a span that
+/// is a child of the span they were sent from, so that a caller like a gRPC handler can be
+/// correlated with the queries it leads to.
 #[derive(Debug)]
 pub(crate) struct RequestHandler {
     inner: oneshot::Sender<Response>,
+    timer: Option<RequestTimer>,
+    span: Span,
+    request_id: Option<String>,
 }
 
 impl RequestHandler {
-    pub(crate) fn new() -> (Self, ResponseHandler) {
+    pub(crate) fn new(request_id: Option<String>) -> (Self, ResponseHandler) {
         let (response_sender, response_receiver) = tokio::sync::oneshot::channel();
         (
             Self {
                 inner: response_sender,
+                timer: None,
+                span: Span::current(),
+                request_id,
             },
             ResponseHandler {
                 inner: response_receiver,
@@ -38,10 +61,42 @@ impl RequestHandler {
         )
     }
 
+    /// Records how long the request takes to answer once it is.
+    pub(crate) fn timed(self, timer: RequestTimer) -> Self {
+        Self {
+            timer: Some(timer),
+            ..self
+        }
+    }
+
+    /// Moves the handler into `span`, which the coordinator derives from the current one.
+    pub(crate) fn in_span(self, span: Span) -> Self {
+        Self { span, ..self }
+    }
+
+    pub(crate) const fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// The id that the requester tagged the request with, see
+    /// [`crate::peer::Peer::with_request_id`].
+    pub(crate) fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
     pub(crate) fn respond(self, response: Response) {
-        self.inner
-            .send(response)
-            .expect("it to send since oneshot client should not have dropped")
+        let Self {
+            inner, timer, span, ..
+        } = self;
+        span.in_scope(|| debug!(ok = response.is_ok(), "Request answered"));
+        if let Some(timer) = timer {
+            timer.finish(response.is_ok());
+        }
+        // NOTE: the requester may have given up on the response, e.g. on a timeout or a cancelled
+        // gRPC call, which must not take the node down
+        if inner.send(response).is_err() {
+            span.in_scope(|| debug!("Requester went away before the request was answered"));
+        }
     }
 }
 
@@ -51,9 +106,61 @@ pub(crate) enum RequestData {
     GetAllListeners,
     GetConnectedPeers,
     IsConnectedTo(PeerId),
-    GetLocalSupplierInfo { file_hash: FileHash },
+    BlockPeer(PeerId),
+    UnblockPeer(PeerId),
+    GetBlockedPeers,
+    GetEvictions,
+    GetMetrics,
+    GetLocalListings,
+    GetListingStats,
+    DetectSupplierAddr,
+    Shutdown,
+    GetLocalSupplierInfo {
+        file_hash: FileHash,
+    },
+    UpdateListing {
+        file_hash: FileHash,
+        update: ListingUpdate,
+    },
     KadRequest(KadRequestData),
     ReqResRequest(FileReqResRequestData),
+    FileTransferRequest(FileTransferRequestData),
+}
+
+impl RequestData {
+    /// The name that the request is counted under in the metrics.
+    pub(crate) const fn name(&self) -> &'static str {
+        match self {
+            Self::GetAllListeners => "get_all_listeners",
+            Self::GetConnectedPeers => "get_connected_peers",
+            Self::IsConnectedTo(_) => "is_connected_to",
+            Self::BlockPeer(_) => "block_peer",
+            Self::UnblockPeer(_) => "unblock_peer",
+            Self::GetBlockedPeers => "get_blocked_peers",
+            Self::GetEvictions => "get_evictions",
+            Self::GetMetrics => "get_metrics",
+            Self::GetLocalListings => "get_local_listings",
+            Self::GetListingStats => "get_listing_stats",
+            Self::DetectSupplierAddr => "detect_supplier_addr",
+            Self::Shutdown => "shutdown",
+            Self::GetLocalSupplierInfo { .. } => "get_local_supplier_info",
+            Self::UpdateListing { .. } => "update_listing",
+            Self::KadRequest(request) => match request {
+                KadRequestData::ClosestLocalPeers { .. } => "closest_local_peers",
+                KadRequestData::ClosestPeers { .. } => "closest_peers",
+                KadRequestData::RegisterFile { .. } => "register_file",
+                KadRequestData::GetProviders { .. } => "get_providers",
+                KadRequestData::PutRecord { .. } => "put_record",
+                KadRequestData::GetRecord { .. } => "get_record",
+                KadRequestData::RemoveRecord { .. } => "remove_record",
+                KadRequestData::IndexFile { .. } => "index_file",
+            },
+            Self::ReqResRequest(FileReqResRequestData::GetSupplierInfo { .. }) => {
+                "get_supplier_info"
+            }
+            Self::FileTransferRequest(FileTransferRequestData::GetChunk { .. }) => "get_chunk",
+        }
+    }
 }
 
 // FIXIT: this is probably bad since now the end user can also see some of the response data that
@@ -63,21 +170,178 @@ pub(crate) enum RequestData {
 pub enum ResponseData {
     // NOTE: the vec is useful for now when we add functionality for users being able to add
     // listeners?
-    AllListeners { listeners: Vec<Multiaddr> },
-    ConnectedPeers { connected_peers: Vec<PeerId> },
-    IsConnectedTo { is_connected: bool },
+    AllListeners {
+        listeners: Vec<Multiaddr>,
+    },
+    ConnectedPeers {
+        connected_peers: Vec<PeerId>,
+    },
+    IsConnectedTo {
+        is_connected: bool,
+    },
     KadResponse(KadResponseData),
     ReqResResponse(FileReqResResponseData),
-    GetLocalSupplierInfo { supplier_info: Option<SupplierInfo> },
+    GetLocalSupplierInfo {
+        supplier_info: Option<SupplierInfo>,
+    },
+    FileTransferResponse(FileTransferResponseData),
+    Evictions {
+        evictions: Vec<Eviction>,
+    },
+    UpdateListing {
+        old: SupplierInfo,
+        new: SupplierInfo,
+    },
+    LocalListings {
+        listings: Vec<ListingInfo>,
+    },
+    DetectSupplierAddr {
+        addr: Option<SupplierAddr>,
+    },
+    Metrics {
+        metrics: Metrics,
+    },
+    BlockedPeers {
+        blocked_peers: Vec<PeerId>,
+    },
+    SearchResults {
+        file_hashes: Vec<FileHash>,
+    },
+    ListingStats {
+        stats: ListingStats,
+    },
+    Shutdown,
+}
+
+/// Counters that describe how the node has been doing since it started.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Metrics {
+    /// Supplier info requests from remote peers.
+    pub file_req_res: InboundMetrics,
+    /// Provider records and records that remote peers stored with this node.
+    pub kademlia: InboundMetrics,
+    pub request_queue: RequestQueueMetrics,
+}
+
+/// The requests that [`crate::peer::Peer`] handed to the node and that are yet to be picked up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestQueueMetrics {
+    pub depth: usize,
+    pub capacity: usize,
+}
+
+/// A file that this node supplies, as reported by [`crate::peer::Peer::local_listings`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListingInfo {
+    pub file_hash: FileHash,
+    pub supplier_info: SupplierInfo,
+    pub registered_at: SystemTime,
+    pub expires_at: SystemTime,
+    pub last_publish: PublishStatus,
+}
+
+/// Outcome of the most recent attempt to publish or republish the provider record of a listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PublishStatus {
+    /// No publish has completed since the listing was registered or restored from the registry.
+    Pending,
+    Succeeded {
+        at: SystemTime,
+    },
+    Failed {
+        at: SystemTime,
+        error: String,
+    },
+}
+
+/// A partial update of a local listing. Fields that are `None` are left untouched.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListingUpdate {
+    pub price: Option<i64>,
+    pub addr: Option<SupplierAddr>,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+}
+
+impl ListingUpdate {
+    pub(crate) fn apply(self, supplier_info: &mut SupplierInfo) {
+        if let Some(price) = self.price {
+            supplier_info.price = price;
+        }
+        if let Some(addr) = self.addr {
+            supplier_info.addr = addr;
+        }
+        if let Some(port) = self.port {
+            supplier_info.port = port;
+        }
+        if let Some(username) = self.username {
+            supplier_info.username = username;
+        }
+    }
+}
+
+/// Why a request about the listings of this node was refused. It comes wrapped in the
+/// [`anyhow::Error`] of a [`Response`] and can be told apart with
+/// [`anyhow::Error::downcast_ref`].
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ListingError {
+    #[error("Reached the maximum of {max_listings} listings for this node")]
+    ListingLimitReached { max_listings: usize },
+    #[error("File {file_hash} is not listed by this node")]
+    NotListed { file_hash: FileHash },
+}
+
+/// A listing that the node removed on its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Eviction {
+    pub file_hash: FileHash,
+    pub reason: EvictionReason,
+    pub evicted_at: SystemTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EvictionReason {
+    Expired,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[non_exhaustive]
 pub(crate) enum KadRequestData {
-    ClosestLocalPeers { key: Vec<u8> },
-    ClosestPeers { key: Vec<u8> },
-    RegisterFile { file_metadata: FileMetadata },
-    GetProviders { key: Vec<u8> },
+    ClosestLocalPeers {
+        key: Vec<u8>,
+    },
+    ClosestPeers {
+        key: Vec<u8>,
+    },
+    RegisterFile {
+        file_metadata: FileMetadata,
+        // NOTE: already checked against the file transfer directory by the peer
+        served_path: Option<PathBuf>,
+    },
+    GetProviders {
+        file_hash: FileHash,
+    },
+    PutRecord {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        quorum: Quorum,
+        ttl: Option<Duration>,
+    },
+    GetRecord {
+        key: Vec<u8>,
+        quorum: Quorum,
+    },
+    RemoveRecord {
+        key: Vec<u8>,
+    },
+    IndexFile {
+        file_hash: FileHash,
+        keywords: Vec<String>,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -91,18 +355,49 @@ pub enum KadResponseData {
         peers: Vec<PeerId>,
     },
     RegisterFile {
-        key: Vec<u8>,
+        key: FileHash,
     },
+    /// The providers are ranked like [`FileReqResResponseData::GetSuppliers`], leaving out blocked
+    /// peers and the ones that score too low.
     GetProviders {
+        file_hash: FileHash,
+        providers: Vec<(PeerId, Reputation)>,
+    },
+    PutRecord {
         key: Vec<u8>,
-        providers: HashSet<PeerId>,
     },
+    GetRecord {
+        records: Vec<DhtRecord>,
+    },
+    RemoveRecord {
+        key: Vec<u8>,
+    },
+    IndexFile {
+        keywords: Vec<String>,
+    },
+}
+
+/// A record that was stored in the DHT with [`crate::peer::Peer::put_record`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhtRecord {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    /// The peer that put the record. Until the record expires, nodes only store copies that come
+    /// from this peer, and [`crate::peer::Peer::remove_record`] only removes records that the node
+    /// published itself.
+    pub publisher: Option<PeerId>,
+    pub expires_at: Option<SystemTime>,
+    /// The peer that returned the record, `None` if it came from the store of this node.
+    pub from: Option<PeerId>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[non_exhaustive]
 pub(crate) enum FileReqResRequestData {
-    GetSupplierInfo { file_hash: Vec<u8>, peer_id: PeerId },
+    GetSupplierInfo {
+        file_hash: FileHash,
+        peer_id: PeerId,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -111,7 +406,46 @@ pub enum FileReqResResponseData {
     GetSupplierInfo {
         supplier_info: SupplierInfo,
     },
+    /// Remote suppliers come first, from the best to the worst reputation, followed by this node
+    /// if it supplies the file too. This node has no reputation.
     GetSuppliers {
         suppliers: Vec<(PeerId, SupplierInfo)>,
+        reputations: HashMap<PeerId, Reputation>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub(crate) enum FileTransferRequestData {
+    GetChunk {
+        file_hash: FileHash,
+        peer_id: PeerId,
+        index: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FileTransferResponseData {
+    Chunk {
+        data: Vec<u8>,
+        file_size: u64,
+    },
+    DownloadFile {
+        file_hash: FileHash,
+        path: PathBuf,
+        num_bytes: u64,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{RequestHandler, ResponseData};
+
+    #[test]
+    fn test_respond_after_the_requester_went_away() {
+        let (request_handler, response_handler) = RequestHandler::new(None);
+        drop(response_handler);
+        request_handler.respond(Ok(ResponseData::Shutdown));
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::{file_hash::FileHash, net::PROVIDER_RECORD_TTL};
+
+const POSTING_KEY_PREFIX: &str = "/orcanet/search/";
+// NOTE: keeps a posting record well below the maximum Kademlia packet size
+const MAX_POSTINGS: usize = 128;
+// NOTE: keeps a single peer from pushing everyone else out of a keyword
+const MAX_POSTINGS_PER_PUBLISHER: usize = 16;
+const MIN_KEYWORD_LEN: usize = 2;
+
+/// The keywords that `texts` are indexed under: lowercase alphanumeric words, sorted and without
+/// duplicates.
+pub(crate) fn keywords<'a>(texts: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut keywords = texts
+        .into_iter()
+        .flat_map(|text| text.split(|c: char| !c.is_alphanumeric()))
+        .filter(|word| word.chars().count() >= MIN_KEYWORD_LEN)
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+    keywords.sort();
+    keywords.dedup();
+    keywords
+}
+
+/// The DHT record key that the postings of `keyword` are stored under.
+pub(crate) fn posting_key(keyword: &str) -> Vec<u8> {
+    format!("{POSTING_KEY_PREFIX}{keyword}").into_bytes()
+}
+
+pub(crate) fn is_posting_key(key: &[u8]) -> bool {
+    key.starts_with(POSTING_KEY_PREFIX.as_bytes())
+}
+
+/// The files listed under one keyword along with who published each posting and when it expires.
+/// Every supplier publishes its own postings, so peers merge the postings they receive instead of
+/// replacing them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Postings {
+    inner: HashMap<(PeerId, FileHash), SystemTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WirePosting {
+    publisher: String,
+    file_hash: String,
+    expires_at: u64,
+}
+
+impl Postings {
+    /// Invalid postings are skipped rather than failing the whole record.
+    pub(crate) fn decode(value: &[u8]) -> Self {
+        let postings: Vec<WirePosting> = serde_json::from_slice(value).unwrap_or_default();
+        let inner = postings
+            .into_iter()
+            .filter_map(|posting| {
+                Some((
+                    (
+                        posting.publisher.parse().ok()?,
+                        FileHash::from_hex(&posting.file_hash).ok()?,
+                    ),
+                    UNIX_EPOCH + Duration::from_secs(posting.expires_at),
+                ))
+            })
+            .collect();
+        Self { inner }
+    }
+
+    /// Leaves out expired postings, and the ones that expire first if a publisher or the whole
+    /// record has too many.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let now = SystemTime::now();
+        let mut postings = self
+            .inner
+            .iter()
+            .filter(|(_, expires_at)| **expires_at > now)
+            .collect::<Vec<_>>();
+        postings.sort_by(|((a_publisher, a_hash), a), ((b_publisher, b_hash), b)| {
+            b.cmp(a)
+                .then_with(|| a_hash.0.cmp(&b_hash.0))
+                .then_with(|| a_publisher.cmp(b_publisher))
+        });
+        let mut per_publisher = HashMap::<&PeerId, usize>::new();
+        let postings = postings
+            .into_iter()
+            .filter(|((publisher, _), _)| {
+                let count = per_publisher.entry(publisher).or_default();
+                *count += 1;
+                *count <= MAX_POSTINGS_PER_PUBLISHER
+            })
+            .take(MAX_POSTINGS)
+            .map(|((publisher, file_hash), expires_at)| WirePosting {
+                publisher: publisher.to_base58(),
+                file_hash: file_hash.to_hex(),
+                expires_at: expires_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+            })
+            .collect::<Vec<_>>();
+        serde_json::to_vec(&postings).expect("postings to serialize")
+    }
+
+    /// Postings live as long as the provider record of their file at most, so later expiries are
+    /// cut short rather than trusted.
+    pub(crate) fn insert(
+        &mut self,
+        publisher: PeerId,
+        file_hash: FileHash,
+        expires_at: SystemTime,
+    ) {
+        let expires_at = expires_at.min(SystemTime::now() + PROVIDER_RECORD_TTL);
+        let entry = self
+            .inner
+            .entry((publisher, file_hash))
+            .or_insert(expires_at);
+        *entry = (*entry).max(expires_at);
+    }
+
+    pub(crate) fn merge(&mut self, other: Self) {
+        for ((publisher, file_hash), expires_at) in other.inner {
+            self.insert(publisher, file_hash, expires_at);
+        }
+    }
+
+    /// Only the postings that `publisher` published itself. Peers take nothing else from a
+    /// publisher, since anyone can claim to pass on the postings of others.
+    pub(crate) fn published_by(mut self, publisher: PeerId) -> Self {
+        self.inner
+            .retain(|(posting_publisher, _), _| *posting_publisher == publisher);
+        self
+    }
+
+    pub(crate) fn file_hashes(&self) -> HashSet<FileHash> {
+        let now = SystemTime::now();
+        self.inner
+            .iter()
+            .filter(|(_, expires_at)| **expires_at > now)
+            .map(|((_, file_hash), _)| file_hash.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        time::{Duration, SystemTime},
+    };
+
+    use libp2p::PeerId;
+    use pretty_assertions::assert_eq;
+
+    use super::{keywords, Postings, MAX_POSTINGS_PER_PUBLISHER};
+    use crate::{file_hash::FileHash, net::PROVIDER_RECORD_TTL};
+
+    #[test]
+    fn test_keywords() {
+        assert_eq!(
+            keywords(["The_Rust-Book (2nd ed).PDF", "rust", "a"]),
+            vec!["2nd", "book", "ed", "pdf", "rust", "the"]
+        );
+    }
+
+    #[test]
+    fn test_merge_and_expire_postings() {
+        let now = SystemTime::now();
+        let (a, b, c) = (
+            FileHash::from_digest([1u8; 32]).unwrap(),
+            FileHash::from_digest([2u8; 32]).unwrap(),
+            FileHash::from_digest([3u8; 32]).unwrap(),
+        );
+        let publisher = PeerId::random();
+        let mut postings = Postings::default();
+        postings.insert(publisher, a.clone(), now + Duration::from_secs(60));
+        postings.insert(publisher, c, now - Duration::from_secs(1));
+        let mut other = Postings::default();
+        other.insert(PeerId::random(), b.clone(), now + Duration::from_secs(60));
+        postings.merge(Postings::decode(&other.encode()));
+        assert_eq!(
+            Postings::decode(&postings.encode()).file_hashes(),
+            HashSet::from([a, b])
+        );
+        assert_eq!(Postings::decode(b"junk"), Postings::default());
+    }
+
+    #[test]
+    fn test_posting_expiries_are_clamped() {
+        let file_hash = FileHash::from_digest([1u8; 32]).unwrap();
+        let mut far_future = Postings::default();
+        far_future.inner.insert(
+            (PeerId::random(), file_hash.clone()),
+            SystemTime::now() + Duration::from_secs(60 * 60 * 24 * 365),
+        );
+        let mut postings = Postings::default();
+        postings.merge(Postings::decode(&far_future.encode()));
+        assert_eq!(
+            postings
+                .inner
+                .values()
+                .all(|expires_at| *expires_at <= SystemTime::now() + PROVIDER_RECORD_TTL),
+            true
+        );
+    }
+
+    #[test]
+    fn test_one_publisher_cannot_fill_a_keyword() {
+        let now = SystemTime::now();
+        let (honest, flooder) = (PeerId::random(), PeerId::random());
+        let real = FileHash::from_digest([0u8; 32]).unwrap();
+        let mut postings = Postings::default();
+        postings.insert(honest, real.clone(), now + Duration::from_secs(60));
+        let mut flood = Postings::default();
+        for i in 1..=u8::MAX {
+            flood.insert(
+                flooder,
+                FileHash::from_digest([i; 32]).unwrap(),
+                now + PROVIDER_RECORD_TTL,
+            );
+        }
+        // the flooder also claims to pass on a posting of the honest peer
+        flood.insert(honest, FileHash::from_digest([1u8; 32]).unwrap(), now);
+        postings.merge(flood.published_by(flooder));
+
+        let postings = Postings::decode(&postings.encode());
+        assert_eq!(postings.inner.len(), MAX_POSTINGS_PER_PUBLISHER + 1);
+        assert_eq!(postings.file_hashes().contains(&real), true);
+        assert_eq!(
+            postings
+                .inner
+                .keys()
+                .filter(|(publisher, _)| *publisher == honest)
+                .count(),
+            1
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use libp2p::PeerId;
+
+use crate::file_hash::FileHash;
+
+const BUCKET: Duration = Duration::from_secs(60);
+const HOUR_BUCKETS: u64 = 60;
+const DAY_BUCKETS: u64 = 60 * 24;
+// NOTE: the peers that were least recently heard from are forgotten past this
+const MAX_TRACKED_PEERS: usize = 1024;
+
+/// How many inbound requests of each kind were counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestCounts {
+    /// Requests for the supplier info of a listing.
+    pub supplier_info: u64,
+    /// Requests for the provider records of a listing.
+    pub provider_lookups: u64,
+}
+
+impl RequestCounts {
+    const fn add(&mut self, other: &Self) {
+        self.supplier_info += other.supplier_info;
+        self.provider_lookups += other.provider_lookups;
+    }
+}
+
+/// [`RequestCounts`] over the last hour, the last day and since the node started. The windows
+/// are made up of whole minutes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WindowedCounts {
+    pub last_hour: RequestCounts,
+    pub last_day: RequestCounts,
+    pub total: RequestCounts,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStats {
+    pub file_hash: FileHash,
+    pub requests: WindowedCounts,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerStats {
+    pub peer_id: PeerId,
+    pub requests: WindowedCounts,
+}
+
+/// How often the listings of this node were requested, see
+/// [`crate::peer::Peer::listing_stats`]. Both lists are ordered from the most to the least
+/// requests in total.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListingStats {
+    /// Every current listing, including the ones that were never requested.
+    pub files: Vec<FileStats>,
+    /// The peers that requested the supplier info of a listing.
+    pub peers: Vec<PeerStats>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RequestKind {
+    SupplierInfo,
+    ProviderLookup,
+}
+
+/// Per minute counts of the last day along with the total.
+#[derive(Debug, Default)]
+struct Counter {
+    buckets: VecDeque<(u64, RequestCounts)>,
+    total: RequestCounts,
+}
+
+impl Counter {
+    fn record(&mut self, kind: RequestKind, bucket: u64) {
+        if self.buckets.back().is_none_or(|(last, _)| *last != bucket) {
+            self.buckets.push_back((bucket, RequestCounts::default()));
+        }
+        while self
+            .buckets
+            .front()
+            .is_some_and(|(first, _)| first + DAY_BUCKETS <= bucket)
+        {
+            self.buckets.pop_front();
+        }
+        let (_, counts) = self
+            .buckets
+            .back_mut()
+            .expect("the current bucket to exist");
+        let increment = match kind {
+            RequestKind::SupplierInfo => RequestCounts {
+                supplier_info: 1,
+                provider_lookups: 0,
+            },
+            RequestKind::ProviderLookup => RequestCounts {
+                supplier_info: 0,
+                provider_lookups: 1,
+            },
+        };
+        counts.add(&increment);
+        self.total.add(&increment);
+    }
+
+    fn last_bucket(&self) -> Option<u64> {
+        self.buckets.back().map(|(bucket, _)| *bucket)
+    }
+
+    fn windowed(&self, bucket: u64) -> WindowedCounts {
+        let mut windowed = WindowedCounts {
+            total: self.total,
+            ..Default::default()
+        };
+        for (start, counts) in &self.buckets {
+            if start + DAY_BUCKETS > bucket {
+                windowed.last_day.add(counts);
+            }
+            if start + HOUR_BUCKETS > bucket {
+                windowed.last_hour.add(counts);
+            }
+        }
+        windowed
+    }
+}
+
+/// Counts the inbound requests for the listings of this node per file and per requesting peer.
+#[derive(Debug)]
+pub(crate) struct RequestStats {
+    started: Instant,
+    files: HashMap<FileHash, Counter>,
+    peers: HashMap<PeerId, Counter>,
+}
+
+impl Default for RequestStats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            files: HashMap::new(),
+            peers: HashMap::new(),
+        }
+    }
+}
+
+impl RequestStats {
+    pub(crate) fn record(&mut self, kind: RequestKind, file_hash: &FileHash, peer: Option<PeerId>) {
+        self.record_at(kind, file_hash, peer, Instant::now())
+    }
+
+    fn record_at(
+        &mut self,
+        kind: RequestKind,
+        file_hash: &FileHash,
+        peer: Option<PeerId>,
+        now: Instant,
+    ) {
+        let bucket = self.bucket(now);
+        self.files
+            .entry(file_hash.clone())
+            .or_default()
+            .record(kind, bucket);
+        if let Some(peer) = peer {
+            if self.peers.len() >= MAX_TRACKED_PEERS && !self.peers.contains_key(&peer) {
+                let least_recent = self
+                    .peers
+                    .iter()
+                    .min_by_key(|(_, counter)| counter.last_bucket())
+                    .map(|(peer, _)| *peer);
+                if let Some(least_recent) = least_recent {
+                    self.peers.remove(&least_recent);
+                }
+            }
+            self.peers.entry(peer).or_default().record(kind, bucket);
+        }
+    }
+
+    /// Forgets the counts of a file that is no longer listed.
+    pub(crate) fn remove_file(&mut self, file_hash: &FileHash) {
+        self.files.remove(file_hash);
+    }
+
+    pub(crate) fn stats<'a>(&self, listed: impl Iterator<Item = &'a FileHash>) -> ListingStats {
+        self.stats_at(listed, Instant::now())
+    }
+
+    fn stats_at<'a>(
+        &self,
+        listed: impl Iterator<Item = &'a FileHash>,
+        now: Instant,
+    ) -> ListingStats {
+        let bucket = self.bucket(now);
+        let mut files = listed
+            .map(|file_hash| FileStats {
+                file_hash: file_hash.clone(),
+                requests: self
+                    .files
+                    .get(file_hash)
+                    .map(|counter| counter.windowed(bucket))
+                    .unwrap_or_default(),
+            })
+            .collect::<Vec<_>>();
+        files.sort_by_key(|file| std::cmp::Reverse(total(&file.requests)));
+        let mut peers = self
+            .peers
+            .iter()
+            .map(|(peer_id, counter)| PeerStats {
+                peer_id: *peer_id,
+                requests: counter.windowed(bucket),
+            })
+            .collect::<Vec<_>>();
+        peers.sort_by_key(|peer| std::cmp::Reverse(total(&peer.requests)));
+        ListingStats { files, peers }
+    }
+
+    fn bucket(&self, now: Instant) -> u64 {
+        (now.saturating_duration_since(self.started).as_secs()) / BUCKET.as_secs()
+    }
+}
+
+const fn total(requests: &WindowedCounts) -> u64 {
+    requests.total.supplier_info + requests.total.provider_lookups
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use libp2p::PeerId;
+    use pretty_assertions::assert_eq;
+
+    use super::{RequestCounts, RequestKind, RequestStats, WindowedCounts};
+    use crate::file_hash::FileHash;
+
+    #[test]
+    fn test_windowed_counts() {
+        let mut stats = RequestStats::default();
+        let start = stats.started;
+        let (popular, unpopular) = (
+            FileHash::from_digest([1u8; 32]).unwrap(),
+            FileHash::from_digest([2u8; 32]).unwrap(),
+        );
+        let peer = PeerId::random();
+        stats.record_at(RequestKind::SupplierInfo, &popular, Some(peer), start);
+        stats.record_at(
+            RequestKind::ProviderLookup,
+            &popular,
+            None,
+            start + Duration::from_secs(60 * 90),
+        );
+        stats.record_at(
+            RequestKind::SupplierInfo,
+            &popular,
+            Some(peer),
+            start + Duration::from_secs(60 * 60 * 24),
+        );
+
+        let listed = [popular.clone(), unpopular.clone()];
+        let now = start + Duration::from_secs(60 * 60 * 24 + 60 * 30);
+        let listing_stats = stats.stats_at(listed.iter(), now);
+        let files = listing_stats
+            .files
+            .iter()
+            .map(|file| (file.file_hash.clone(), file.requests))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            vec![
+                (
+                    popular,
+                    WindowedCounts {
+                        last_hour: RequestCounts {
+                            supplier_info: 1,
+                            provider_lookups: 0
+                        },
+                        last_day: RequestCounts {
+                            supplier_info: 1,
+                            provider_lookups: 1
+                        },
+                        total: RequestCounts {
+                            supplier_info: 2,
+                            provider_lookups: 1
+                        },
+                    }
+                ),
+                (unpopular, WindowedCounts::default()),
+            ]
+        );
+        assert_eq!(listing_stats.peers.len(), 1);
+        assert_eq!(listing_stats.peers[0].peer_id, peer);
+        assert_eq!(listing_stats.peers[0].requests.total.supplier_info, 2);
+        assert_eq!(stats.stats_at([].iter(), Instant::now()).files, vec![]);
+    }
+}
@@ -0,0 +1,379 @@
+//! Defenses against nodes that spin up many identities to crowd honest suppliers out of the DHT.
+//! They are configured with [`crate::config::ConfigBuilder::with_sybil_limits`].
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    time::Instant,
+};
+
+use libp2p::{identity::Keypair, Multiaddr, PeerId};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::{address::multiaddr_ip, config::SybilLimits, net::PROVIDER_RECORD_TTL};
+
+// NOTE: the addresses of providers are only pruned once there is a fair amount of them
+const PRUNE_THRESHOLD: usize = 4096;
+
+/// The number of leading zero bits of the SHA-256 hash of `peer_id`, which is what
+/// [`SybilLimits::peer_id_difficulty`] is checked against.
+pub fn peer_id_work(peer_id: &PeerId) -> u32 {
+    let mut work = 0;
+    for byte in Sha256::digest(peer_id.to_bytes()) {
+        work += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    work
+}
+
+/// Generates ed25519 key pairs until one of them has a PeerId with at least `difficulty` bits of
+/// work. Every extra bit doubles how long this takes on average.
+pub fn generate_identity(difficulty: u32) -> Keypair {
+    loop {
+        let keypair = Keypair::generate_ed25519();
+        if peer_id_work(&keypair.public().to_peer_id()) >= difficulty {
+            return keypair;
+        }
+    }
+}
+
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SybilRejection {
+    #[error("PeerId does not have enough proof of work")]
+    InsufficientWork,
+    #[error("Too many providers of the key share the IP address {0}")]
+    TooManyFromIp(IpAddr),
+    #[error("Too many providers of the key share the subnet of {0}")]
+    TooManyFromSubnet(IpAddr),
+    #[error("Too many providers of the key have no known IP address")]
+    TooManyWithoutIp,
+}
+
+/// How many provider records were dropped for each reason since the node started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ProviderRejections {
+    pub(crate) insufficient_work: u64,
+    pub(crate) too_many_from_ip: u64,
+    pub(crate) too_many_from_subnet: u64,
+    pub(crate) too_many_without_ip: u64,
+}
+
+impl ProviderRejections {
+    const fn record(&mut self, rejection: SybilRejection) {
+        let count = match rejection {
+            SybilRejection::InsufficientWork => &mut self.insufficient_work,
+            SybilRejection::TooManyFromIp(_) => &mut self.too_many_from_ip,
+            SybilRejection::TooManyFromSubnet(_) => &mut self.too_many_from_subnet,
+            SybilRejection::TooManyWithoutIp => &mut self.too_many_without_ip,
+        };
+        *count += 1;
+    }
+}
+
+/// What to do with a peer that was just added to the routing table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Admission {
+    Keep,
+    Remove(SybilRejection),
+    /// The peer has not been connected for long enough. It is added back once it has.
+    Defer,
+}
+
+#[derive(Debug)]
+struct ConnectedPeer {
+    ip: Option<IpAddr>,
+    since: Instant,
+}
+
+/// Enforces [`SybilLimits`] on the routing table and on inbound provider records.
+#[derive(Debug)]
+pub(crate) struct SybilGuard {
+    limits: SybilLimits,
+    // boot nodes are trusted to be routable right away
+    trusted: HashSet<PeerId>,
+    connected: HashMap<PeerId, ConnectedPeer>,
+    // the address that each provider record was received from, if it had one
+    provider_ips: HashMap<PeerId, (Option<IpAddr>, Instant)>,
+    deferred: HashMap<PeerId, Vec<Multiaddr>>,
+    rejected_providers: ProviderRejections,
+}
+
+impl SybilGuard {
+    pub(crate) fn new(limits: SybilLimits, trusted: impl IntoIterator<Item = PeerId>) -> Self {
+        Self {
+            limits,
+            trusted: trusted.into_iter().collect(),
+            connected: HashMap::new(),
+            provider_ips: HashMap::new(),
+            deferred: HashMap::new(),
+            rejected_providers: ProviderRejections::default(),
+        }
+    }
+
+    pub(crate) fn connection_established(&mut self, peer: PeerId, addr: &Multiaddr, now: Instant) {
+        self.connected.entry(peer).or_insert(ConnectedPeer {
+            ip: multiaddr_ip(addr),
+            since: now,
+        });
+    }
+
+    pub(crate) fn connection_closed(&mut self, peer: &PeerId) {
+        self.connected.remove(peer);
+        self.deferred.remove(peer);
+    }
+
+    fn has_enough_work(&self, peer: &PeerId) -> bool {
+        self.limits.peer_id_difficulty == 0
+            || self.trusted.contains(peer)
+            || peer_id_work(peer) >= self.limits.peer_id_difficulty
+    }
+
+    /// Decides whether `peer` can stay in the routing table. Deferred peers are remembered along
+    /// with `addresses` until [`SybilGuard::take_matured`] hands them back.
+    pub(crate) fn admit_route(
+        &mut self,
+        peer: PeerId,
+        is_new_peer: bool,
+        addresses: impl IntoIterator<Item = Multiaddr>,
+        now: Instant,
+    ) -> Admission {
+        if !self.has_enough_work(&peer) {
+            return Admission::Remove(SybilRejection::InsufficientWork);
+        }
+        if !is_new_peer || self.trusted.contains(&peer) {
+            return Admission::Keep;
+        }
+        let is_mature = self.connected.get(&peer).is_some_and(|connected| {
+            now.duration_since(connected.since) >= self.limits.min_routing_age
+        });
+        if is_mature {
+            return Admission::Keep;
+        }
+        let deferred = self.deferred.entry(peer).or_default();
+        for addr in addresses {
+            if !deferred.contains(&addr) {
+                deferred.push(addr);
+            }
+        }
+        Admission::Defer
+    }
+
+    /// The deferred peers that have now been connected for long enough to be routed to.
+    pub(crate) fn take_matured(&mut self, now: Instant) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        let min_routing_age = self.limits.min_routing_age;
+        let matured = self
+            .deferred
+            .keys()
+            .filter(|peer| {
+                self.connected
+                    .get(peer)
+                    .is_some_and(|connected| now.duration_since(connected.since) >= min_routing_age)
+            })
+            .copied()
+            .collect::<Vec<_>>();
+        matured
+            .into_iter()
+            .filter_map(|peer| Some((peer, self.deferred.remove(&peer)?)))
+            .collect()
+    }
+
+    /// Checks a provider record from `provider` against the providers that are already stored for
+    /// the same key. Providers without a known IP address, such as peers on transports without
+    /// one, all count as sharing a single address and subnet, so that they cannot get around the
+    /// limits.
+    pub(crate) fn check_provider(
+        &mut self,
+        provider: PeerId,
+        existing_providers: &[PeerId],
+        now: Instant,
+    ) -> Result<(), SybilRejection> {
+        let checked = self.check_provider_limits(provider, existing_providers, now);
+        if let Err(rejection) = checked {
+            self.rejected_providers.record(rejection);
+        }
+        checked
+    }
+
+    pub(crate) const fn rejected_providers(&self) -> ProviderRejections {
+        self.rejected_providers
+    }
+
+    fn check_provider_limits(
+        &mut self,
+        provider: PeerId,
+        existing_providers: &[PeerId],
+        now: Instant,
+    ) -> Result<(), SybilRejection> {
+        if !self.has_enough_work(&provider) {
+            return Err(SybilRejection::InsufficientWork);
+        }
+        let ip = self
+            .connected
+            .get(&provider)
+            .and_then(|connected| connected.ip);
+        if !existing_providers.contains(&provider) {
+            let ips = existing_providers
+                .iter()
+                .filter_map(|peer| self.provider_ips.get(peer))
+                .filter(|(_, at)| now.duration_since(*at) < PROVIDER_RECORD_TTL)
+                .map(|(ip, _)| *ip)
+                .collect::<Vec<_>>();
+            if let Some(max) = self.limits.max_providers_per_ip {
+                if ips.iter().filter(|other| **other == ip).count() >= max {
+                    return Err(ip.map_or(
+                        SybilRejection::TooManyWithoutIp,
+                        SybilRejection::TooManyFromIp,
+                    ));
+                }
+            }
+            if let Some(max) = self.limits.max_providers_per_subnet {
+                let subnet = ip.map(|ip| self.subnet(&ip));
+                if ips
+                    .iter()
+                    .filter(|other| other.map(|other| self.subnet(&other)) == subnet)
+                    .count()
+                    >= max
+                {
+                    return Err(ip.map_or(
+                        SybilRejection::TooManyWithoutIp,
+                        SybilRejection::TooManyFromSubnet,
+                    ));
+                }
+            }
+        }
+        if self.provider_ips.len() >= PRUNE_THRESHOLD {
+            self.provider_ips
+                .retain(|_, (_, at)| now.duration_since(*at) < PROVIDER_RECORD_TTL);
+        }
+        self.provider_ips.insert(provider, (ip, now));
+        Ok(())
+    }
+
+    /// `ip` with everything past the configured subnet prefix zeroed out.
+    fn subnet(&self, ip: &IpAddr) -> IpAddr {
+        match ip {
+            IpAddr::V4(ip) => {
+                let prefix = u32::from(self.limits.ipv4_subnet_prefix.min(32));
+                let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+                IpAddr::V4((u32::from(*ip) & mask).into())
+            }
+            IpAddr::V6(ip) => {
+                let prefix = u32::from(self.limits.ipv6_subnet_prefix.min(128));
+                let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+                IpAddr::V6((u128::from(*ip) & mask).into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use libp2p::{multiaddr::multiaddr, PeerId};
+    use pretty_assertions::assert_eq;
+
+    use super::{generate_identity, peer_id_work, Admission, SybilGuard, SybilRejection};
+    use crate::config::SybilLimits;
+
+    #[test]
+    fn test_provider_limits_per_ip_and_subnet() {
+        let mut guard = SybilGuard::new(
+            SybilLimits {
+                max_providers_per_ip: Some(1),
+                max_providers_per_subnet: Some(2),
+                ..Default::default()
+            },
+            [],
+        );
+        let now = Instant::now();
+        let peers = (0..4).map(|_| PeerId::random()).collect::<Vec<_>>();
+        for (peer, ip) in
+            peers
+                .iter()
+                .zip([[10, 0, 0, 1], [10, 0, 0, 1], [10, 0, 0, 2], [10, 0, 0, 3]])
+        {
+            guard.connection_established(*peer, &multiaddr!(Ip4(ip), Tcp(4000u16)), now);
+        }
+        guard.check_provider(peers[0], &[], now).unwrap();
+        assert_eq!(
+            guard.check_provider(peers[1], &peers[..1], now),
+            Err(SybilRejection::TooManyFromIp([10, 0, 0, 1].into()))
+        );
+        guard.check_provider(peers[2], &peers[..1], now).unwrap();
+        assert_eq!(
+            guard.check_provider(peers[3], &[peers[0], peers[2]], now),
+            Err(SybilRejection::TooManyFromSubnet([10, 0, 0, 3].into()))
+        );
+        // refreshing a record that is already stored is always fine
+        guard
+            .check_provider(peers[0], &[peers[0], peers[2]], now)
+            .unwrap();
+        // and other keys are limited separately
+        guard.check_provider(peers[1], &[], now).unwrap();
+    }
+
+    #[test]
+    fn test_providers_without_ip_share_the_limits() {
+        let mut guard = SybilGuard::new(
+            SybilLimits {
+                max_providers_per_ip: Some(1),
+                ..Default::default()
+            },
+            [],
+        );
+        let now = Instant::now();
+        let peers = (0..3).map(|_| PeerId::random()).collect::<Vec<_>>();
+        guard.connection_established(peers[0], &multiaddr!(Memory(1u64)), now);
+        guard.connection_established(peers[2], &multiaddr!(Ip4([10, 0, 0, 1]), Tcp(4000u16)), now);
+        guard.check_provider(peers[0], &[], now).unwrap();
+        // peers that are not even connected have no known address either
+        assert_eq!(
+            guard.check_provider(peers[1], &peers[..1], now),
+            Err(SybilRejection::TooManyWithoutIp)
+        );
+        guard.check_provider(peers[2], &peers[..1], now).unwrap();
+        assert_eq!(guard.rejected_providers().too_many_without_ip, 1);
+    }
+
+    #[test]
+    fn test_routing_admission() {
+        let mut guard = SybilGuard::new(
+            SybilLimits {
+                peer_id_difficulty: 4,
+                min_routing_age: Duration::from_secs(10),
+                ..Default::default()
+            },
+            [],
+        );
+        let now = Instant::now();
+        let worked = generate_identity(4).public().to_peer_id();
+        assert!(peer_id_work(&worked) >= 4);
+        let lazy = std::iter::repeat_with(PeerId::random)
+            .find(|peer| peer_id_work(peer) < 4)
+            .unwrap();
+        let addr = multiaddr!(Ip4([10, 0, 0, 1]), Tcp(4000u16));
+        guard.connection_established(worked, &addr, now);
+        guard.connection_established(lazy, &addr, now);
+        assert_eq!(
+            guard.admit_route(lazy, true, [addr.clone()], now),
+            Admission::Remove(SybilRejection::InsufficientWork)
+        );
+        assert_eq!(
+            guard.admit_route(worked, true, [addr.clone()], now),
+            Admission::Defer
+        );
+        assert!(guard.take_matured(now + Duration::from_secs(5)).is_empty());
+        assert_eq!(
+            guard.take_matured(now + Duration::from_secs(10)),
+            vec![(worked, vec![addr.clone()])]
+        );
+        assert_eq!(
+            guard.admit_route(worked, true, [addr], now + Duration::from_secs(10)),
+            Admission::Keep
+        );
+    }
+}
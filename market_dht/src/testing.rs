@@ -0,0 +1,385 @@
+//! Networks of nodes in one process for testing, behind the `testing` feature.
+//!
+//! [`TestNetwork`] runs its nodes over libp2p's in-memory transport, see
+//! [`crate::config::Transport::Memory`], so no ports are taken and no sockets are opened. Every
+//! node boots from the first one. Instead of sleeping for a fixed time, tests wait for the state
+//! they need with [`TestNetwork::wait_for_convergence`], [`TestNetwork::assert_holders`] or
+//! [`TestNetwork::wait_until`].
+
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    net::{IpAddr, Ipv4Addr},
+    num::NonZeroUsize,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use anyhow::bail;
+use libp2p::kad::K_VALUE;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::time::{self, Instant};
+
+use crate::{
+    address::SupplierAddr,
+    boot_nodes::BootNodes,
+    config::{Config, ConfigBuilder, Transport},
+    file_hash::FileHash,
+    memory_links, multiaddr,
+    net::{spawn_bridge, NetworkBridgeError},
+    peer::Peer,
+    FileReqResResponseData, KadResponseData, Multiaddr, PeerId, ResponseData,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// The port that test suppliers claim to serve their files from.
+pub const SUPPLIER_PORT: u16 = 8080;
+
+// NOTE: memory ports are shared by the whole process, so every network takes its own
+static NEXT_MEMORY_PORT: AtomicU64 = AtomicU64::new(1);
+
+/// A node of a [`TestNetwork`].
+#[derive(Debug)]
+pub struct TestNode {
+    peer: Peer,
+    addr: Multiaddr,
+    port: u64,
+    live: bool,
+}
+
+impl TestNode {
+    pub const fn peer(&self) -> &Peer {
+        &self.peer
+    }
+
+    pub const fn id(&self) -> &PeerId {
+        self.peer.id()
+    }
+
+    /// The `/memory/<port>` address that the node listens on.
+    pub const fn addr(&self) -> &Multiaddr {
+        &self.addr
+    }
+
+    /// Whether the node is still running, see [`TestNetwork::leave`].
+    pub const fn is_live(&self) -> bool {
+        self.live
+    }
+}
+
+/// Nodes that are wired up with each other over the in-memory transport. Dropping the network
+/// stops every node that has no other [`Peer`] handle left.
+#[derive(Debug)]
+pub struct TestNetwork {
+    nodes: Vec<TestNode>,
+}
+
+impl TestNetwork {
+    /// Spawns `n` nodes with the default config.
+    pub fn spawn(n: usize) -> Result<Self, NetworkBridgeError> {
+        Self::spawn_with(n, |_, builder| builder)
+    }
+
+    /// Spawns `n` nodes, letting `configure` adjust the config of each by its index. The
+    /// transport, listener and boot nodes are set by the network.
+    pub fn spawn_with(
+        n: usize,
+        mut configure: impl FnMut(usize, ConfigBuilder) -> ConfigBuilder,
+    ) -> Result<Self, NetworkBridgeError> {
+        let mut network = Self {
+            nodes: Vec::with_capacity(n),
+        };
+        for index in 0..n {
+            let builder = configure(index, network.node_config(index));
+            network.nodes.push(spawn_node(builder)?);
+        }
+        Ok(network)
+    }
+
+    /// Spawns another node with the default config, booting from the first live node, and
+    /// returns its index.
+    pub fn join(&mut self) -> Result<usize, NetworkBridgeError> {
+        let index = self.len();
+        let node = spawn_node(self.node_config(index))?;
+        self.nodes.push(node);
+        Ok(index)
+    }
+
+    /// Shuts the node at `index` down. It keeps its index, but requests to it fail from then on
+    /// and it no longer counts towards [`TestNetwork::wait_for_convergence`].
+    pub async fn leave(&mut self, index: usize) {
+        let node = &mut self.nodes[index];
+        if node.live {
+            let _ = node.peer.shutdown().await;
+            node.live = false;
+        }
+    }
+
+    /// Cuts the link between the nodes at `a` and `b` at the transport, until
+    /// [`TestNetwork::heal_link`] is called: their connections drop and dials between them are
+    /// refused, as if the network between them failed. The network does not converge in the
+    /// meantime.
+    pub fn fail_link(&self, a: usize, b: usize) {
+        memory_links::cut_link(self.node(a).port, self.node(b).port);
+    }
+
+    /// Undoes [`TestNetwork::fail_link`]. The failed dials may have dropped the nodes from each
+    /// other's routing table, so both look up their own key to find each other again instead of
+    /// waiting for the next bootstrap.
+    pub async fn heal_link(&self, a: usize, b: usize) -> anyhow::Result<()> {
+        let (a, b) = (self.node(a), self.node(b));
+        memory_links::restore_link(a.port, b.port);
+        for node in [a, b] {
+            node.peer.get_closest_peers_to(*node.id()).await?;
+        }
+        Ok(())
+    }
+
+    /// The config of the node at `index` before any adjustments, booting from the first live
+    /// node.
+    fn node_config(&self, index: usize) -> ConfigBuilder {
+        // NOTE: a single worker keeps networks of hundreds of nodes within reasonable thread counts
+        let builder = Config::builder()
+            .with_thread_name(format!("test-node-{index}"))
+            .with_worker_threads(NonZeroUsize::MIN);
+        match self.live_nodes().next() {
+            Some(first) => builder.with_boot_nodes(
+                BootNodes::new([(first.addr.clone(), *first.id())])
+                    .expect("a single boot node to convert"),
+            ),
+            None => builder,
+        }
+    }
+
+    pub const fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// # Panics
+    ///
+    /// If there is no node at `index`.
+    pub fn node(&self, index: usize) -> &TestNode {
+        &self.nodes[index]
+    }
+
+    pub fn nodes(&self) -> &[TestNode] {
+        &self.nodes
+    }
+
+    pub fn live_nodes(&self) -> impl Iterator<Item = &TestNode> + '_ {
+        self.nodes.iter().filter(|node| node.live)
+    }
+
+    /// Waits until the routing table of every live node holds as many of the other live nodes as a
+    /// Kademlia bucket can, which is all of them in networks of up to 21 nodes. Nodes that joined
+    /// before others only hear of them once they are contacted, so the nodes that lag behind look
+    /// up their own key like a bootstrap does.
+    pub async fn wait_for_convergence(&self, timeout: Duration) -> Result<(), TestNetworkError> {
+        let deadline = Deadline::after(timeout, "the routing tables to converge");
+        loop {
+            let lagging = self.lagging_nodes().await;
+            if lagging.is_empty() {
+                return Ok(());
+            }
+            for node in lagging {
+                let _ = node.peer.get_closest_peers_to(*node.id()).await;
+            }
+            deadline.wait().await?;
+        }
+    }
+
+    /// Polls `condition` until it holds, for the states that the other helpers do not cover. Fails
+    /// once `timeout` is up, naming what the test was `waiting_for`.
+    pub async fn wait_until(
+        timeout: Duration,
+        waiting_for: &'static str,
+        mut condition: impl AsyncFnMut() -> bool,
+    ) -> Result<(), TestNetworkError> {
+        let deadline = Deadline::after(timeout, waiting_for);
+        while !condition().await {
+            deadline.wait().await?;
+        }
+        Ok(())
+    }
+
+    /// The nodes that do not know enough of the network yet, see
+    /// [`TestNetwork::wait_for_convergence`].
+    async fn lagging_nodes(&self) -> Vec<&TestNode> {
+        let network = self
+            .live_nodes()
+            .map(|node| *node.id())
+            .collect::<HashSet<_>>();
+        let expected = network.len().saturating_sub(1).min(K_VALUE.get());
+        let mut lagging = Vec::new();
+        for node in self.live_nodes() {
+            let key = node.id().to_bytes();
+            let known = match node.peer.get_closest_local_peers(Cow::Owned(key)).await {
+                Ok(ResponseData::KadResponse(KadResponseData::ClosestLocalPeers { peers })) => {
+                    peers
+                        .into_iter()
+                        .filter(|peer| network.contains(peer))
+                        .count()
+                }
+                _ => 0,
+            };
+            if known < expected {
+                lagging.push(node);
+            }
+        }
+        lagging
+    }
+
+    /// Registers the node at `index` as a supplier of `file_hash` on [`SUPPLIER_PORT`] of the
+    /// loopback address.
+    pub async fn register_file(&self, index: usize, file_hash: &FileHash) -> anyhow::Result<()> {
+        self.node(index)
+            .peer
+            .register_file(
+                Cow::Borrowed(file_hash),
+                Some(SupplierAddr::Ip(IpAddr::V4(Ipv4Addr::LOCALHOST))),
+                SUPPLIER_PORT,
+                0,
+                format!("test-node-{index}"),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// The suppliers of `file_hash` as seen from the node at `index`.
+    pub async fn holders(
+        &self,
+        index: usize,
+        file_hash: &FileHash,
+    ) -> anyhow::Result<HashSet<PeerId>> {
+        match self
+            .node(index)
+            .peer
+            .check_holders(Cow::Borrowed(file_hash))
+            .await?
+        {
+            ResponseData::ReqResResponse(FileReqResResponseData::GetSuppliers {
+                suppliers,
+                ..
+            }) => Ok(suppliers.into_iter().map(|(peer_id, _)| peer_id).collect()),
+            _ => bail!("Unexpected response"),
+        }
+    }
+
+    /// Asserts that the node at `index` finds exactly the nodes at `expected` as the suppliers of
+    /// `file_hash` within `timeout`.
+    ///
+    /// # Panics
+    ///
+    /// If the holders still differ once `timeout` is up.
+    pub async fn assert_holders(
+        &self,
+        index: usize,
+        file_hash: &FileHash,
+        expected: impl IntoIterator<Item = usize>,
+        timeout: Duration,
+    ) {
+        let expected = expected
+            .into_iter()
+            .map(|index| *self.node(index).id())
+            .collect::<HashSet<_>>();
+        let deadline = Deadline::after(timeout, "the expected holders");
+        loop {
+            let holders = self.holders(index, file_hash).await;
+            if holders.as_ref().is_ok_and(|holders| *holders == expected) {
+                return;
+            }
+            if deadline.wait().await.is_err() {
+                match holders {
+                    Ok(holders) => panic!(
+                        "node {index} found the holders {:?} of {file_hash:?} instead of {:?}",
+                        self.indices(&holders),
+                        self.indices(&expected),
+                    ),
+                    Err(err) => panic!("node {index} failed to look up {file_hash:?}: {err}"),
+                }
+            }
+        }
+    }
+
+    fn ids(&self) -> impl Iterator<Item = PeerId> + '_ {
+        self.nodes.iter().map(|node| *node.id())
+    }
+
+    /// Maps peers back to node indices for readable failures, with `None` for outsiders.
+    fn indices(&self, peers: &HashSet<PeerId>) -> Vec<Option<usize>> {
+        let mut indices = peers
+            .iter()
+            .map(|peer| self.ids().position(|id| id == *peer))
+            .collect::<Vec<_>>();
+        indices.sort();
+        indices
+    }
+}
+
+/// A [`FileHash`] made from the SHA-256 digest of `name`.
+pub fn file_hash(name: &str) -> FileHash {
+    FileHash::from_digest(Sha256::digest(name.as_bytes()).to_vec())
+        .expect("a SHA-256 digest to be a valid file hash")
+}
+
+fn spawn_node(builder: ConfigBuilder) -> Result<TestNode, NetworkBridgeError> {
+    let port = NEXT_MEMORY_PORT.fetch_add(1, Ordering::Relaxed);
+    let addr = multiaddr!(Memory(port));
+    let peer = spawn_bridge(
+        builder
+            .with_transport(Transport::Memory)
+            .with_listener(addr.clone())
+            .build(),
+    )?;
+    Ok(TestNode {
+        peer,
+        addr,
+        port,
+        live: true,
+    })
+}
+
+/// Paces polling for a state that other nodes bring about.
+#[derive(Debug)]
+struct Deadline {
+    at: Instant,
+    timeout: Duration,
+    waiting_for: &'static str,
+}
+
+impl Deadline {
+    fn after(timeout: Duration, waiting_for: &'static str) -> Self {
+        Self {
+            at: Instant::now() + timeout,
+            timeout,
+            waiting_for,
+        }
+    }
+
+    /// Sleeps for [`POLL_INTERVAL`] unless the deadline has passed.
+    async fn wait(&self) -> Result<(), TestNetworkError> {
+        if Instant::now() >= self.at {
+            return Err(TestNetworkError::TimedOut {
+                waiting_for: self.waiting_for,
+                timeout: self.timeout,
+            });
+        }
+        time::sleep(POLL_INTERVAL).await;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum TestNetworkError {
+    #[error("Timed out after {timeout:?} waiting for {waiting_for}")]
+    TimedOut {
+        waiting_for: &'static str,
+        timeout: Duration,
+    },
+}
@@ -0,0 +1,136 @@
+use std::{borrow::Cow, time::Duration};
+
+use market_dht::{
+    file_hash::FileHash,
+    peer::Peer,
+    testing::{file_hash, TestNetwork},
+    Announcement, ListingUpdate, MarketSubscription, MarketTopic,
+};
+use pretty_assertions::assert_eq;
+use tokio::time::timeout;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn next(subscription: &mut MarketSubscription) -> Announcement {
+    timeout(Duration::from_secs(5), subscription.recv())
+        .await
+        .expect("an announcement to arrive")
+        .expect("the node to be running")
+        .announcement
+}
+
+/// Has `publisher` list throwaway files until `subscriber` hears of one, which it only does once
+/// the nodes learned which topics the other one is subscribed to.
+async fn wait_for_announcements(publisher: &Peer, subscriber: &Peer) {
+    let mut subscription = subscriber.subscribe([MarketTopic::NewListings]);
+    let mut attempt = 0;
+    TestNetwork::wait_until(TIMEOUT, "the announcements", async || {
+        attempt += 1;
+        publisher
+            .register_file(
+                Cow::Owned(file_hash(&format!("warm-up {attempt}"))),
+                Some("127.0.0.1".parse().unwrap()),
+                8080,
+                10,
+                "publisher".to_owned(),
+            )
+            .await
+            .unwrap();
+        timeout(Duration::from_millis(200), subscription.recv())
+            .await
+            .is_ok()
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_listing_announcements() {
+    let network = TestNetwork::spawn(2).unwrap();
+    let (peer1, peer2) = (network.node(0).peer(), network.node(1).peer());
+    wait_for_announcements(peer1, peer2).await;
+
+    let mut all = peer2.subscribe(MarketTopic::ALL);
+    let mut prices = peer2.subscribe([MarketTopic::PriceChanges]);
+    let mut local = peer1.subscribe([MarketTopic::NewListings]);
+
+    let file_hash = FileHash::from_digest([7u8; 32]).unwrap();
+    peer1
+        .register_file(
+            Cow::Borrowed(&file_hash),
+            Some("127.0.0.1".parse().unwrap()),
+            8080,
+            10,
+            "peer1".to_owned(),
+        )
+        .await
+        .unwrap();
+    let announcement = timeout(Duration::from_secs(5), all.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(announcement.supplier, *peer1.id());
+    let Announcement::NewListing { supplier_info, .. } = announcement.announcement else {
+        panic!("Unexpected announcement {announcement:?}");
+    };
+    assert_eq!(supplier_info.price, 10);
+    assert_eq!(next(&mut local).await.file_hash(), &file_hash);
+
+    // only price changes are announced
+    peer1
+        .update_listing(
+            Cow::Borrowed(&file_hash),
+            ListingUpdate {
+                port: Some(8081),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    peer1
+        .update_listing(
+            Cow::Borrowed(&file_hash),
+            ListingUpdate {
+                price: Some(20),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    for subscription in [&mut all, &mut prices] {
+        match next(subscription).await {
+            Announcement::PriceChanged {
+                old_price,
+                supplier_info,
+                ..
+            } => {
+                assert_eq!(old_price, 10);
+                assert_eq!((supplier_info.port, supplier_info.price), (8081, 20));
+            }
+            announcement => panic!("Unexpected announcement {announcement:?}"),
+        }
+    }
+
+    // registering the file again is not a new listing, but it can change the price
+    peer1
+        .register_file(
+            Cow::Borrowed(&file_hash),
+            Some("127.0.0.1".parse().unwrap()),
+            8080,
+            30,
+            "peer1".to_owned(),
+        )
+        .await
+        .unwrap();
+    match next(&mut all).await {
+        Announcement::PriceChanged {
+            old_price,
+            supplier_info,
+            ..
+        } => assert_eq!((old_price, supplier_info.price), (20, 30)),
+        announcement => panic!("Unexpected announcement {announcement:?}"),
+    }
+    assert!(timeout(Duration::from_millis(500), local.recv())
+        .await
+        .is_err());
+}
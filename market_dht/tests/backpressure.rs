@@ -0,0 +1,54 @@
+use std::num::NonZeroUsize;
+
+use futures::future::join_all;
+use market_dht::{
+    peer::{Peer, PeerError},
+    testing::TestNetwork,
+    Metrics, RequestQueueMetrics, ResponseData,
+};
+use pretty_assertions::assert_eq;
+
+const CAPACITY: usize = 2;
+
+async fn metrics(peer: &Peer) -> Metrics {
+    match peer.get_metrics().await {
+        Ok(ResponseData::Metrics { metrics }) => metrics,
+        res => panic!("Unexpected response {res:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_requests_past_the_queue_capacity_are_refused() {
+    let network = TestNetwork::spawn_with(1, |_, builder| {
+        builder.with_request_queue_capacity(NonZeroUsize::new(CAPACITY).unwrap())
+    })
+    .unwrap();
+    let peer = network.node(0).peer();
+
+    // every request is queued before the first one is awaited, which is far quicker than the
+    // coordinator picks them up
+    let responses = join_all((0..1000).map(|_| peer.get_connected_peers())).await;
+    let busy = responses
+        .iter()
+        .filter(|res| {
+            res.as_ref().is_err_and(|err| {
+                err.downcast_ref::<PeerError>() == Some(&PeerError::Busy { capacity: CAPACITY })
+            })
+        })
+        .count();
+    assert!(busy > 0);
+    assert_eq!(
+        responses.iter().filter(|res| res.is_ok()).count(),
+        responses.len() - busy
+    );
+
+    // the queue drains once the burst is over
+    assert_eq!(
+        metrics(peer).await.request_queue,
+        RequestQueueMetrics {
+            depth: 0,
+            capacity: CAPACITY,
+        }
+    );
+    assert!(peer.get_connected_peers().await.is_ok());
+}
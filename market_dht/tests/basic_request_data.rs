@@ -1,50 +1,30 @@
-use std::{thread, time::Duration};
+use std::time::Duration;
 
-use market_dht::{config::Config, multiaddr, net::spawn_bridge, ResponseData};
+use market_dht::{testing::TestNetwork, ResponseData};
 use pretty_assertions::{self, assert_eq};
-use tokio::runtime::Runtime;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
 
 #[tokio::test]
 async fn test_should_not_panic_in_async_context() {
-    let _ = spawn_bridge(
-        Config::builder()
-            .with_listener(multiaddr!(Ip4([127, 0, 0, 1]), Tcp(4444u16)))
-            .with_thread_name("peer1".to_owned())
-            .build(),
-    )
-    .unwrap();
+    let _ = TestNetwork::spawn(1).unwrap();
 }
 
-#[test]
-fn test_get_connected_peers() {
-    let peer1 = spawn_bridge(
-        Config::builder()
-            .with_listener(multiaddr!(Ip4([127, 0, 0, 1]), Tcp(1233u16)))
-            .with_thread_name("peer1".to_owned())
-            .build(),
-    )
-    .unwrap();
-
-    let _peer2 = spawn_bridge(
-        Config::builder()
-            .with_listener(multiaddr!(Ip4([127, 0, 0, 1]), Tcp(1234u16)))
-            .with_thread_name("peer2".to_owned())
-            .with_boot_nodes(
-                vec![("/ip4/127.0.0.1/tcp/1233".to_owned(), peer1.id().to_string())]
-                    .try_into()
-                    .unwrap(),
-            )
-            .build(),
-    )
-    .unwrap();
+#[tokio::test]
+async fn test_get_connected_peers() {
+    let network = TestNetwork::spawn(2).unwrap();
+    let peer1 = network.node(0).peer();
 
-    thread::sleep(Duration::from_secs(1));
-    Runtime::new().unwrap().block_on(async move {
+    let connected_peers = async || {
         let response = peer1.get_connected_peers().await.unwrap();
-        if let ResponseData::ConnectedPeers { connected_peers } = response {
-            assert_eq!(1, connected_peers.len());
-        } else {
+        let ResponseData::ConnectedPeers { connected_peers } = response else {
             panic!("Didn't get the correct response!")
-        }
-    });
+        };
+        connected_peers
+    };
+    let _ = TestNetwork::wait_until(TIMEOUT, "a connection", async || {
+        !connected_peers().await.is_empty()
+    })
+    .await;
+    assert_eq!(connected_peers().await, vec![*network.node(1).id()]);
 }
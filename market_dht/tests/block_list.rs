@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use market_dht::{
+    testing::{file_hash, TestNetwork},
+    ResponseData,
+};
+use pretty_assertions::assert_eq;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[tokio::test]
+async fn test_blocked_peers_are_left_out_of_holders() {
+    let network = TestNetwork::spawn(2).unwrap();
+    network.wait_for_convergence(TIMEOUT).await.unwrap();
+    let (supplier, peer2) = (network.node(0).id(), network.node(1).peer());
+    let file_hash = file_hash("blocked");
+    network.register_file(0, &file_hash).await.unwrap();
+    network.assert_holders(1, &file_hash, [0], TIMEOUT).await;
+
+    assert_eq!(
+        peer2.block_peer(*supplier).await.unwrap(),
+        ResponseData::BlockedPeers {
+            blocked_peers: vec![*supplier]
+        }
+    );
+    TestNetwork::wait_until(TIMEOUT, "the blocked peer to disconnect", async || {
+        peer2.is_connected_to(*supplier).await.unwrap()
+            == (ResponseData::IsConnectedTo {
+                is_connected: false,
+            })
+    })
+    .await
+    .unwrap();
+    assert!(network.holders(1, &file_hash).await.unwrap().is_empty());
+
+    assert_eq!(
+        peer2.unblock_peer(*supplier).await.unwrap(),
+        ResponseData::BlockedPeers {
+            blocked_peers: vec![]
+        }
+    );
+}
@@ -0,0 +1,157 @@
+use std::{borrow::Cow, fs, path::PathBuf, time::Duration};
+
+use market_dht::{
+    file_hash::FileHash, peer::RegisterPathError, testing::TestNetwork, FileTransferResponseData,
+    KadResponseData, ResponseData,
+};
+use pretty_assertions::assert_eq;
+use sha2::{Digest, Sha256};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A supplier serving from `serve_dir` and a node downloading to `download_dir` that know each
+/// other.
+async fn spawn_pair(serve_dir: PathBuf, download_dir: PathBuf) -> TestNetwork {
+    let mut dirs = [serve_dir, download_dir].into_iter();
+    let network = TestNetwork::spawn_with(2, |_, builder| {
+        builder.with_file_transfer_dir(dirs.next().unwrap())
+    })
+    .unwrap();
+    network.wait_for_convergence(TIMEOUT).await.unwrap();
+    network
+}
+
+#[tokio::test]
+async fn test_download_file_from_supplier() {
+    let (serve_dir, download_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    let (serve_dir, download_dir) = (serve_dir.path(), download_dir.path());
+    // spans more than a single chunk
+    let content = (0..600 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let file_hash = FileHash::from_digest(Sha256::digest(&content).to_vec()).unwrap();
+    fs::write(serve_dir.join(file_hash.to_hex()), &content).unwrap();
+
+    let network = spawn_pair(serve_dir.to_path_buf(), download_dir.to_path_buf()).await;
+    let (peer1, peer2) = (network.node(0).peer(), network.node(1).peer());
+
+    let dest = download_dir.join("downloaded");
+    fs::write(&dest, b"an older download").unwrap();
+    let res = peer2
+        .download_file(*peer1.id(), Cow::Borrowed(&file_hash), &dest)
+        .await;
+    assert!(res.is_err(), "unregistered files should not be served");
+    // a failed download leaves whatever was at the destination alone
+    assert_eq!(fs::read(&dest).unwrap(), b"an older download");
+    assert_eq!(download_dir.join("downloaded.part").exists(), false);
+
+    peer1
+        .register_file(
+            Cow::Borrowed(&file_hash),
+            Some([127, 0, 0, 1].into()),
+            8080,
+            10,
+            "supplier".to_owned(),
+        )
+        .await
+        .unwrap();
+    let res = peer2
+        .download_file(*peer1.id(), Cow::Borrowed(&file_hash), &dest)
+        .await
+        .unwrap();
+    assert_eq!(
+        res,
+        ResponseData::FileTransferResponse(FileTransferResponseData::DownloadFile {
+            file_hash: file_hash.clone(),
+            path: dest.clone(),
+            num_bytes: content.len() as u64,
+        })
+    );
+    assert_eq!(fs::read(&dest).unwrap(), content);
+}
+
+#[tokio::test]
+async fn test_register_path_and_download() {
+    let root = tempfile::tempdir().unwrap();
+    let (shared_dir, download_dir) = (root.path().join("shared"), root.path().join("download"));
+    fs::create_dir_all(&shared_dir).unwrap();
+    fs::create_dir_all(&download_dir).unwrap();
+    let content = b"some file that is served under its own name".to_vec();
+    let path = shared_dir.join("notes.txt");
+    fs::write(&path, &content).unwrap();
+    let outside = root.path().join("outside.txt");
+    fs::write(&outside, b"not for sharing").unwrap();
+
+    let network = spawn_pair(shared_dir.clone(), download_dir.clone()).await;
+    let (peer1, peer2) = (network.node(0).peer(), network.node(1).peer());
+
+    for path in [outside, shared_dir.join("../outside.txt")] {
+        let err = peer1
+            .register_path(&path, None, 8080, 10, "supplier".to_owned())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<RegisterPathError>(),
+                Some(RegisterPathError::OutsideFileTransferDir { .. })
+            ),
+            "{path:?} should not be served: {err}"
+        );
+    }
+    let Ok(ResponseData::LocalListings { listings }) = peer1.local_listings().await else {
+        panic!("Unexpected response");
+    };
+    assert_eq!(listings.is_empty(), true);
+
+    let res = peer1
+        .register_path(
+            &path,
+            Some([127, 0, 0, 1].into()),
+            8080,
+            10,
+            "supplier".to_owned(),
+        )
+        .await
+        .unwrap();
+    let file_hash = FileHash::from_digest(Sha256::digest(&content).to_vec()).unwrap();
+    assert_eq!(
+        res,
+        ResponseData::KadResponse(KadResponseData::RegisterFile {
+            key: file_hash.clone()
+        })
+    );
+    let Ok(ResponseData::LocalListings { listings }) = peer1.local_listings().await else {
+        panic!("Unexpected response");
+    };
+    assert_eq!(listings[0].supplier_info.addr, [127, 0, 0, 1].into());
+    let dest = download_dir.join("notes.txt");
+    peer2
+        .download_file(*peer1.id(), Cow::Borrowed(&file_hash), &dest)
+        .await
+        .unwrap();
+    assert_eq!(fs::read(&dest).unwrap(), content);
+}
+
+#[tokio::test]
+async fn test_register_path_without_file_transfer() {
+    let dir = tempfile::tempdir().unwrap();
+    let content = b"listed, but not served by this node".to_vec();
+    let path = dir.path().join("listed.txt");
+    fs::write(&path, &content).unwrap();
+
+    let network = TestNetwork::spawn(1).unwrap();
+    let peer = network.node(0).peer();
+    let res = peer
+        .register_path(
+            &path,
+            Some([127, 0, 0, 1].into()),
+            8080,
+            10,
+            "supplier".to_owned(),
+        )
+        .await
+        .unwrap();
+    let file_hash = FileHash::from_digest(Sha256::digest(&content).to_vec()).unwrap();
+    assert_eq!(
+        res,
+        ResponseData::KadResponse(KadResponseData::RegisterFile { key: file_hash })
+    );
+}
@@ -0,0 +1,60 @@
+use std::{borrow::Cow, time::Duration};
+
+use market_dht::{
+    file_hash::FileHash, peer::Peer, testing::TestNetwork, ListingStats, RequestCounts,
+    ResponseData,
+};
+use pretty_assertions::assert_eq;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn listing_stats(peer: &Peer) -> ListingStats {
+    match peer.listing_stats().await {
+        Ok(ResponseData::ListingStats { stats }) => stats,
+        res => panic!("Unexpected response {res:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_inbound_requests_are_counted_per_listing_and_peer() {
+    let network = TestNetwork::spawn(2).unwrap();
+    network.wait_for_convergence(TIMEOUT).await.unwrap();
+    let (peer1, peer2) = (network.node(0).peer(), network.node(1).peer());
+
+    let (popular, unpopular) = (
+        FileHash::from_digest([1u8; 32]).unwrap(),
+        FileHash::from_digest([2u8; 32]).unwrap(),
+    );
+    for file_hash in [&popular, &unpopular] {
+        peer1
+            .register_file(
+                Cow::Borrowed(file_hash),
+                Some("127.0.0.1".parse().unwrap()),
+                8080,
+                10,
+                "peer1".to_owned(),
+            )
+            .await
+            .unwrap();
+    }
+    for _ in 0..2 {
+        peer2.check_holders(Cow::Borrowed(&popular)).await.unwrap();
+    }
+    // requests for files that are not listed are not counted
+    let unlisted = FileHash::from_digest([3u8; 32]).unwrap();
+    peer2.check_holders(Cow::Borrowed(&unlisted)).await.unwrap();
+
+    let stats = listing_stats(peer1).await;
+    assert_eq!(stats.files.len(), 2);
+    assert_eq!(stats.files[0].file_hash, popular);
+    let requests = stats.files[0].requests;
+    assert_eq!(requests.total, requests.last_hour);
+    assert_eq!(requests.total.supplier_info, 2);
+    assert_eq!(requests.total.provider_lookups, 2);
+    assert_eq!(stats.files[1].file_hash, unpopular);
+    assert_eq!(stats.files[1].requests.total, RequestCounts::default());
+    assert_eq!(stats.peers.len(), 1);
+    assert_eq!(stats.peers[0].peer_id, *peer2.id());
+    assert_eq!(stats.peers[0].requests.total.supplier_info, 2);
+    assert_eq!(stats.peers[0].requests.total.provider_lookups, 2);
+}
@@ -0,0 +1,84 @@
+use std::{
+    borrow::Cow,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use market_dht::{
+    file_hash::FileHash,
+    metrics::{encode, Registry},
+    testing::TestNetwork,
+};
+use pretty_assertions::assert_eq;
+
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+fn metric_value(registry: &Mutex<Registry>, metric: &str) -> Option<f64> {
+    let mut encoded = String::new();
+    encode(&mut encoded, &registry.lock().unwrap()).unwrap();
+    encoded
+        .lines()
+        .find_map(|line| line.strip_prefix(metric)?.strip_prefix(' ')?.parse().ok())
+}
+
+#[tokio::test]
+async fn test_coordinator_records_metrics() {
+    let registry = Arc::new(Mutex::new(Registry::default()));
+    let network = TestNetwork::spawn_with(2, |index, builder| match index {
+        0 => builder.with_metrics_registry(registry.clone()),
+        _ => builder,
+    })
+    .unwrap();
+    network.wait_for_convergence(TIMEOUT).await.unwrap();
+    let (peer1, peer2) = (network.node(0).peer(), network.node(1).peer());
+
+    let file_hash = FileHash::from_digest([1u8; 32]).unwrap();
+    peer1
+        .register_file(
+            Cow::Borrowed(&file_hash),
+            Some("127.0.0.1".parse().unwrap()),
+            8080,
+            10,
+            "peer1".to_owned(),
+        )
+        .await
+        .unwrap();
+    peer2
+        .check_holders(Cow::Borrowed(&file_hash))
+        .await
+        .unwrap();
+    peer1.get_connected_peers().await.unwrap();
+
+    // the gauges and the inbound counts are only brought up to date every few seconds
+    let inbound = "market_inbound_requests_total{protocol=\"file_req_res\",outcome=\"accepted\"}";
+    TestNetwork::wait_until(TIMEOUT, "the inbound request to be counted", async || {
+        metric_value(&registry, inbound) == Some(1.0)
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        metric_value(
+            &registry,
+            "market_requests_total{request=\"get_connected_peers\",outcome=\"ok\"}"
+        ),
+        Some(1.0)
+    );
+    assert_eq!(
+        metric_value(
+            &registry,
+            "market_request_duration_seconds_count{request=\"register_file\"}"
+        ),
+        Some(1.0)
+    );
+    assert_eq!(metric_value(&registry, "market_connections"), Some(1.0));
+    assert_eq!(
+        metric_value(&registry, "market_routing_table_size"),
+        Some(1.0)
+    );
+    // the libp2p metrics are recorded too
+    let mut encoded = String::new();
+    encode(&mut encoded, &registry.lock().unwrap()).unwrap();
+    assert!(encoded
+        .lines()
+        .any(|line| line.starts_with("libp2p_swarm_connections_incoming_total{")));
+}
@@ -0,0 +1,111 @@
+use std::{borrow::Cow, num::NonZeroUsize, time::Duration};
+
+use futures::future::join_all;
+use market_dht::{
+    file_hash::FileHash,
+    peer::{Peer, PeerError},
+    testing::TestNetwork,
+    PeerId, ResponseData,
+};
+use pretty_assertions::assert_eq;
+
+const NUM_TASKS: u8 = 16;
+const REQUESTS_PER_TASK: usize = 20;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn is_connected_to(peer: &Peer, other: PeerId) -> bool {
+    match peer.is_connected_to(other).await {
+        Ok(ResponseData::IsConnectedTo { is_connected }) => is_connected,
+        res => panic!("Unexpected response {res:?}"),
+    }
+}
+
+/// Waits until `peer` is no longer connected to `other`, which it notices once `other` shut down.
+async fn wait_for_disconnect(peer: &Peer, other: PeerId) {
+    TestNetwork::wait_until(TIMEOUT, "the peer to disconnect", async || {
+        !is_connected_to(peer, other).await
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_handles_can_be_used_from_many_tasks_at_once() {
+    let network = TestNetwork::spawn(1).unwrap();
+    let peer = network.node(0).peer();
+
+    let tasks = (0..NUM_TASKS)
+        .map(|task| {
+            let peer = peer.clone();
+            tokio::spawn(async move {
+                let file_hash = FileHash::from_digest([task; 32]).unwrap();
+                peer.register_file(
+                    Cow::Borrowed(&file_hash),
+                    Some("127.0.0.1".parse().unwrap()),
+                    8080,
+                    10,
+                    format!("task{task}"),
+                )
+                .await
+                .unwrap();
+                for _ in 0..REQUESTS_PER_TASK {
+                    peer.get_connected_peers().await.unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    let Ok(ResponseData::LocalListings { listings }) = peer.local_listings().await else {
+        panic!("Expected the local listings");
+    };
+    assert_eq!(listings.len(), NUM_TASKS as usize);
+}
+
+#[tokio::test]
+async fn test_node_shuts_down_once_every_handle_is_dropped() {
+    let network = TestNetwork::spawn(2).unwrap();
+    network.wait_for_convergence(TIMEOUT).await.unwrap();
+    let observer = network.node(0).peer().clone();
+    let handle = network.node(1).peer().clone();
+    let id = *handle.id();
+
+    // the network drops its own handles, so only `handle` keeps the second node running
+    drop(network);
+    assert!(handle.get_connected_peers().await.is_ok());
+    assert!(is_connected_to(&observer, id).await);
+
+    drop(handle);
+    wait_for_disconnect(&observer, id).await;
+}
+
+#[tokio::test]
+async fn test_shutdown_stops_the_node_for_every_handle() {
+    let network = TestNetwork::spawn(2).unwrap();
+    network.wait_for_convergence(TIMEOUT).await.unwrap();
+    let (peer, handle) = (network.node(0).peer(), network.node(0).peer().clone());
+
+    assert_eq!(peer.shutdown().await.unwrap(), ResponseData::Shutdown);
+    let err = handle.get_connected_peers().await.unwrap_err();
+    assert_eq!(err.downcast_ref::<PeerError>(), Some(&PeerError::ShutDown));
+
+    wait_for_disconnect(network.node(1).peer(), *handle.id()).await;
+}
+
+#[tokio::test]
+async fn test_shutdown_waits_for_room_in_a_full_queue() {
+    let network = TestNetwork::spawn_with(1, |_, builder| {
+        builder.with_request_queue_capacity(NonZeroUsize::MIN)
+    })
+    .unwrap();
+    let peer = network.node(0).peer();
+
+    // the requests fill the queue before the shutdown is sent, see the backpressure tests
+    let (_, shutdown) = futures::join!(
+        join_all((0..100).map(|_| peer.get_connected_peers())),
+        peer.shutdown()
+    );
+    assert_eq!(shutdown.unwrap(), ResponseData::Shutdown);
+}
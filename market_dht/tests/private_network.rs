@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use market_dht::{testing::TestNetwork, PreSharedKey, ResponseData};
+use pretty_assertions::assert_eq;
+use tokio::time;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[tokio::test]
+async fn test_only_peers_with_the_same_key_connect() {
+    let key = PreSharedKey::new([7u8; 32]);
+    // the first node boots the others, the last one is an outsider with a key of its own
+    let network = TestNetwork::spawn_with(3, |index, builder| {
+        let key = match index {
+            2 => PreSharedKey::new([8u8; 32]),
+            _ => key,
+        };
+        builder
+            .with_pre_shared_key(key)
+            .with_protocol_prefix("/orcanet-test".to_owned())
+    })
+    .unwrap();
+    let (boot, member, outsider) = (network.node(0), network.node(1), network.node(2));
+
+    TestNetwork::wait_until(TIMEOUT, "the member to connect", async || {
+        member.peer().is_connected_to(*boot.id()).await.unwrap()
+            == (ResponseData::IsConnectedTo { is_connected: true })
+    })
+    .await
+    .unwrap();
+
+    // NOTE: with different keys the handshake garbles and only fails once the upgrade times out,
+    // so the lookup just gives the outsider's dial the time to connect if it could
+    let _ = time::timeout(
+        Duration::from_secs(1),
+        outsider.peer().get_closest_peers_to(*boot.id()),
+    )
+    .await;
+    assert_eq!(
+        outsider.peer().is_connected_to(*boot.id()).await.unwrap(),
+        ResponseData::IsConnectedTo {
+            is_connected: false
+        }
+    );
+    assert_eq!(
+        boot.peer().is_connected_to(*outsider.id()).await.unwrap(),
+        ResponseData::IsConnectedTo {
+            is_connected: false
+        }
+    );
+}
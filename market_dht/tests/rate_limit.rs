@@ -0,0 +1,61 @@
+use std::{borrow::Cow, time::Duration};
+
+use market_dht::{
+    config::InboundLimits, file_hash::FileHash, testing::TestNetwork, FileReqResResponseData,
+    InboundMetrics, Metrics, ResponseData,
+};
+use pretty_assertions::assert_eq;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[tokio::test]
+async fn test_supplier_info_requests_are_rate_limited() {
+    let network = TestNetwork::spawn_with(2, |index, builder| match index {
+        0 => builder.with_inbound_limits(InboundLimits {
+            max_requests: 1,
+            window: Duration::from_secs(60),
+            ..Default::default()
+        }),
+        _ => builder,
+    })
+    .unwrap();
+    network.wait_for_convergence(TIMEOUT).await.unwrap();
+    let (peer1, peer2) = (network.node(0).peer(), network.node(1).peer());
+
+    let file_hash = FileHash::from_digest([5u8; 32]).unwrap();
+    peer1
+        .register_file(
+            Cow::Borrowed(&file_hash),
+            Some([127, 0, 0, 1].into()),
+            8080,
+            10,
+            "supplier".to_owned(),
+        )
+        .await
+        .unwrap();
+    let num_suppliers = || async {
+        match peer2.check_holders(Cow::Borrowed(&file_hash)).await {
+            Ok(ResponseData::ReqResResponse(FileReqResResponseData::GetSuppliers {
+                suppliers,
+                ..
+            })) => suppliers.len(),
+            res => panic!("Unexpected response {res:?}"),
+        }
+    };
+    assert_eq!(num_suppliers().await, 1);
+    // the second lookup within the window is rejected by peer1
+    assert_eq!(num_suppliers().await, 0);
+
+    let Ok(ResponseData::Metrics { metrics }) = peer1.get_metrics().await else {
+        panic!("Unexpected response");
+    };
+    let Metrics { file_req_res, .. } = metrics;
+    assert_eq!(
+        file_req_res,
+        InboundMetrics {
+            accepted: 1,
+            rate_limited: 1,
+            too_many_in_flight: 0,
+        }
+    );
+}
@@ -0,0 +1,60 @@
+use std::{borrow::Cow, time::Duration};
+
+use market_dht::{
+    peer::Peer, testing::TestNetwork, DhtRecord, KadResponseData, Quorum, ResponseData,
+};
+use pretty_assertions::assert_eq;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn get_record(peer: &Peer, key: &Vec<u8>) -> DhtRecord {
+    match peer.get_record(Cow::Borrowed(key), Quorum::One).await {
+        Ok(ResponseData::KadResponse(KadResponseData::GetRecord { mut records })) => {
+            records.remove(0)
+        }
+        res => panic!("Unexpected response {res:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_put_get_and_remove_records() {
+    let network = TestNetwork::spawn(2).unwrap();
+    network.wait_for_convergence(TIMEOUT).await.unwrap();
+    let (peer1, peer2) = (network.node(0).peer(), network.node(1).peer());
+
+    let key = b"profile/alice".to_vec();
+    assert_eq!(
+        peer1
+            .put_record(
+                Cow::Borrowed(&key),
+                b"v1".to_vec(),
+                Quorum::One,
+                Some(Duration::from_secs(60)),
+            )
+            .await
+            .unwrap(),
+        ResponseData::KadResponse(KadResponseData::PutRecord { key: key.clone() })
+    );
+    let record = get_record(peer2, &key).await;
+    assert_eq!(record.value, b"v1".to_vec());
+    assert_eq!(record.publisher, Some(*peer1.id()));
+    assert!(record.expires_at.is_some());
+
+    // peer1 keeps its own record when someone else tries to overwrite it
+    assert!(peer2
+        .put_record(Cow::Borrowed(&key), b"v2".to_vec(), Quorum::One, None)
+        .await
+        .is_err());
+    let record = get_record(peer1, &key).await;
+    assert_eq!(record.value, b"v1".to_vec());
+    assert_eq!(record.publisher, Some(*peer1.id()));
+
+    assert_eq!(
+        peer1.remove_record(Cow::Borrowed(&key)).await.unwrap(),
+        ResponseData::KadResponse(KadResponseData::RemoveRecord { key: key.clone() })
+    );
+    assert!(peer1
+        .get_record(Cow::Borrowed(&b"missing".to_vec()), Quorum::One)
+        .await
+        .is_err());
+}
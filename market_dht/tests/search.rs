@@ -0,0 +1,94 @@
+use std::{borrow::Cow, time::Duration};
+
+use market_dht::{
+    file_hash::FileHash,
+    peer::{Peer, SearchError},
+    testing::TestNetwork,
+    ResponseData,
+};
+use pretty_assertions::assert_eq;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn search(peer: &Peer, query: &str) -> Vec<FileHash> {
+    match peer.search(query).await {
+        Ok(ResponseData::SearchResults { file_hashes }) => file_hashes,
+        res => panic!("Unexpected response {res:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_index_and_search_files() {
+    let network = TestNetwork::spawn(2).unwrap();
+    network.wait_for_convergence(TIMEOUT).await.unwrap();
+    let (peer1, peer2) = (network.node(0).peer(), network.node(1).peer());
+
+    let book = FileHash::from_digest([1u8; 32]).unwrap();
+    let guide = FileHash::from_digest([2u8; 32]).unwrap();
+    peer1
+        .register_file(
+            Cow::Borrowed(&book),
+            Some("127.0.0.1".parse().unwrap()),
+            8080,
+            10,
+            "peer1".to_owned(),
+        )
+        .await
+        .unwrap();
+    peer1
+        .index_file(
+            Cow::Borrowed(&book),
+            "The Rust Book.pdf",
+            &["programming".to_owned()],
+        )
+        .await
+        .unwrap();
+    peer1
+        .register_named_file(
+            Cow::Borrowed(&guide),
+            "rust-async-guide.md",
+            Some("127.0.0.1".parse().unwrap()),
+            8080,
+            10,
+            "peer1".to_owned(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        peer1
+            .index_file(Cow::Borrowed(&book), "!", &[])
+            .await
+            .unwrap_err()
+            .downcast_ref::<SearchError>(),
+        Some(&SearchError::NoKeywords {
+            text: "!".to_owned()
+        })
+    );
+    assert!(peer1
+        .index_file(
+            Cow::Owned(FileHash::from_digest([3u8; 32]).unwrap()),
+            "unlisted",
+            &[]
+        )
+        .await
+        .is_err());
+
+    // the results are sorted by digest
+    assert_eq!(
+        search(peer2, "rust").await,
+        vec![book.clone(), guide.clone()]
+    );
+    assert_eq!(search(peer2, "RUST book").await, vec![book.clone()]);
+    assert_eq!(search(peer2, "programming").await, vec![book]);
+    assert_eq!(search(peer2, "rust missing").await, vec![]);
+    assert_eq!(
+        peer2
+            .search("")
+            .await
+            .unwrap_err()
+            .downcast_ref::<SearchError>(),
+        Some(&SearchError::NoKeywords {
+            text: String::new()
+        })
+    );
+}
@@ -0,0 +1,135 @@
+use std::{
+    borrow::Cow,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use market_dht::{
+    config::SybilLimits,
+    metrics::{encode, Registry},
+    peer::Peer,
+    sybil::{generate_identity, peer_id_work},
+    testing::{file_hash, TestNetwork},
+    KadResponseData, Keypair, PeerId, ResponseData,
+};
+use pretty_assertions::assert_eq;
+use tokio::time::Instant;
+
+const DIFFICULTY: u32 = 10;
+const MIN_ROUTING_AGE: Duration = Duration::from_secs(3);
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+async fn routing_table(peer: &Peer) -> Vec<PeerId> {
+    match peer
+        .get_closest_local_peers(Cow::Owned(vec![0u8; 32]))
+        .await
+    {
+        Ok(ResponseData::KadResponse(KadResponseData::ClosestLocalPeers { peers })) => peers,
+        res => panic!("Unexpected response {res:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_new_and_unworked_identities_are_kept_out_of_the_routing_table() {
+    // the hub boots the others: one honest node and three sybils without enough work
+    let network = TestNetwork::spawn_with(5, |index, builder| match index {
+        0 => builder.with_sybil_limits(SybilLimits {
+            peer_id_difficulty: DIFFICULTY,
+            min_routing_age: MIN_ROUTING_AGE,
+            ..Default::default()
+        }),
+        1 => builder.with_identity(generate_identity(DIFFICULTY)),
+        _ => builder.with_identity(
+            std::iter::repeat_with(Keypair::generate_ed25519)
+                .find(|keypair| peer_id_work(&keypair.public().to_peer_id()) < DIFFICULTY)
+                .unwrap(),
+        ),
+    })
+    .unwrap();
+    let (hub, honest) = (network.node(0), network.node(1));
+    let started = Instant::now();
+
+    // the boot node is trusted by the nodes themselves
+    TestNetwork::wait_until(TIMEOUT, "the nodes to add the hub", async || {
+        for node in network.nodes().iter().skip(1) {
+            if !routing_table(node.peer()).await.contains(hub.id()) {
+                return false;
+            }
+        }
+        true
+    })
+    .await
+    .unwrap();
+    TestNetwork::wait_until(TIMEOUT, "the hub to add the honest node", async || {
+        let routing_table = routing_table(hub.peer()).await;
+        if routing_table != vec![*honest.id()] {
+            assert_eq!(routing_table, vec![]);
+            return false;
+        }
+        true
+    })
+    .await
+    .unwrap();
+    // the honest node connected after the network was spawned
+    assert!(started.elapsed() >= MIN_ROUTING_AGE);
+}
+
+/// Has the nodes 1 to 3 provide the same file to node 0, which enforces `limits`, and waits for
+/// node 0 to report `expected` rejected records for `reason`. Nodes on the in-memory transport
+/// have no IP address, so they all count as sharing one.
+async fn assert_rejected_providers(limits: SybilLimits, reason: &str, expected: u64) {
+    let registry = Arc::new(Mutex::new(Registry::default()));
+    let network = TestNetwork::spawn_with(4, |index, builder| match index {
+        0 => builder
+            .with_sybil_limits(limits)
+            .with_metrics_registry(registry.clone()),
+        _ => builder,
+    })
+    .unwrap();
+    network.wait_for_convergence(TIMEOUT).await.unwrap();
+    let file_hash = file_hash("popular");
+    for provider in 1..4 {
+        network.register_file(provider, &file_hash).await.unwrap();
+    }
+
+    let metric = format!("market_rejected_provider_records_total{{reason=\"{reason}\"}} ");
+    let rejected = || {
+        let mut encoded = String::new();
+        encode(&mut encoded, &registry.lock().unwrap()).unwrap();
+        encoded
+            .lines()
+            .find_map(|line| line.strip_prefix(metric.as_str())?.parse::<u64>().ok())
+    };
+    // the metrics are only brought up to date every few seconds
+    let _ = TestNetwork::wait_until(TIMEOUT, "the rejected records to be counted", async || {
+        rejected() == Some(expected)
+    })
+    .await;
+    assert_eq!(rejected(), Some(expected));
+}
+
+#[tokio::test]
+async fn test_provider_records_are_limited_per_ip() {
+    assert_rejected_providers(
+        SybilLimits {
+            max_providers_per_ip: Some(1),
+            ..Default::default()
+        },
+        "too_many_without_ip",
+        2,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_provider_records_are_limited_per_subnet() {
+    assert_rejected_providers(
+        SybilLimits {
+            max_providers_per_subnet: Some(2),
+            ..Default::default()
+        },
+        "too_many_without_ip",
+        1,
+    )
+    .await;
+}
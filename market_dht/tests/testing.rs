@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use market_dht::testing::{file_hash, TestNetwork};
+use pretty_assertions::assert_eq;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[tokio::test]
+async fn test_network_converges() {
+    let network = TestNetwork::spawn(5).unwrap();
+    network.wait_for_convergence(TIMEOUT).await.unwrap();
+    assert_eq!(network.len(), 5);
+}
+
+#[tokio::test]
+async fn test_holders_are_found_from_every_node() {
+    let network = TestNetwork::spawn(4).unwrap();
+    network.wait_for_convergence(TIMEOUT).await.unwrap();
+    let (shared, single) = (file_hash("shared"), file_hash("single"));
+    network.register_file(1, &shared).await.unwrap();
+    network.register_file(3, &shared).await.unwrap();
+    network.register_file(2, &single).await.unwrap();
+
+    for index in 0..network.len() {
+        network
+            .assert_holders(index, &shared, [1, 3], TIMEOUT)
+            .await;
+        network.assert_holders(index, &single, [2], TIMEOUT).await;
+    }
+    network
+        .assert_holders(0, &file_hash("unknown"), [], TIMEOUT)
+        .await;
+}
+
+#[tokio::test]
+#[should_panic(expected = "node 0 found the holders [Some(1)] of")]
+async fn test_assert_holders_reports_the_difference() {
+    let network = TestNetwork::spawn(2).unwrap();
+    network.wait_for_convergence(TIMEOUT).await.unwrap();
+    let file_hash = file_hash("file");
+    network.register_file(1, &file_hash).await.unwrap();
+    network.assert_holders(0, &file_hash, [1], TIMEOUT).await;
+    network
+        .assert_holders(0, &file_hash, [0, 1], Duration::from_millis(200))
+        .await;
+}
+
+#[tokio::test]
+async fn test_churn_and_failed_links() {
+    let mut network = TestNetwork::spawn(3).unwrap();
+    network.wait_for_convergence(TIMEOUT).await.unwrap();
+    let file_hash = file_hash("churn");
+    network.register_file(2, &file_hash).await.unwrap();
+
+    network.fail_link(0, 2);
+    network.assert_holders(0, &file_hash, [], TIMEOUT).await;
+    network.heal_link(0, 2).await.unwrap();
+    network.assert_holders(0, &file_hash, [2], TIMEOUT).await;
+
+    network.leave(2).await;
+    assert_eq!(network.node(2).is_live(), false);
+    let joined = network.join().unwrap();
+    network.wait_for_convergence(TIMEOUT).await.unwrap();
+    assert_eq!(network.live_nodes().count(), 3);
+    network
+        .assert_holders(joined, &file_hash, [], TIMEOUT)
+        .await;
+    network.register_file(joined, &file_hash).await.unwrap();
+    network
+        .assert_holders(0, &file_hash, [joined], TIMEOUT)
+        .await;
+}
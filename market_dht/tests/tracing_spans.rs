@@ -0,0 +1,89 @@
+use std::{
+    borrow::Cow,
+    sync::{Arc, Mutex},
+};
+
+use market_dht::{file_hash::FileHash, testing::TestNetwork};
+use pretty_assertions::assert_eq;
+use tracing::{
+    field::{Field, Visit},
+    info_span, span, Instrument, Subscriber,
+};
+use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+type Spans = Arc<Mutex<Vec<(&'static str, Option<&'static str>)>>>;
+type RequestIds = Arc<Mutex<Vec<(&'static str, String)>>>;
+
+/// Remembers every span along with the name of its parent, and the `request_id` of the spans
+/// that have one.
+struct SpanRecorder(Spans, RequestIds);
+
+impl<S> Layer<S> for SpanRecorder
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("the span to exist");
+        let parent = span.parent().map(|parent| parent.name());
+        self.0.lock().unwrap().push((span.name(), parent));
+        let mut request_id = RequestIdVisitor(None);
+        attrs.record(&mut request_id);
+        if let Some(request_id) = request_id.0 {
+            self.1.lock().unwrap().push((span.name(), request_id));
+        }
+    }
+}
+
+struct RequestIdVisitor(Option<String>);
+
+impl Visit for RequestIdVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "request_id" {
+            self.0 = Some(value.to_owned());
+        }
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn std::fmt::Debug) {}
+}
+
+#[tokio::test]
+async fn test_requests_are_handled_in_child_spans_of_the_caller() {
+    let (spans, request_ids) = (Spans::default(), RequestIds::default());
+    tracing_subscriber::registry()
+        .with(SpanRecorder(spans.clone(), request_ids.clone()))
+        .init();
+    let network = TestNetwork::spawn(1).unwrap();
+    let peer = network.node(0).peer();
+
+    peer.get_connected_peers()
+        .instrument(info_span!("caller"))
+        .await
+        .unwrap();
+    // without any peers the query finishes right away
+    let _ = peer
+        .get_closest_peers(Cow::Owned(FileHash::from_digest([1u8; 32]).unwrap()))
+        .instrument(info_span!("caller"))
+        .await;
+    peer.with_request_id("req-1")
+        .get_connected_peers()
+        .await
+        .unwrap();
+
+    let spans = spans.lock().unwrap();
+    assert_eq!(
+        spans
+            .iter()
+            .filter(|(name, _)| *name == "request")
+            .collect::<Vec<_>>(),
+        vec![
+            &("request", Some("caller")),
+            &("request", Some("caller")),
+            &("request", None)
+        ]
+    );
+    assert_eq!(
+        *request_ids.lock().unwrap(),
+        vec![("request", "req-1".to_owned())]
+    );
+    assert!(spans.contains(&("kad_query", Some("request"))));
+}
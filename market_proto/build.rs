@@ -1,8 +1,11 @@
 use std::error::Error;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let proto_files = &["./proto/market/market.proto"];
-    let dirs = &["./proto"];
+    let proto_files = &[
+        "./proto/market/market.proto",
+        "./ext/market_ext/market_ext.proto",
+    ];
+    let dirs = &["./proto", "./ext"];
     tonic_build::configure().compile(proto_files, dirs)?;
     for file in proto_files {
         println!("cargo:rerun-if-changed={}", file);
@@ -8,9 +8,16 @@ use gen::market_proto_rpc::User;
 pub use self::gen::*;
 
 mod gen {
+    // prost refers to the upstream messages from the extensions as `super::market::*`
+    use self::market_proto_rpc as market;
+
     pub mod market_proto_rpc {
         tonic::include_proto!("market");
     }
+
+    pub mod market_ext_proto_rpc {
+        tonic::include_proto!("market_ext");
+    }
 }
 
 impl User {
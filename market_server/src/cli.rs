@@ -1,6 +1,8 @@
+use std::{net::SocketAddr, num::NonZeroUsize, path::PathBuf};
+
 use clap::Parser;
 
-use crate::Port;
+use crate::{telemetry::LogFormat, Port};
 
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
@@ -11,4 +13,38 @@ pub struct Cli {
     pub peer_port: Port,
     #[arg(short, long, value_parser, num_args = 0.., value_delimiter = '|')]
     pub boot_nodes: Option<Vec<String>>,
+    /// File that the registered files are persisted to so they are restored on restart
+    #[arg(long)]
+    pub registry_path: Option<PathBuf>,
+    /// Directory that files are served from to other peers; RegisterPath only accepts paths
+    /// within it
+    #[arg(long)]
+    pub file_transfer_dir: Option<PathBuf>,
+    /// Maximum number of files this node lists at once
+    #[arg(long)]
+    pub max_listings: Option<usize>,
+    /// Swarm key file of a private network (`/key/swarm/psk/1.0.0/` format); only peers with the
+    /// same key can connect
+    #[arg(long)]
+    pub swarm_key: Option<PathBuf>,
+    /// Prefix for the protocol names of a separate network, e.g. `/my-market`
+    #[arg(long)]
+    pub protocol_prefix: Option<String>,
+    /// Leave holders whose reputation score (0 to 1) is below this out of lookups
+    #[arg(long)]
+    pub min_supplier_score: Option<f64>,
+    /// Serve Prometheus metrics on `http://<addr>/metrics`, e.g. `127.0.0.1:9090`
+    #[arg(long)]
+    pub metrics_addr: Option<SocketAddr>,
+    /// Maximum number of requests waiting for the node; requests past it fail with
+    /// RESOURCE_EXHAUSTED
+    #[arg(long)]
+    pub request_queue_capacity: Option<NonZeroUsize>,
+    /// How the log lines are written to stdout
+    #[arg(long, value_enum, default_value = "compact")]
+    pub log_format: LogFormat,
+    /// Export traces to the OpenTelemetry collector at this gRPC endpoint, e.g.
+    /// `http://127.0.0.1:4317`
+    #[arg(long)]
+    pub otlp_endpoint: Option<String>,
 }
@@ -5,3 +5,5 @@ pub type Port = u16;
 
 pub mod cli;
 pub mod market_service;
+pub mod metrics;
+pub mod telemetry;
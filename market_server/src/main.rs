@@ -1,25 +1,32 @@
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Parser;
 use libp2p::{multiaddr::Protocol, Multiaddr};
-use market_dht::{boot_nodes::BootNodes, config::Config, net::spawn_bridge};
-use market_proto::market_proto_rpc::market_server::MarketServer;
-use market_server::{cli::Cli, market_service::MarketService};
+use market_dht::{
+    boot_nodes::BootNodes, config::Config, metrics::Registry, net::spawn_bridge, PreSharedKey,
+};
+use market_proto::{
+    market_ext_proto_rpc::market_ext_server::MarketExtServer,
+    market_proto_rpc::market_server::MarketServer,
+};
+use market_server::{cli::Cli, market_service::MarketService, metrics::serve_metrics, telemetry};
 use tokio::runtime::Runtime;
 use tonic::transport::Server;
-use tracing::info;
+use tracing::{error, info};
 
 fn main() -> Result<()> {
-    tracing_log::LogTracer::init()?;
-    let subscriber = tracing_subscriber::fmt()
-        .compact()
-        .with_line_number(true)
-        .with_file(true)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber)?;
-
     let cli = Cli::parse();
+    let runtime = Runtime::new().unwrap();
+    {
+        // the OTLP exporter is spawned onto the runtime
+        let _runtime = runtime.enter();
+        telemetry::init(cli.log_format, cli.otlp_endpoint.clone())?;
+    }
+
     let market_port = cli.market_port;
     let peer_port = cli.peer_port;
     let boot_nodes = {
@@ -43,29 +50,76 @@ fn main() -> Result<()> {
     let mut listen_addr = Multiaddr::empty();
     listen_addr.push(Protocol::Ip4(Ipv4Addr::new(127, 0, 0, 1)));
     listen_addr.push(Protocol::Tcp(peer_port));
-    let config = {
-        if let Some(boot_nodes) = boot_nodes {
-            Config::builder()
-                .with_listener(listen_addr)
-                .with_boot_nodes(boot_nodes)
-        } else {
-            Config::builder().with_listener(listen_addr)
-        }
+    let mut config = Config::builder().with_listener(listen_addr);
+    if let Some(boot_nodes) = boot_nodes {
+        config = config.with_boot_nodes(boot_nodes);
+    }
+    if let Some(registry_path) = cli.registry_path {
+        config = config.with_registry_path(registry_path);
+    }
+    if let Some(file_transfer_dir) = cli.file_transfer_dir {
+        config = config.with_file_transfer_dir(file_transfer_dir);
+    }
+    if let Some(max_listings) = cli.max_listings {
+        config = config.with_max_listings(max_listings);
     }
-    .build();
+    if let Some(swarm_key) = cli.swarm_key {
+        let key = std::fs::read_to_string(&swarm_key)
+            .with_context(|| format!("Failed to read swarm key {}", swarm_key.display()))?;
+        let key = key
+            .parse::<PreSharedKey>()
+            .with_context(|| format!("Invalid swarm key {}", swarm_key.display()))?;
+        info!(
+            "Joining the private network with key fingerprint {}",
+            key.fingerprint()
+        );
+        config = config.with_pre_shared_key(key);
+    }
+    if let Some(protocol_prefix) = cli.protocol_prefix {
+        config = config.with_protocol_prefix(protocol_prefix);
+    }
+    if let Some(min_supplier_score) = cli.min_supplier_score {
+        config = config.with_min_supplier_score(min_supplier_score);
+    }
+    if let Some(capacity) = cli.request_queue_capacity {
+        config = config.with_request_queue_capacity(capacity);
+    }
+    let metrics_registry = cli
+        .metrics_addr
+        .map(|addr| (addr, Arc::new(Mutex::new(Registry::default()))));
+    if let Some((_, registry)) = &metrics_registry {
+        config = config.with_metrics_registry(registry.clone());
+    }
+    let config = config.build();
     let peer = spawn_bridge(config)?;
     let market_listen_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), market_port);
-    let market_service = MarketService::new(peer);
+    let market_service = Arc::new(MarketService::new(peer.clone()));
 
     info!("Market is listening on {}", market_listen_addr);
-    Runtime::new()
-        .unwrap()
+    if let Some((metrics_addr, registry)) = metrics_registry {
+        info!("Metrics are served on http://{}/metrics", metrics_addr);
+        runtime.spawn(async move {
+            if let Err(err) = serve_metrics(metrics_addr, registry).await {
+                error!("Metrics server failed: {}", err);
+            }
+        });
+    }
+    runtime
         .block_on(
             Server::builder()
-                .add_service(MarketServer::new(market_service))
-                .serve(market_listen_addr),
+                .trace_fn(telemetry::grpc_span)
+                .add_service(MarketServer::from_arc(market_service.clone()))
+                .add_service(MarketExtServer::from_arc(market_service))
+                .serve_with_shutdown(market_listen_addr, async {
+                    let _ = tokio::signal::ctrl_c().await;
+                }),
         )
         .unwrap();
+    info!("Shutting down");
+    if let Err(err) = runtime.block_on(peer.shutdown()) {
+        error!("Failed to shut down the market: {}", err);
+    }
+    telemetry::shutdown();
 
     Ok(())
 }
@@ -1,19 +1,129 @@
-use std::{borrow::Cow, net::Ipv4Addr};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use market_dht::{peer::Peer, FileReqResResponseData, ResponseData};
-use market_proto::market_proto_rpc::{
-    market_server::Market, CheckHoldersRequest, HoldersResponse, RegisterFileRequest, User,
+use futures::{stream, Stream};
+use market_dht::{
+    address::SupplierAddr,
+    file_hash::FileHash,
+    peer::{Peer, PeerError, RegisterPathError, SearchError},
+    Announcement, FileReqResResponseData, KadResponseData, ListingError, ListingInfo,
+    ListingUpdate, MarketAnnouncement, MarketTopic, PublishStatus, RequestCounts, ResponseData,
+    SupplierInfo, WindowedCounts,
+};
+use market_proto::{
+    market_ext_proto_rpc::{
+        market_ext_server::MarketExt, FileStats, GetListingStatsRequest, GetListingStatsResponse,
+        IndexFileRequest, IndexFileResponse, ListMyFilesRequest, ListMyFilesResponse, Listing,
+        MarketEvent, MarketTopic as ProtoMarketTopic, PublishStatus as ProtoPublishStatus,
+        RegisterPathRequest, RegisterPathResponse, RequestCounts as ProtoRequestCounts,
+        RequesterStats, SearchFilesRequest, SearchFilesResponse, UpdateFileRequest,
+        UpdateFileResponse, WatchMarketRequest, WindowedRequestCounts,
+    },
+    market_proto_rpc::{
+        market_server::Market, CheckHoldersRequest, HoldersResponse, RegisterFileRequest, User,
+    },
 };
 use tonic::{Request, Response, Status};
+use tracing::Span;
+
+use crate::telemetry;
+
+const DEFAULT_SEARCH_PAGE_SIZE: usize = 20;
+const MAX_SEARCH_PAGE_SIZE: usize = 100;
+/// How long the results of a search are kept around for the pages after the first one.
+const SEARCH_RESULTS_TTL: Duration = Duration::from_secs(60);
+const MAX_CACHED_SEARCHES: usize = 64;
+/// Binary request metadata of `RegisterFile` with the UTF-8 name to index the file by, see
+/// `market_ext.proto`.
+pub const FILE_NAME_METADATA: &str = "x-file-name-bin";
+/// Response metadata of `CheckHolders` with the reputation scores of the remote holders, see
+/// `market_ext.proto`.
+pub const HOLDER_SCORES_METADATA: &str = "x-holder-scores";
 
 #[derive(Debug)]
 pub struct MarketService {
     peer: Peer,
+    searches: Mutex<SearchCache>,
 }
 
 impl MarketService {
     pub fn new(peer: Peer) -> Self {
-        MarketService { peer }
+        MarketService {
+            peer,
+            searches: Mutex::default(),
+        }
+    }
+
+    /// The peer that handles `request`, with the requests it sends to the node tagged by the id
+    /// of the gRPC request, which is also recorded on the current (gRPC) span.
+    fn peer_for<T>(&self, request: &Request<T>) -> Peer {
+        let request_id = telemetry::request_id(request.metadata());
+        Span::current().record("request_id", request_id.as_str());
+        self.peer.with_request_id(request_id)
+    }
+
+    /// The results of `query`, searched for again unless `cached` and still fresh.
+    async fn search(
+        &self,
+        peer: &Peer,
+        query: &str,
+        cached: bool,
+    ) -> Result<Arc<Vec<FileHash>>, Status> {
+        if cached {
+            let now = Instant::now();
+            if let Some(file_hashes) = self.searches.lock().unwrap().get(query, now) {
+                return Ok(file_hashes);
+            }
+        }
+        let ResponseData::SearchResults { file_hashes } =
+            peer.search(query).await.map_err(peer_error)?
+        else {
+            return Err(Status::internal(
+                "Did not get the right response for some reason...",
+            ));
+        };
+        let file_hashes = Arc::new(file_hashes);
+        self.searches
+            .lock()
+            .unwrap()
+            .insert(query.to_owned(), file_hashes.clone(), Instant::now());
+        Ok(file_hashes)
+    }
+}
+
+/// The results of recent searches, so that paging through them does not search the DHT again
+/// for every page.
+#[derive(Debug, Default)]
+struct SearchCache {
+    results: HashMap<String, (Instant, Arc<Vec<FileHash>>)>,
+}
+
+impl SearchCache {
+    fn get(&mut self, query: &str, now: Instant) -> Option<Arc<Vec<FileHash>>> {
+        self.results
+            .retain(|_, (searched_at, _)| now.duration_since(*searched_at) < SEARCH_RESULTS_TTL);
+        self.results
+            .get(query)
+            .map(|(_, file_hashes)| file_hashes.clone())
+    }
+
+    fn insert(&mut self, query: String, file_hashes: Arc<Vec<FileHash>>, now: Instant) {
+        if self.results.len() >= MAX_CACHED_SEARCHES && !self.results.contains_key(&query) {
+            let oldest = self
+                .results
+                .iter()
+                .min_by_key(|(_, (searched_at, _))| *searched_at)
+                .map(|(query, _)| query.clone());
+            if let Some(oldest) = oldest {
+                self.results.remove(&oldest);
+            }
+        }
+        self.results.insert(query, (now, file_hashes));
     }
 }
 
@@ -23,27 +133,33 @@ impl Market for MarketService {
         &self,
         request: Request<RegisterFileRequest>,
     ) -> Result<Response<()>, Status> {
+        let name = match request.metadata().get_bin(FILE_NAME_METADATA) {
+            Some(name) => {
+                let name = name.to_bytes().map_err(|err| {
+                    Status::invalid_argument(format!("Invalid file name: {}", err))
+                })?;
+                Some(String::from_utf8(name.to_vec()).map_err(|err| {
+                    Status::invalid_argument(format!("Invalid file name: {}", err))
+                })?)
+            }
+            None => None,
+        };
+        let peer = self.peer_for(&request);
         let file_req = request.into_inner();
 
-        let file_hash = file_req.file_hash.as_bytes().to_vec();
-        let user = file_req.user.ok_or(Status::invalid_argument(
-            "The user field is required for this request",
-        ))?;
-        let ip = user
-            .ip
-            .as_str()
-            .parse::<Ipv4Addr>()
-            .map_err(|err| Status::internal(format!("Internal Server Error: {}", err)))?;
-        // NOTE: please make the proto port a u16
-        let port: u16 = user
-            .port
-            .try_into()
-            .map_err(|err| Status::internal(format!("Internal Server Error: {}", err)))?;
-        let _res = self
-            .peer
-            .register_file(Cow::Owned(file_hash), ip, port, user.price, user.name)
-            .await
-            .map_err(|err| Status::internal(format!("Internal Server Error: {}", err)))?;
+        let file_hash = Cow::Owned(parse_file_hash(&file_req.file_hash)?);
+        let (user, addr, port) = parse_user(file_req.user)?;
+        let _res = match name {
+            Some(name) => {
+                peer.register_named_file(file_hash, &name, addr, port, user.price, user.name)
+                    .await
+            }
+            None => {
+                peer.register_file(file_hash, addr, port, user.price, user.name)
+                    .await
+            }
+        }
+        .map_err(peer_error)?;
         Ok(Response::new(()))
     }
 
@@ -51,28 +167,237 @@ impl Market for MarketService {
         &self,
         request: Request<CheckHoldersRequest>,
     ) -> Result<Response<HoldersResponse>, Status> {
-        // NOTE: Please make the file_hash not a String but a Vec<u8>
+        let peer = self.peer_for(&request);
         let holders_req = request.into_inner();
-        let file_hash = Cow::Owned(holders_req.file_hash.as_bytes().to_vec());
-        if let ResponseData::ReqResResponse(FileReqResResponseData::GetSuppliers { suppliers }) =
-            self.peer
-                .check_holders(file_hash)
-                .await
-                .map_err(|err| Status::internal(format!("Internal Server Error: {}", err)))?
+        let file_hash = Cow::Owned(parse_file_hash(&holders_req.file_hash)?);
+        if let ResponseData::ReqResResponse(FileReqResResponseData::GetSuppliers {
+            suppliers,
+            reputations,
+        }) = peer.check_holders(file_hash).await.map_err(peer_error)?
         {
+            let scores = suppliers
+                .iter()
+                .filter_map(|(peer_id, _)| {
+                    let reputation = reputations.get(peer_id)?;
+                    Some(format!("{peer_id}={:.3}", reputation.score()))
+                })
+                .collect::<Vec<_>>()
+                .join(",");
             let holders = suppliers
                 .into_iter()
-                .map(|(peer_id, supplier_info)| {
-                    User::new(
-                        peer_id.to_string(),
-                        supplier_info.username,
-                        supplier_info.ip.to_string(),
-                        supplier_info.port as i32,
-                        supplier_info.price,
-                    )
+                .map(|(peer_id, supplier_info)| to_user(peer_id.to_string(), supplier_info))
+                .collect::<Vec<_>>();
+            let mut response = Response::new(HoldersResponse { holders });
+            if !scores.is_empty() {
+                let scores = scores.parse().map_err(|err| {
+                    Status::internal(format!("Failed to encode the holder scores: {}", err))
+                })?;
+                response
+                    .metadata_mut()
+                    .insert(HOLDER_SCORES_METADATA, scores);
+            }
+            Ok(response)
+        } else {
+            Err(Status::internal(
+                "Did not get the right response for some reason...",
+            ))
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl MarketExt for MarketService {
+    type WatchMarketStream = Pin<Box<dyn Stream<Item = Result<MarketEvent, Status>> + Send>>;
+
+    async fn register_path(
+        &self,
+        request: Request<RegisterPathRequest>,
+    ) -> Result<Response<RegisterPathResponse>, Status> {
+        let peer = self.peer_for(&request);
+        let path_req = request.into_inner();
+        let (user, addr, port) = parse_user(path_req.user)?;
+        if let ResponseData::KadResponse(KadResponseData::RegisterFile { key }) = peer
+            .register_path(path_req.path, addr, port, user.price, user.name)
+            .await
+            .map_err(peer_error)?
+        {
+            Ok(Response::new(RegisterPathResponse {
+                file_hash: key.to_string(),
+            }))
+        } else {
+            Err(Status::internal(
+                "Did not get the right response for some reason...",
+            ))
+        }
+    }
+
+    async fn update_file(
+        &self,
+        request: Request<UpdateFileRequest>,
+    ) -> Result<Response<UpdateFileResponse>, Status> {
+        let peer = self.peer_for(&request);
+        let update_req = request.into_inner();
+        let file_hash = parse_file_hash(&update_req.file_hash)?;
+        let addr = update_req.ip.as_deref().map(parse_addr).transpose()?;
+        let port = update_req
+            .port
+            .map(u16::try_from)
+            .transpose()
+            .map_err(|err| Status::invalid_argument(format!("Invalid port: {}", err)))?;
+        let update = ListingUpdate {
+            price: update_req.price,
+            addr,
+            port,
+            username: update_req.name,
+        };
+        if let ResponseData::UpdateListing { old, new } = peer
+            .update_listing(Cow::Owned(file_hash), update)
+            .await
+            .map_err(peer_error)?
+        {
+            let id = peer.id().to_string();
+            Ok(Response::new(UpdateFileResponse {
+                old: Some(to_user(id.clone(), old)),
+                new: Some(to_user(id, new)),
+            }))
+        } else {
+            Err(Status::internal(
+                "Did not get the right response for some reason...",
+            ))
+        }
+    }
+
+    async fn index_file(
+        &self,
+        request: Request<IndexFileRequest>,
+    ) -> Result<Response<IndexFileResponse>, Status> {
+        let peer = self.peer_for(&request);
+        let index_req = request.into_inner();
+        let file_hash = Cow::Owned(parse_file_hash(&index_req.file_hash)?);
+        if let ResponseData::KadResponse(KadResponseData::IndexFile { keywords }) = peer
+            .index_file(file_hash, &index_req.name, &index_req.tags)
+            .await
+            .map_err(peer_error)?
+        {
+            Ok(Response::new(IndexFileResponse { keywords }))
+        } else {
+            Err(Status::internal(
+                "Did not get the right response for some reason...",
+            ))
+        }
+    }
+
+    async fn search_files(
+        &self,
+        request: Request<SearchFilesRequest>,
+    ) -> Result<Response<SearchFilesResponse>, Status> {
+        let peer = self.peer_for(&request);
+        let search_req = request.into_inner();
+        // NOTE: the page token is the offset into the results, which are sorted
+        let offset = if search_req.page_token.is_empty() {
+            0
+        } else {
+            search_req
+                .page_token
+                .parse::<usize>()
+                .map_err(|err| Status::invalid_argument(format!("Invalid page token: {}", err)))?
+        };
+        let page_size = match search_req.page_size as usize {
+            0 => DEFAULT_SEARCH_PAGE_SIZE,
+            page_size => page_size.min(MAX_SEARCH_PAGE_SIZE),
+        };
+        // NOTE: the first page searches again, the others page through the results of that search
+        let file_hashes = self.search(&peer, &search_req.query, offset > 0).await?;
+        let end = offset.saturating_add(page_size);
+        let next_page_token = if end < file_hashes.len() {
+            end.to_string()
+        } else {
+            String::new()
+        };
+        let file_hashes = file_hashes
+            .iter()
+            .skip(offset)
+            .take(page_size)
+            .map(|file_hash| file_hash.to_string())
+            .collect::<Vec<_>>();
+        Ok(Response::new(SearchFilesResponse {
+            file_hashes,
+            next_page_token,
+        }))
+    }
+
+    async fn watch_market(
+        &self,
+        request: Request<WatchMarketRequest>,
+    ) -> Result<Response<Self::WatchMarketStream>, Status> {
+        let peer = self.peer_for(&request);
+        let topics = request
+            .into_inner()
+            .topics()
+            .map(|topic| match topic {
+                ProtoMarketTopic::NewListings => MarketTopic::NewListings,
+                ProtoMarketTopic::PriceChanges => MarketTopic::PriceChanges,
+                ProtoMarketTopic::WithdrawnListings => MarketTopic::WithdrawnListings,
+            })
+            .collect::<Vec<_>>();
+        let subscription = if topics.is_empty() {
+            peer.subscribe(MarketTopic::ALL)
+        } else {
+            peer.subscribe(topics)
+        };
+        let events = stream::unfold(subscription, |mut subscription| async move {
+            let announcement = subscription.recv().await?;
+            Some((Ok(to_market_event(announcement)), subscription))
+        });
+        Ok(Response::new(Box::pin(events)))
+    }
+
+    async fn get_listing_stats(
+        &self,
+        request: Request<GetListingStatsRequest>,
+    ) -> Result<Response<GetListingStatsResponse>, Status> {
+        let peer = self.peer_for(&request);
+        if let ResponseData::ListingStats { stats } =
+            peer.listing_stats().await.map_err(peer_error)?
+        {
+            let files = stats
+                .files
+                .into_iter()
+                .map(|file| FileStats {
+                    file_hash: file.file_hash.to_string(),
+                    requests: Some(to_windowed_counts(file.requests)),
+                })
+                .collect::<Vec<_>>();
+            let requesters = stats
+                .peers
+                .into_iter()
+                .map(|peer| RequesterStats {
+                    peer_id: peer.peer_id.to_string(),
+                    requests: Some(to_windowed_counts(peer.requests)),
                 })
                 .collect::<Vec<_>>();
-            Ok(Response::new(HoldersResponse { holders }))
+            Ok(Response::new(GetListingStatsResponse { files, requesters }))
+        } else {
+            Err(Status::internal(
+                "Did not get the right response for some reason...",
+            ))
+        }
+    }
+
+    async fn list_my_files(
+        &self,
+        request: Request<ListMyFilesRequest>,
+    ) -> Result<Response<ListMyFilesResponse>, Status> {
+        let peer = self.peer_for(&request);
+        if let ResponseData::LocalListings { listings } =
+            peer.local_listings().await.map_err(peer_error)?
+        {
+            let id = peer.id().to_string();
+            let listings = listings
+                .into_iter()
+                .map(|listing| to_listing(id.clone(), listing))
+                .collect::<Vec<_>>();
+            Ok(Response::new(ListMyFilesResponse { listings }))
         } else {
             Err(Status::internal(
                 "Did not get the right response for some reason...",
@@ -80,3 +405,145 @@ impl Market for MarketService {
         }
     }
 }
+
+fn to_listing(id: String, listing: ListingInfo) -> Listing {
+    let (last_publish, last_publish_at, last_publish_error) = match listing.last_publish {
+        PublishStatus::Succeeded { at } => {
+            (ProtoPublishStatus::Succeeded, Some(unix_secs(at)), None)
+        }
+        PublishStatus::Failed { at, error } => {
+            (ProtoPublishStatus::Failed, Some(unix_secs(at)), Some(error))
+        }
+        _ => (ProtoPublishStatus::Pending, None, None),
+    };
+    Listing {
+        file_hash: listing.file_hash.to_string(),
+        user: Some(to_user(id, listing.supplier_info)),
+        registered_at: unix_secs(listing.registered_at),
+        expires_at: unix_secs(listing.expires_at),
+        last_publish: last_publish.into(),
+        last_publish_at,
+        last_publish_error,
+    }
+}
+
+fn to_windowed_counts(requests: WindowedCounts) -> WindowedRequestCounts {
+    let to_counts = |counts: RequestCounts| ProtoRequestCounts {
+        supplier_info: counts.supplier_info,
+        provider_lookups: counts.provider_lookups,
+    };
+    WindowedRequestCounts {
+        last_hour: Some(to_counts(requests.last_hour)),
+        last_day: Some(to_counts(requests.last_day)),
+        total: Some(to_counts(requests.total)),
+    }
+}
+
+fn to_market_event(
+    MarketAnnouncement {
+        supplier,
+        published_at,
+        announcement,
+    }: MarketAnnouncement,
+) -> MarketEvent {
+    let topic = match announcement.topic() {
+        MarketTopic::PriceChanges => ProtoMarketTopic::PriceChanges,
+        MarketTopic::WithdrawnListings => ProtoMarketTopic::WithdrawnListings,
+        _ => ProtoMarketTopic::NewListings,
+    };
+    let file_hash = announcement.file_hash().to_string();
+    let (user, old_price) = match announcement {
+        Announcement::NewListing { supplier_info, .. } => {
+            (to_user(supplier.to_string(), supplier_info), None)
+        }
+        Announcement::PriceChanged {
+            supplier_info,
+            old_price,
+            ..
+        } => (
+            to_user(supplier.to_string(), supplier_info),
+            Some(old_price),
+        ),
+        _ => (
+            User::new(supplier.to_string(), String::new(), String::new(), 0, 0),
+            None,
+        ),
+    };
+    MarketEvent {
+        topic: topic.into(),
+        file_hash,
+        user: Some(user),
+        old_price,
+        published_at: unix_secs(published_at),
+    }
+}
+
+fn unix_secs(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+fn to_user(id: String, supplier_info: SupplierInfo) -> User {
+    User::new(
+        id,
+        supplier_info.username,
+        supplier_info.addr.to_string(),
+        supplier_info.port as i32,
+        supplier_info.price,
+    )
+}
+
+/// Requests that the node is too busy to take are reported as `RESOURCE_EXHAUSTED` so that clients
+/// know to back off and retry.
+fn peer_error(err: anyhow::Error) -> Status {
+    if let Some(busy @ PeerError::Busy { .. }) = err.downcast_ref::<PeerError>() {
+        return Status::resource_exhausted(busy.to_string());
+    }
+    if let Some(not_listed @ ListingError::NotListed { .. }) = err.downcast_ref::<ListingError>() {
+        return Status::not_found(not_listed.to_string());
+    }
+    if let Some(search) = err.downcast_ref::<SearchError>() {
+        return Status::invalid_argument(search.to_string());
+    }
+    match err.downcast_ref::<RegisterPathError>() {
+        Some(outside @ RegisterPathError::OutsideFileTransferDir { .. }) => {
+            Status::permission_denied(outside.to_string())
+        }
+        Some(err) => Status::failed_precondition(err.to_string()),
+        None => Status::internal(format!("Internal Server Error: {}", err)),
+    }
+}
+
+/// Accepts a hex encoded SHA-256 digest, a base58 multihash or a CIDv1.
+#[allow(clippy::result_large_err)]
+fn parse_file_hash(file_hash: &str) -> Result<FileHash, Status> {
+    file_hash
+        .parse::<FileHash>()
+        .map_err(|err| Status::invalid_argument(format!("Invalid file hash: {}", err)))
+}
+
+/// Accepts an IPv4 or IPv6 address, a DNS name or a multiaddr.
+#[allow(clippy::result_large_err)]
+fn parse_addr(addr: &str) -> Result<SupplierAddr, Status> {
+    addr.parse::<SupplierAddr>()
+        .map_err(|err| Status::invalid_argument(format!("Invalid address: {}", err)))
+}
+
+/// An empty ip leaves it up to the peer to detect its own address.
+#[allow(clippy::result_large_err)]
+fn parse_user(user: Option<User>) -> Result<(User, Option<SupplierAddr>, u16), Status> {
+    let user = user.ok_or(Status::invalid_argument(
+        "The user field is required for this request",
+    ))?;
+    let addr = if user.ip.is_empty() {
+        None
+    } else {
+        Some(parse_addr(&user.ip)?)
+    };
+    // NOTE: please make the proto port a u16
+    let port: u16 = user
+        .port
+        .try_into()
+        .map_err(|err| Status::internal(format!("Internal Server Error: {}", err)))?;
+    Ok((user, addr, port))
+}
@@ -0,0 +1,53 @@
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use market_dht::metrics::{encode, Registry};
+
+const METRICS_PATH: &str = "/metrics";
+const CONTENT_TYPE_TEXT: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Serves the metrics in `registry` on `GET /metrics` until the server fails.
+pub async fn serve_metrics(addr: SocketAddr, registry: Arc<Mutex<Registry>>) -> hyper::Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let registry = registry.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = respond(&request, &registry);
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+    Server::try_bind(&addr)?.serve(make_service).await
+}
+
+fn respond(request: &Request<Body>, registry: &Mutex<Registry>) -> Response<Body> {
+    if request.method() != Method::GET || request.uri().path() != METRICS_PATH {
+        return status(StatusCode::NOT_FOUND);
+    }
+    let mut encoded = String::new();
+    let registry = registry
+        .lock()
+        .expect("the metrics registry to not be poisoned");
+    if encode(&mut encoded, &registry).is_err() {
+        return status(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    Response::builder()
+        .header(CONTENT_TYPE, CONTENT_TYPE_TEXT)
+        .body(Body::from(encoded))
+        .expect("the response to be valid")
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .expect("the response to be valid")
+}
@@ -0,0 +1,120 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{runtime, trace, trace::Tracer, Resource};
+use tonic::{codegen::http, metadata::MetadataMap};
+use tracing::{info_span, Span, Subscriber};
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::{
+    filter::LevelFilter, fmt, fmt::MakeWriter, layer::SubscriberExt, registry::LookupSpan, Layer,
+};
+
+/// Header that clients can set to pick the id that a gRPC request is logged under.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const SERVICE_NAME: &str = "market_server";
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// One human readable line per event
+    Compact,
+    /// One JSON object per event, along with the spans it happened in
+    Json,
+}
+
+/// Installs the global subscriber. With an `otlp_endpoint` the spans are also exported to an
+/// OpenTelemetry collector over gRPC, which needs to happen from within a tokio runtime.
+pub fn init(format: LogFormat, otlp_endpoint: Option<String>) -> Result<()> {
+    tracing_log::LogTracer::init()?;
+    let otlp_layer = otlp_endpoint.map(otlp_layer).transpose()?;
+    let subscriber = tracing_subscriber::registry()
+        .with(fmt_layer(format, std::io::stdout))
+        .with(otlp_layer)
+        .with(LevelFilter::INFO);
+    tracing::subscriber::set_global_default(subscriber)?;
+    Ok(())
+}
+
+/// The layer that writes the log lines in `format` to `make_writer`.
+pub fn fmt_layer<S, W>(format: LogFormat, make_writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Compact => fmt::layer()
+            .compact()
+            .with_line_number(true)
+            .with_file(true)
+            .with_writer(make_writer)
+            .boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_writer(make_writer)
+            .boxed(),
+    }
+}
+
+/// The layer that exports the spans to the OpenTelemetry collector at `endpoint`, in batches
+/// from the tokio runtime it is created in.
+pub fn otlp_layer<S>(endpoint: String) -> Result<OpenTelemetryLayer<S, Tracer>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(
+            trace::config()
+                .with_resource(Resource::new([KeyValue::new("service.name", SERVICE_NAME)])),
+        )
+        .install_batch(runtime::Tokio)?;
+    Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// Flushes the spans that were not exported yet.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// The span that a gRPC request is handled in. Everything the request leads to in the market,
+/// down to the Kademlia queries and the requests to other peers, is logged within it.
+///
+/// The `request_id` is the [`REQUEST_ID_HEADER`] if the client set one; otherwise it is recorded
+/// once the request reaches the service, see [`request_id`].
+pub fn grpc_span(request: &http::Request<()>) -> Span {
+    let span = info_span!(
+        "grpc",
+        method = request.uri().path(),
+        request_id = tracing::field::Empty
+    );
+    if let Some(request_id) = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        span.record("request_id", request_id);
+    }
+    span
+}
+
+/// The id that a gRPC request is logged under: the [`REQUEST_ID_HEADER`] if the client set one,
+/// a new one otherwise.
+pub fn request_id(metadata: &MetadataMap) -> String {
+    metadata
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(ToOwned::to_owned)
+        // NOTE: only unique for as long as the server runs
+        .unwrap_or_else(|| NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed).to_string())
+}
@@ -0,0 +1,106 @@
+use std::sync::{Arc, Mutex};
+
+use market_dht::testing::TestNetwork;
+use market_proto::market_ext_proto_rpc::{market_ext_server::MarketExt, ListMyFilesRequest};
+use market_server::{
+    market_service::MarketService,
+    telemetry::{self, REQUEST_ID_HEADER},
+};
+use pretty_assertions::assert_eq;
+use tonic::{codegen::http, Request};
+use tracing::{
+    field::{Field, Visit},
+    span, Instrument, Subscriber,
+};
+use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+type RequestIds = Arc<Mutex<Vec<(&'static str, String)>>>;
+
+/// Remembers the `request_id` of every span that gets one, when it is created or later on.
+struct RequestIdRecorder(RequestIds);
+
+impl RequestIdRecorder {
+    fn push<S>(&self, values: &span::Record<'_>, id: &span::Id, ctx: Context<'_, S>)
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let mut request_id = RequestIdVisitor(None);
+        values.record(&mut request_id);
+        if let Some(request_id) = request_id.0 {
+            let span = ctx.span(id).expect("the span to exist");
+            self.0.lock().unwrap().push((span.name(), request_id));
+        }
+    }
+}
+
+impl<S> Layer<S> for RequestIdRecorder
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        self.push(&span::Record::new(attrs.values()), id, ctx);
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        self.push(values, id, ctx);
+    }
+}
+
+struct RequestIdVisitor(Option<String>);
+
+impl Visit for RequestIdVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "request_id" {
+            self.0 = Some(value.to_owned());
+        }
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn std::fmt::Debug) {}
+}
+
+/// Calls `ListMyFiles` the way the server does, within the gRPC span of the request.
+async fn list_my_files(service: &MarketService, request_id: Option<&str>) {
+    let mut http_request =
+        http::Request::builder().uri("/market_ext_proto_rpc.MarketExt/ListMyFiles");
+    let mut request = Request::new(ListMyFilesRequest {});
+    if let Some(request_id) = request_id {
+        http_request = http_request.header(REQUEST_ID_HEADER, request_id);
+        request
+            .metadata_mut()
+            .insert(REQUEST_ID_HEADER, request_id.parse().unwrap());
+    }
+    let span = telemetry::grpc_span(&http_request.body(()).unwrap());
+    service
+        .list_my_files(request)
+        .instrument(span)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_request_ids_reach_the_requests_to_the_node() {
+    let request_ids = RequestIds::default();
+    tracing_subscriber::registry()
+        .with(RequestIdRecorder(request_ids.clone()))
+        .init();
+    let network = TestNetwork::spawn(1).unwrap();
+    let service = MarketService::new(network.node(0).peer().clone());
+
+    list_my_files(&service, Some("req-1")).await;
+    let with_header = request_ids.lock().unwrap().drain(..).collect::<Vec<_>>();
+    assert_eq!(
+        with_header,
+        vec![
+            ("grpc", "req-1".to_owned()),
+            ("grpc", "req-1".to_owned()),
+            ("request", "req-1".to_owned())
+        ]
+    );
+
+    list_my_files(&service, None).await;
+    let without_header = request_ids.lock().unwrap().drain(..).collect::<Vec<_>>();
+    let [("grpc", generated), ("request", request_id)] = without_header.as_slice() else {
+        panic!("unexpected request ids: {without_header:?}");
+    };
+    assert_eq!(request_id, generated);
+}
@@ -0,0 +1,146 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+use market_server::telemetry::{self, LogFormat, REQUEST_ID_HEADER};
+use opentelemetry_proto::tonic::{
+    collector::trace::v1::{
+        trace_service_server::{TraceService, TraceServiceServer},
+        ExportTraceServiceRequest, ExportTraceServiceResponse,
+    },
+    common::v1::any_value::Value,
+    trace::v1::Span as ProtoSpan,
+};
+use pretty_assertions::assert_eq;
+use serde_json::Value as Json;
+use tokio::{
+    net::TcpListener,
+    sync::mpsc,
+    time::{self, Duration},
+};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::{codegen::http, transport::Server, Request, Response, Status};
+use tracing::info;
+use tracing_subscriber::prelude::*;
+
+const METHOD: &str = "/market_proto_rpc.Market/CheckHolders";
+
+fn http_request(request_id: Option<&str>) -> http::Request<()> {
+    let mut request = http::Request::builder().uri(METHOD);
+    if let Some(request_id) = request_id {
+        request = request.header(REQUEST_ID_HEADER, request_id);
+    }
+    request.body(()).unwrap()
+}
+
+/// Everything the JSON logs are written to.
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_json_logs_carry_the_grpc_span() {
+    let buffer = Buffer::default();
+    let writer = buffer.clone();
+    let subscriber = tracing_subscriber::registry()
+        .with(telemetry::fmt_layer(LogFormat::Json, move || {
+            writer.clone()
+        }));
+
+    tracing::subscriber::with_default(subscriber, || {
+        let _span = telemetry::grpc_span(&http_request(Some("req-1"))).entered();
+        info!("handled");
+    });
+
+    let logs = buffer.0.lock().unwrap();
+    let lines = std::str::from_utf8(&logs)
+        .unwrap()
+        .lines()
+        .collect::<Vec<_>>();
+    assert_eq!(lines.len(), 1);
+    let line: Json = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(line["level"], "INFO");
+    assert_eq!(line["fields"]["message"], "handled");
+    assert_eq!(line["span"]["name"], "grpc");
+    assert_eq!(line["span"]["method"], METHOD);
+    assert_eq!(line["span"]["request_id"], "req-1");
+    assert_eq!(line["spans"][0]["request_id"], "req-1");
+}
+
+/// A collector that passes on every span it is sent.
+struct Collector(mpsc::UnboundedSender<ProtoSpan>);
+
+#[tonic::async_trait]
+impl TraceService for Collector {
+    async fn export(
+        &self,
+        request: Request<ExportTraceServiceRequest>,
+    ) -> Result<Response<ExportTraceServiceResponse>, Status> {
+        let spans = request
+            .into_inner()
+            .resource_spans
+            .into_iter()
+            .flat_map(|resource_spans| resource_spans.scope_spans)
+            .flat_map(|scope_spans| scope_spans.spans);
+        for span in spans {
+            let _ = self.0.send(span);
+        }
+        Ok(Response::new(ExportTraceServiceResponse::default()))
+    }
+}
+
+fn attribute<'a>(span: &'a ProtoSpan, key: &str) -> Option<&'a Value> {
+    span.attributes
+        .iter()
+        .find(|attribute| attribute.key == key)?
+        .value
+        .as_ref()?
+        .value
+        .as_ref()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_spans_are_exported_over_otlp() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    tokio::spawn(
+        Server::builder()
+            .add_service(TraceServiceServer::new(Collector(tx)))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+
+    let subscriber = tracing_subscriber::registry().with(telemetry::otlp_layer(endpoint).unwrap());
+    tracing::subscriber::with_default(subscriber, || {
+        let _span = telemetry::grpc_span(&http_request(Some("req-1"))).entered();
+        info!("handled");
+    });
+    tokio::task::spawn_blocking(telemetry::shutdown)
+        .await
+        .unwrap();
+
+    let span = time::timeout(Duration::from_secs(10), rx.recv())
+        .await
+        .expect("the span to be exported")
+        .unwrap();
+    assert_eq!(span.name, "grpc");
+    assert_eq!(
+        attribute(&span, "request_id"),
+        Some(&Value::StringValue("req-1".to_owned()))
+    );
+    assert_eq!(
+        attribute(&span, "method"),
+        Some(&Value::StringValue(METHOD.to_owned()))
+    );
+}
@@ -0,0 +1,12 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+/// Runs a scenario against many market nodes in one process and reports the lookup success rate,
+/// latency and record availability. Networks of hundreds of nodes call for a release build.
+#[derive(Debug, Parser)]
+#[command(author, version, long_about = None)]
+pub struct Cli {
+    /// JSON file with the scenario to run, see the `scenarios` directory for examples
+    pub scenario: PathBuf,
+}
@@ -0,0 +1,7 @@
+#![warn(missing_debug_implementations, unreachable_pub)]
+#![deny(unsafe_code)]
+
+pub mod cli;
+pub mod report;
+pub mod scenario;
+pub mod sim;
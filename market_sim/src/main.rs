@@ -0,0 +1,15 @@
+use anyhow::Result;
+use clap::Parser;
+use market_sim::{cli::Cli, scenario::Scenario, sim::Simulation};
+use tokio::runtime::Runtime;
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let scenario = Scenario::from_file(&cli.scenario)?;
+    let report = Runtime::new()?.block_on(async move {
+        let simulation = Simulation::start(scenario)?;
+        simulation.run().await
+    })?;
+    print!("{report}");
+    Ok(())
+}
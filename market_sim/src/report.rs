@@ -0,0 +1,209 @@
+use std::{fmt, time::Duration};
+
+/// How a single lookup went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LookupResult {
+    /// The lookup answered with this many of the expected suppliers.
+    Found(usize),
+    Failed,
+    TimedOut,
+}
+
+/// Lookups of one step or of the whole simulation. Lookups of files without a live supplier
+/// cannot succeed, so they are left out of the success rate and the availability. Suppliers behind
+/// failed links still count, since finding them despite the failures is what lookups are for.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LookupStats {
+    pub lookups: usize,
+    /// Lookups that found at least one live supplier.
+    pub succeeded: usize,
+    pub failed: usize,
+    pub timed_out: usize,
+    /// Lookups of files that had no live supplier at the time.
+    pub without_suppliers: usize,
+    found_share: f64,
+    latencies: Vec<Duration>,
+}
+
+impl LookupStats {
+    /// Records a lookup that was expected to find the `expected` suppliers that were live at the
+    /// time.
+    pub fn record(&mut self, expected: usize, result: LookupResult, latency: Duration) {
+        self.lookups += 1;
+        match result {
+            _ if expected == 0 => self.without_suppliers += 1,
+            LookupResult::Found(found) => {
+                if found > 0 {
+                    self.succeeded += 1;
+                }
+                self.found_share += found as f64 / expected as f64;
+            }
+            LookupResult::Failed => self.failed += 1,
+            LookupResult::TimedOut => self.timed_out += 1,
+        }
+        if result != LookupResult::TimedOut {
+            self.latencies.push(latency);
+        }
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        self.lookups += other.lookups;
+        self.succeeded += other.succeeded;
+        self.failed += other.failed;
+        self.timed_out += other.timed_out;
+        self.without_suppliers += other.without_suppliers;
+        self.found_share += other.found_share;
+        self.latencies.extend_from_slice(&other.latencies);
+    }
+
+    const fn with_suppliers(&self) -> usize {
+        self.lookups - self.without_suppliers
+    }
+
+    /// Share of the lookups that found a supplier.
+    pub fn success_rate(&self) -> Option<f64> {
+        let with_suppliers = self.with_suppliers();
+        (with_suppliers > 0).then(|| self.succeeded as f64 / with_suppliers as f64)
+    }
+
+    /// Share of the live suppliers that a lookup found on average, which is how many of the
+    /// provider records are still available.
+    pub fn availability(&self) -> Option<f64> {
+        let with_suppliers = self.with_suppliers();
+        (with_suppliers > 0).then(|| self.found_share / with_suppliers as f64)
+    }
+
+    /// The latency below which `percentile` percent of the lookups that did not time out
+    /// finished.
+    pub fn latency(&self, percentile: f64) -> Option<Duration> {
+        let mut latencies = self.latencies.clone();
+        latencies.sort();
+        let rank = (percentile / 100.0 * latencies.len() as f64).ceil() as usize;
+        latencies.get(rank.saturating_sub(1)).copied()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LookupStep {
+    /// Index of the step in the scenario.
+    pub step: usize,
+    pub file: String,
+    pub stats: LookupStats,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConvergeStep {
+    pub step: usize,
+    /// How long the network took to converge, if it did in time.
+    pub took: Option<Duration>,
+}
+
+/// What [`crate::sim::Simulation::run`] observed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    pub lookups: Vec<LookupStep>,
+    pub convergence: Vec<ConvergeStep>,
+    pub joined: usize,
+    pub left: usize,
+    pub elapsed: Duration,
+}
+
+impl Report {
+    pub fn total(&self) -> LookupStats {
+        let mut total = LookupStats::default();
+        for step in &self.lookups {
+            total.merge(&step.stats);
+        }
+        total
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Simulated for {:.1?} with {} nodes joining and {} leaving",
+            self.elapsed, self.joined, self.left
+        )?;
+        for converge in &self.convergence {
+            match converge.took {
+                Some(took) => writeln!(f, "step {}: converged in {took:.1?}", converge.step)?,
+                None => writeln!(f, "step {}: did not converge", converge.step)?,
+            }
+        }
+        writeln!(
+            f,
+            "{:>5} {:<20} {:>7} {:>8} {:>8} {:>13} {:>9} {:>9} {:>9}",
+            "step", "file", "lookups", "success", "avail", "failed/t.out", "p50", "p90", "max"
+        )?;
+        for lookup in &self.lookups {
+            write_stats(f, &lookup.step.to_string(), &lookup.file, &lookup.stats)?;
+        }
+        write_stats(f, "total", "", &self.total())
+    }
+}
+
+fn write_stats(
+    f: &mut fmt::Formatter<'_>,
+    step: &str,
+    file: &str,
+    stats: &LookupStats,
+) -> fmt::Result {
+    let percent = |rate: Option<f64>| match rate {
+        Some(rate) => format!("{:.1}%", rate * 100.0),
+        None => "-".to_owned(),
+    };
+    let millis = |latency: Option<Duration>| match latency {
+        Some(latency) => format!("{}ms", latency.as_millis()),
+        None => "-".to_owned(),
+    };
+    writeln!(
+        f,
+        "{step:>5} {file:<20} {:>7} {:>8} {:>8} {:>13} {:>9} {:>9} {:>9}",
+        stats.lookups,
+        percent(stats.success_rate()),
+        percent(stats.availability()),
+        format!("{}/{}", stats.failed, stats.timed_out),
+        millis(stats.latency(50.0)),
+        millis(stats.latency(90.0)),
+        millis(stats.latency(100.0)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{LookupResult, LookupStats};
+
+    #[test]
+    fn test_lookup_stats() {
+        let mut stats = LookupStats::default();
+        assert_eq!(stats.success_rate(), None);
+        assert_eq!(stats.latency(50.0), None);
+
+        let ms = Duration::from_millis;
+        stats.record(2, LookupResult::Found(2), ms(10));
+        stats.record(2, LookupResult::Found(1), ms(30));
+        stats.record(1, LookupResult::Found(0), ms(20));
+        stats.record(0, LookupResult::Failed, ms(40));
+        stats.record(1, LookupResult::TimedOut, ms(1000));
+        stats.record(0, LookupResult::TimedOut, ms(1000));
+
+        assert_eq!(stats.lookups, 6);
+        assert_eq!(stats.without_suppliers, 2);
+        assert_eq!(stats.timed_out, 1);
+        assert_eq!(stats.success_rate(), Some(0.5));
+        assert_eq!(stats.availability(), Some(1.5 / 4.0));
+        assert_eq!(stats.latency(50.0), Some(ms(20)));
+        assert_eq!(stats.latency(100.0), Some(ms(40)));
+
+        let mut merged = LookupStats::default();
+        merged.merge(&stats);
+        merged.merge(&stats);
+        assert_eq!(merged.lookups, 12);
+        assert_eq!(merged.success_rate(), Some(0.5));
+    }
+}
@@ -0,0 +1,169 @@
+//! Scenarios are JSON files that list the steps of a simulation, which run one after another.
+//! Nodes are referred to by their index, in the order in which they joined, starting with the
+//! nodes that are up from the start. For example:
+//!
+//! ```json
+//! {
+//!   "nodes": 20,
+//!   "steps": [
+//!     { "action": "converge", "timeout_ms": 30000 },
+//!     { "action": "register", "file": "movie.mp4", "nodes": [3, 7] },
+//!     { "action": "lookup", "file": "movie.mp4" },
+//!     { "action": "leave", "nodes": [3] },
+//!     { "action": "partition", "groups": [[0, 1, 2, 7], [4, 5, 6]] },
+//!     { "action": "lookup", "file": "movie.mp4", "from": [0, 4] },
+//!     { "action": "heal" },
+//!     { "action": "join", "count": 5 },
+//!     { "action": "wait", "ms": 1000 },
+//!     { "action": "lookup", "file": "movie.mp4" }
+//!   ]
+//! }
+//! ```
+
+use std::{fs, path::Path, time::Duration};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+const DEFAULT_LOOKUP_TIMEOUT_MS: u64 = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    /// Nodes that are up before the first step.
+    pub nodes: usize,
+    /// How long a lookup may take before it counts as timed out.
+    #[serde(default = "default_lookup_timeout_ms")]
+    pub lookup_timeout_ms: u64,
+    pub steps: Vec<Step>,
+}
+
+const fn default_lookup_timeout_ms() -> u64 {
+    DEFAULT_LOOKUP_TIMEOUT_MS
+}
+
+impl Scenario {
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read the scenario {}", path.display()))?;
+        Self::from_json(&json)
+            .with_context(|| format!("Failed to parse the scenario {}", path.display()))
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub const fn lookup_timeout(&self) -> Duration {
+        Duration::from_millis(self.lookup_timeout_ms)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Step {
+    /// Waits until the routing tables of the live nodes converge, for `timeout_ms` at most.
+    /// Failed links keep the network from converging.
+    Converge {
+        timeout_ms: u64,
+    },
+    Wait {
+        ms: u64,
+    },
+    /// Starts `count` new nodes, which boot from the first live node.
+    Join {
+        count: usize,
+    },
+    /// Shuts the nodes down. Their provider records stay behind in the network.
+    Leave {
+        nodes: Vec<usize>,
+    },
+    /// Registers the nodes as suppliers of `file`.
+    Register {
+        file: String,
+        nodes: Vec<usize>,
+    },
+    /// Looks up the suppliers of `file` from the nodes in `from`, or from every live node.
+    Lookup {
+        file: String,
+        from: Option<Vec<usize>>,
+    },
+    /// Cuts the nodes of each pair off from each other.
+    FailLinks {
+        links: Vec<(usize, usize)>,
+    },
+    HealLinks {
+        links: Vec<(usize, usize)>,
+    },
+    /// Fails the links between every two nodes of different groups. Nodes outside of the groups
+    /// are left as they are.
+    Partition {
+        groups: Vec<Vec<usize>>,
+    },
+    /// Heals every failed link.
+    Heal,
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{Scenario, Step, DEFAULT_LOOKUP_TIMEOUT_MS};
+
+    #[test]
+    fn test_from_json() {
+        let scenario = Scenario::from_json(
+            r#"{
+                "nodes": 3,
+                "steps": [
+                    { "action": "converge", "timeout_ms": 5000 },
+                    { "action": "register", "file": "a", "nodes": [1] },
+                    { "action": "lookup", "file": "a" },
+                    { "action": "fail_links", "links": [[0, 1]] },
+                    { "action": "lookup", "file": "a", "from": [0] },
+                    { "action": "heal" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            scenario,
+            Scenario {
+                nodes: 3,
+                lookup_timeout_ms: DEFAULT_LOOKUP_TIMEOUT_MS,
+                steps: vec![
+                    Step::Converge { timeout_ms: 5000 },
+                    Step::Register {
+                        file: "a".to_owned(),
+                        nodes: vec![1]
+                    },
+                    Step::Lookup {
+                        file: "a".to_owned(),
+                        from: None
+                    },
+                    Step::FailLinks {
+                        links: vec![(0, 1)]
+                    },
+                    Step::Lookup {
+                        file: "a".to_owned(),
+                        from: Some(vec![0])
+                    },
+                    Step::Heal,
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_from_json_rejects_unknown_fields() {
+        assert_eq!(
+            Scenario::from_json(r#"{ "nodes": 3, "steps": [{ "action": "wait", "secs": 1 }] }"#)
+                .is_err(),
+            true
+        );
+        assert_eq!(
+            Scenario::from_json(r#"{ "nodes": 3, "steps": [{ "action": "crash" }] }"#).is_err(),
+            true
+        );
+    }
+}
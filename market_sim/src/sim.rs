@@ -0,0 +1,217 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use futures::future::join_all;
+use market_dht::testing::{file_hash, TestNetwork};
+use tokio::time::{self, Instant};
+
+use crate::{
+    report::{ConvergeStep, LookupResult, LookupStats, LookupStep, Report},
+    scenario::{Scenario, Step},
+};
+
+/// Runs a [`Scenario`] against nodes that all live in this process and talk over the in-memory
+/// transport, see [`TestNetwork`].
+#[derive(Debug)]
+pub struct Simulation {
+    scenario: Scenario,
+    network: TestNetwork,
+    /// The nodes that registered each file.
+    suppliers: HashMap<String, BTreeSet<usize>>,
+    /// Failed links, with the lower index first.
+    failed_links: BTreeSet<(usize, usize)>,
+    report: Report,
+}
+
+impl Simulation {
+    /// Starts the nodes that are up before the first step.
+    pub fn start(scenario: Scenario) -> anyhow::Result<Self> {
+        let network =
+            TestNetwork::spawn(scenario.nodes).context("Failed to start the initial nodes")?;
+        Ok(Self {
+            scenario,
+            network,
+            suppliers: HashMap::new(),
+            failed_links: BTreeSet::new(),
+            report: Report::default(),
+        })
+    }
+
+    pub async fn run(mut self) -> anyhow::Result<Report> {
+        let started = Instant::now();
+        let steps = std::mem::take(&mut self.scenario.steps);
+        for (index, step) in steps.into_iter().enumerate() {
+            self.step(index, step)
+                .await
+                .with_context(|| format!("Step {index} failed"))?;
+        }
+        self.report.elapsed = started.elapsed();
+        Ok(self.report)
+    }
+
+    async fn step(&mut self, index: usize, step: Step) -> anyhow::Result<()> {
+        match step {
+            Step::Converge { timeout_ms } => {
+                let started = Instant::now();
+                let took = self
+                    .network
+                    .wait_for_convergence(Duration::from_millis(timeout_ms))
+                    .await
+                    .ok()
+                    .map(|()| started.elapsed());
+                self.report
+                    .convergence
+                    .push(ConvergeStep { step: index, took });
+            }
+            Step::Wait { ms } => time::sleep(Duration::from_millis(ms)).await,
+            Step::Join { count } => {
+                for _ in 0..count {
+                    self.network.join()?;
+                }
+                self.report.joined += count;
+            }
+            Step::Leave { nodes } => {
+                for node in nodes {
+                    self.check_live(node)?;
+                    self.network.leave(node).await;
+                    self.report.left += 1;
+                }
+            }
+            Step::Register { file, nodes } => {
+                let hash = file_hash(&file);
+                for node in nodes {
+                    self.check_live(node)?;
+                    self.network.register_file(node, &hash).await?;
+                    self.suppliers.entry(file.clone()).or_default().insert(node);
+                }
+            }
+            Step::Lookup { file, from } => {
+                let from = match from {
+                    Some(from) => {
+                        for node in &from {
+                            self.check_live(*node)?;
+                        }
+                        from
+                    }
+                    None => (0..self.network.len())
+                        .filter(|node| self.network.node(*node).is_live())
+                        .collect(),
+                };
+                let stats = self.lookup(&file, &from).await;
+                self.report.lookups.push(LookupStep {
+                    step: index,
+                    file,
+                    stats,
+                });
+            }
+            Step::FailLinks { links } => {
+                for (a, b) in links {
+                    self.fail_link(a, b)?;
+                }
+            }
+            Step::HealLinks { links } => {
+                for (a, b) in links {
+                    self.heal_link(a, b).await?;
+                }
+            }
+            Step::Partition { groups } => {
+                for (group_index, group) in groups.iter().enumerate() {
+                    for other in &groups[group_index + 1..] {
+                        for &a in group {
+                            for &b in other {
+                                self.fail_link(a, b)?;
+                            }
+                        }
+                    }
+                }
+            }
+            Step::Heal => {
+                let links = self.failed_links.clone();
+                for (a, b) in links {
+                    self.heal_link(a, b).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn lookup(&self, file: &str, from: &[usize]) -> LookupStats {
+        let hash = file_hash(file);
+        let timeout = self.scenario.lookup_timeout();
+        let lookups = from.iter().map(|&node| {
+            let hash = &hash;
+            async move {
+                let expected = self
+                    .suppliers
+                    .get(file)
+                    .into_iter()
+                    .flatten()
+                    .copied()
+                    .filter(|supplier| self.network.node(*supplier).is_live())
+                    .collect::<BTreeSet<_>>();
+                let started = Instant::now();
+                let result = match time::timeout(timeout, self.network.holders(node, hash)).await {
+                    Ok(Ok(holders)) => LookupResult::Found(
+                        expected
+                            .iter()
+                            .filter(|supplier| holders.contains(self.network.node(**supplier).id()))
+                            .count(),
+                    ),
+                    Ok(Err(_)) => LookupResult::Failed,
+                    Err(_) => LookupResult::TimedOut,
+                };
+                (expected.len(), result, started.elapsed())
+            }
+        });
+        let mut stats = LookupStats::default();
+        for (expected, result, latency) in join_all(lookups).await {
+            stats.record(expected, result, latency);
+        }
+        stats
+    }
+
+    /// Links to nodes that left are dropped along with the nodes, so they are skipped.
+    fn fail_link(&mut self, a: usize, b: usize) -> anyhow::Result<()> {
+        self.check_node(a)?;
+        self.check_node(b)?;
+        let link = (a.min(b), a.max(b));
+        if a == b || !self.is_live_link(link) || !self.failed_links.insert(link) {
+            return Ok(());
+        }
+        self.network.fail_link(a, b);
+        Ok(())
+    }
+
+    async fn heal_link(&mut self, a: usize, b: usize) -> anyhow::Result<()> {
+        let link = (a.min(b), a.max(b));
+        if !self.failed_links.remove(&link) || !self.is_live_link(link) {
+            return Ok(());
+        }
+        self.network.heal_link(a, b).await
+    }
+
+    fn is_live_link(&self, (a, b): (usize, usize)) -> bool {
+        self.network.node(a).is_live() && self.network.node(b).is_live()
+    }
+
+    fn check_node(&self, node: usize) -> anyhow::Result<()> {
+        if node >= self.network.len() {
+            bail!(
+                "There is no node {node}, only {} joined so far",
+                self.network.len()
+            );
+        }
+        Ok(())
+    }
+
+    fn check_live(&self, node: usize) -> anyhow::Result<()> {
+        self.check_node(node)?;
+        if !self.network.node(node).is_live() {
+            bail!("Node {node} has left");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+use market_sim::{scenario::Scenario, sim::Simulation};
+use pretty_assertions::assert_eq;
+
+#[tokio::test]
+async fn test_run_scenario() {
+    let scenario = Scenario::from_json(
+        r#"{
+            "nodes": 4,
+            "steps": [
+                { "action": "converge", "timeout_ms": 10000 },
+                { "action": "register", "file": "a", "nodes": [1] },
+                { "action": "lookup", "file": "a" },
+                { "action": "fail_links", "links": [[0, 1]] },
+                { "action": "lookup", "file": "a", "from": [0] },
+                { "action": "heal" },
+                { "action": "join", "count": 1 },
+                { "action": "leave", "nodes": [1] },
+                { "action": "lookup", "file": "a" }
+            ]
+        }"#,
+    )
+    .unwrap();
+    let report = Simulation::start(scenario).unwrap().run().await.unwrap();
+
+    assert_eq!(report.joined, 1);
+    assert_eq!(report.left, 1);
+    assert_eq!(report.convergence.len(), 1);
+    assert_eq!(report.convergence[0].took.is_some(), true);
+    let lookups = report
+        .lookups
+        .iter()
+        .map(|lookup| {
+            (
+                lookup.step,
+                lookup.stats.lookups,
+                lookup.stats.without_suppliers,
+                lookup.stats.success_rate().is_some(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        lookups,
+        vec![(2, 4, 0, true), (4, 1, 0, true), (8, 4, 4, false)]
+    );
+    let stats = &report.lookups[0].stats;
+    assert_eq!(stats.success_rate(), Some(1.0));
+    assert_eq!(stats.availability(), Some(1.0));
+    assert_eq!(report.total().lookups, 9);
+    assert_eq!(report.to_string().contains("total"), true);
+}
+
+#[tokio::test]
+async fn test_invalid_step() {
+    let scenario =
+        Scenario::from_json(r#"{ "nodes": 2, "steps": [{ "action": "leave", "nodes": [5] }] }"#)
+            .unwrap();
+    let err = Simulation::start(scenario)
+        .unwrap()
+        .run()
+        .await
+        .unwrap_err();
+    assert_eq!(
+        format!("{err:#}"),
+        "Step 0 failed: There is no node 5, only 2 joined so far"
+    );
+}
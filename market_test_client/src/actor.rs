@@ -1,8 +1,11 @@
 use anyhow::Result;
 use std::fmt::Display;
 
-use market_proto::market_proto_rpc::{
-    market_client::MarketClient, CheckHoldersRequest, RegisterFileRequest, User,
+use market_proto::{
+    market_ext_proto_rpc::{market_ext_client::MarketExtClient, ListMyFilesRequest},
+    market_proto_rpc::{
+        market_client::MarketClient, CheckHoldersRequest, RegisterFileRequest, User,
+    },
 };
 use std::str::FromStr;
 use strum_macros::EnumString;
@@ -21,14 +24,18 @@ impl Actor {
         Actor { user, receiver }
     }
 
-    pub async fn run(mut self, mut client: MarketClient<Channel>) {
+    pub async fn run(
+        mut self,
+        mut client: MarketClient<Channel>,
+        mut ext_client: MarketExtClient<Channel>,
+    ) {
         while let Some(cmd) = self.receiver.recv().await {
             match cmd {
                 Command::Quit => break,
                 Command::Help => {
                     // TODO: maybe make it more modular by enumiter in command
                     println!(
-                        "Available commands: quit, register <file_hash>, check <file_hash>, help"
+                        "Available commands: quit, register <file_hash>, check <file_hash>, list, help"
                     );
                 }
                 Command::RegisterFile { file_hash } => {
@@ -63,6 +70,34 @@ impl Actor {
                         }
                     }
                 }
+                Command::ListFiles => {
+                    let res = ext_client.list_my_files(ListMyFilesRequest {}).await;
+                    match res {
+                        Ok(res) => {
+                            let listings = res.into_inner().listings;
+                            if listings.is_empty() {
+                                println!("No files registered");
+                            }
+                            for listing in listings {
+                                let price = listing
+                                    .user
+                                    .as_ref()
+                                    .map(|user| user.price)
+                                    .unwrap_or_default();
+                                println!(
+                                    "File Hash: {}, Price Per MB: ${}, Expires At: {}, Last Publish: {:?}",
+                                    listing.file_hash,
+                                    price,
+                                    listing.expires_at,
+                                    listing.last_publish()
+                                );
+                            }
+                        }
+                        Err(err) => {
+                            eprintln!("Failed to list files: {}", err.message())
+                        }
+                    }
+                }
             }
         }
     }
@@ -76,6 +111,8 @@ pub enum Command {
     RegisterFile { file_hash: String },
     #[strum(serialize = "check")]
     CheckHolders { file_hash: String },
+    #[strum(serialize = "list")]
+    ListFiles,
     #[strum(serialize = "help")]
     Help,
 }
@@ -85,7 +122,7 @@ pub struct Message(String);
 
 impl Message {
     pub fn new(line: String) -> Self {
-        Message(line.to_lowercase())
+        Message(line)
     }
 
     pub fn into_command(self) -> Result<Command, CommandParseError> {
@@ -99,11 +136,14 @@ impl TryFrom<Message> for Command {
         let trimmed = value.0.trim();
         let mut iter = trimmed.split_whitespace();
         let cmd = iter.next().ok_or(CommandParseError::NoCommand)?;
-        let mut cmd = Command::from_str(cmd).map_err(|_| CommandParseError::NotFound {
-            cmd: cmd.to_owned(),
-        })?;
+        // NOTE: only the command is case insensitive since file hashes like base58 multihashes
+        // are not
+        let mut cmd =
+            Command::from_str(&cmd.to_lowercase()).map_err(|_| CommandParseError::NotFound {
+                cmd: cmd.to_owned(),
+            })?;
         Ok(match &mut cmd {
-            Command::Quit | Command::Help => cmd,
+            Command::Quit | Command::Help | Command::ListFiles => cmd,
             Command::RegisterFile {
                 file_hash: cur_hash,
             }
@@ -177,6 +217,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_message_keeps_file_hash_case() {
+        let register = Message::new("REGISTER QmYwAPJzv5CZsnA".to_owned())
+            .into_command()
+            .unwrap();
+        assert_eq!(
+            register,
+            Command::RegisterFile {
+                file_hash: "QmYwAPJzv5CZsnA".to_owned()
+            }
+        );
+    }
+
+    #[test]
+    fn test_message_list_command_conversion() {
+        let cmd = Message::new("list".to_owned()).into_command().unwrap();
+        assert_eq!(cmd, Command::ListFiles);
+    }
+
     #[test]
     fn test_message_check_holders_args_command_conversion() {
         let request = Message::new("check sample_hash".to_owned())
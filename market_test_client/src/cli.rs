@@ -1,5 +1,3 @@
-use std::net::Ipv4Addr;
-
 use clap::Parser;
 
 use crate::util::DEFAULT_MARKET_SERVER_PORT;
@@ -27,7 +25,9 @@ pub struct Cli {
     /// Port where other consumer peer clients should connect to retrieve files
     #[arg(long)]
     pub client_port: Port,
-    /// IP where other consumer peer clients should connect to retrieve files
+    /// Address where other consumer peer clients should connect to retrieve files. Either an IPv4
+    /// or IPv6 address, a DNS name or a multiaddr. If not provided, then the market server
+    /// detects it
     #[arg(long)]
-    pub client_ip: Ipv4Addr,
+    pub client_ip: Option<String>,
 }
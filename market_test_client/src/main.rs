@@ -19,7 +19,7 @@ fn main() -> Result<()> {
     let user = User::new(
         cli.id.unwrap_or("test_id".to_owned()),
         cli.username,
-        cli.client_ip.to_string(),
+        cli.client_ip.unwrap_or_default(),
         cli.client_port as i32,
         i64::try_from(cli.price)?,
     );
@@ -32,9 +32,9 @@ fn main() -> Result<()> {
         s.spawn(move || -> Result<()> {
             let actor = Actor::new(user, rx);
             Runtime::new()?.block_on(async {
-                if let Ok(client) = initialize_client(market_port).await {
+                if let Ok((client, ext_client)) = initialize_client(market_port).await {
                     if let Ok(()) = m_state_tx.send(ActorMarketState::Connected) {
-                        actor.run(client).await;
+                        actor.run(client, ext_client).await;
                     }
                 } else {
                     m_state_tx.send(ActorMarketState::NotConnected).unwrap();
@@ -1,5 +1,8 @@
 use anyhow::Result;
-use market_proto::market_proto_rpc::market_client::MarketClient;
+use market_proto::{
+    market_ext_proto_rpc::market_ext_client::MarketExtClient,
+    market_proto_rpc::market_client::MarketClient,
+};
 use rustyline::error::ReadlineError;
 
 use rustyline::DefaultEditor;
@@ -50,7 +53,9 @@ pub fn start_main_loop(tx: UnboundedSender<Command>) -> Result<()> {
     Ok(())
 }
 
-pub async fn initialize_client(market_port: Port) -> Result<MarketClient<Channel>> {
+pub async fn initialize_client(
+    market_port: Port,
+) -> Result<(MarketClient<Channel>, MarketExtClient<Channel>)> {
     // Market server is typically just a local server process that represents the DHT for the peer
     // node. Peer nodes then communicate through TCP sockets to the market server with the gRPC
     // method abstractions. So we can just use the loopback address; eventually I believe we
@@ -60,9 +65,15 @@ pub async fn initialize_client(market_port: Port) -> Result<MarketClient<Channel
         .authority(format!("{}:{}", LOOPBACK_ADDR, market_port).as_str())
         .path_and_query("/")
         .build()?;
-    MarketClient::connect(uri)
+    // both services are served by the same market server so they can share the channel
+    let channel = Channel::builder(uri)
+        .connect()
         .await
-        .map_err(|err| anyhow::anyhow!(err))
+        .map_err(|err| anyhow::anyhow!(err))?;
+    Ok((
+        MarketClient::new(channel.clone()),
+        MarketExtClient::new(channel),
+    ))
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]